@@ -1,6 +1,7 @@
 use cctalk::{
     device::{CoinAcceptor, CoinTable, CoreInfo},
-    protocol::{ChecksumType, Message},
+    protocol::{ChecksumType, DataStorage, Message, SerialNumber},
+    scenario::{Scenario, ScenarioRunner},
 };
 use clap::{App, Arg};
 use std::thread;
@@ -10,6 +11,24 @@ const PROGRAM: Option<&'static str> = option_env!("CARGO_PKG_NAME");
 const VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
 const DESCRIPTION: Option<&'static str> = option_env!("CARGO_PKG_DESCRIPTION");
 
+#[cfg(feature = "serde")]
+fn load_config(path: &str) -> (CoinTable, CoreInfo, DataStorage) {
+    let config = cctalk::config::DeviceConfig::load(path).expect("Failed to load device config");
+    let coin_table = config
+        .coin_table()
+        .expect("Invalid coin table in device config");
+    let data_storage = config.data_storage.unwrap_or_default();
+    (coin_table, config.core, data_storage)
+}
+
+#[cfg(not(feature = "serde"))]
+fn load_config(path: &str) -> (CoinTable, CoreInfo, DataStorage) {
+    panic!(
+        "Cannot load config from {}, serde feature is disabled",
+        path
+    );
+}
+
 fn main() {
     env_logger::init();
 
@@ -25,6 +44,28 @@ fn main() {
                 .takes_value(true)
                 .required(true),
         )
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .value_name("FILE")
+                .help("TOML or JSON device configuration (requires the serde feature)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("state")
+                .long("state")
+                .value_name("FILE")
+                .help("JSON file for persisting device state (requires the serde feature)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("scenario")
+                .long("scenario")
+                .value_name("FILE")
+                .help("Replay coin inserts, errors and resets from a scenario file")
+                .takes_value(true),
+        )
         .get_matches();
 
     let dev = matches.value_of("serial").unwrap();
@@ -36,23 +77,48 @@ fn main() {
 
     let device_address = 2;
     let serial_dev = Box::new(cctalk::client::SerialClient::new(serial, device_address).unwrap());
-    let device_info = CoreInfo {
-        manufacturer: "NONE",
-        product_code: "Test",
-        build_code: "A0",
-        software_revision: "000-000",
-        // TODO: This needs to be fixed..
-        serial_number: 123u16,
+    let (coin_table, device_info, data_storage) = match matches.value_of("config") {
+        Some(path) => load_config(path),
+        None => (
+            CoinTable::default(),
+            CoreInfo::new(
+                "NONE",
+                "Test",
+                "A0",
+                SerialNumber::new(123).unwrap(),
+                "000-000",
+            ),
+            DataStorage::default(),
+        ),
     };
 
     let mut cctalk = CoinAcceptor::init(
         serial_dev,
         ChecksumType::SimpleChecksum,
-        // TODO: Supply empty coin table for now..
-        Box::new(CoinTable::default()),
+        Box::new(coin_table),
         Box::new(device_info),
     )
     .unwrap();
+    cctalk
+        .set_data_storage(data_storage)
+        .expect("Failed to set up data storage");
+
+    if let Some(path) = matches.value_of("state") {
+        #[cfg(feature = "serde")]
+        cctalk
+            .set_store(Box::new(cctalk::storage::FileStore::new(path)))
+            .expect("Failed to load device state");
+        #[cfg(not(feature = "serde"))]
+        panic!(
+            "Cannot persist state to {}, serde feature is disabled",
+            path
+        );
+    }
+
+    let mut scenario = matches.value_of("scenario").map(|path| {
+        let scenario = Scenario::load(path).expect("Failed to load scenario");
+        ScenarioRunner::new(scenario)
+    });
 
     println!(
         "Example CoinAcceptor listening at address = {}",
@@ -60,10 +126,14 @@ fn main() {
     );
 
     loop {
+        if let Some(runner) = scenario.as_mut() {
+            runner.poll(&mut cctalk);
+        }
+
         let mut msg: Vec<Message> = cctalk.read_messages();
 
-        while msg.len() > 0 {
-            log::info!("<- {:?}", &msg);
+        while !msg.is_empty() {
+            log::info!("<- {}", &msg[0]);
             cctalk.reply_message(&msg.remove(0)).unwrap();
 
             thread::sleep(Duration::from_millis(20));
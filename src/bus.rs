@@ -0,0 +1,237 @@
+use std::thread;
+use std::time::Duration;
+
+use crate::client::*;
+use crate::peripheral::*;
+use crate::protocol::*;
+
+/// Broadcast address, peripherals act on the request but never reply
+pub const BROADCAST_ADDRESS: Address = 0;
+
+struct BusPeripheral {
+    address: Address,
+    peripheral: Box<dyn Respond>,
+}
+
+/// Several emulated peripherals sharing one transport
+///
+/// Every message read from the client is routed to the peripheral with
+/// the destination address, and the reply is sent back to the source of
+/// the request using the same checksum type. Messages for other addresses,
+/// including the echo of our own replies, are ignored.
+///
+/// The client must receive messages for all addresses, for a serial port
+/// use `SerialClient::new_promiscuous`. It is switched to
+/// `ChecksumMode::Auto` to accept requests with either checksum type.
+pub struct BusEmulator {
+    client: Box<dyn CCTalkClient + 'static>,
+    peripherals: Vec<BusPeripheral>,
+    reply_delay: Duration,
+}
+
+impl BusEmulator {
+    pub fn new(mut client: Box<dyn CCTalkClient + 'static>) -> BusEmulator {
+        client.set_checksum_mode(ChecksumMode::Auto);
+        BusEmulator {
+            client,
+            peripherals: Vec::new(),
+            reply_delay: Duration::from_millis(5),
+        }
+    }
+
+    /// Attach a peripheral, replacing the one already at `address`
+    pub fn add_peripheral(&mut self, address: Address, peripheral: Box<dyn Respond>) {
+        self.remove_peripheral(address);
+        self.peripherals.push(BusPeripheral {
+            address,
+            peripheral,
+        });
+    }
+
+    pub fn remove_peripheral(&mut self, address: Address) {
+        self.peripherals.retain(|p| p.address != address);
+    }
+
+    pub fn addresses(&self) -> Vec<Address> {
+        self.peripherals.iter().map(|p| p.address).collect()
+    }
+
+    /// Delay between receiving a request and sending the reply
+    ///
+    /// Mimics the processing time of a real peripheral, default is 5ms.
+    pub fn set_reply_delay(&mut self, reply_delay: Duration) {
+        self.reply_delay = reply_delay;
+    }
+
+    /// Answer a single request, returns whether a reply was sent
+    pub fn reply_message(&mut self, message: &Message) -> Result<bool, ClientError> {
+        if message.destination == BROADCAST_ADDRESS {
+            for p in self.peripherals.iter_mut() {
+                p.peripheral.respond(&message.payload);
+            }
+            return Ok(false);
+        }
+
+        let p = match self
+            .peripherals
+            .iter_mut()
+            .find(|p| p.address == message.destination)
+        {
+            Some(p) => p,
+            None => {
+                log::trace!(
+                    "message to another recipient {} ignored",
+                    message.destination
+                );
+                return Ok(false);
+            }
+        };
+
+        // The transport is shared, so are its receive error counters
+        let response = match message.payload.header {
+            HeaderType::RequestCommsStatusVariables => {
+                Response::Reply(self.client.comms_stats().to_bytes().to_vec())
+            }
+            HeaderType::ClearCommsStatusVariables => {
+                self.client.clear_comms_stats();
+                Response::Ack
+            }
+            _ => p.peripheral.respond(&message.payload),
+        };
+        let payload = match response.into_payload() {
+            Some(payload) => payload,
+            None => return Ok(false),
+        };
+        let reply = Message::new(
+            message.source_address(),
+            p.address,
+            payload,
+            message.checksum_type,
+        )?;
+
+        if !self.reply_delay.is_zero() {
+            thread::sleep(self.reply_delay);
+        }
+        log::trace!("Sent: {}", reply);
+        self.client.send_message(&reply)?;
+        Ok(true)
+    }
+
+    /// Read pending requests and answer them, returns the number of replies
+    pub fn process(&mut self) -> Result<usize, ClientError> {
+        let messages = self.client.read_messages()?;
+        let mut replies = 0;
+        for message in messages.iter() {
+            log::trace!("Read: {}", message);
+            if self.reply_message(message)? {
+                replies += 1;
+            }
+        }
+        Ok(replies)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::{CoinAcceptor, CoinTable};
+    use crate::peripheral::{test_core_info, CoreInfo};
+
+    use crate::peripheral::wire::wire;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn acceptor(address: Address, product_code: &str) -> CoinAcceptor {
+        CoinAcceptor::new(
+            address,
+            Box::<CoinTable>::default(),
+            Box::new(CoreInfo {
+                product_code: product_code.to_string(),
+                ..test_core_info()
+            }),
+        )
+    }
+
+    fn request(destination: Address, header: HeaderType) -> Message {
+        Message::new(
+            destination,
+            1,
+            Payload {
+                header,
+                data: vec![],
+            },
+            ChecksumType::SimpleChecksum,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_routing() {
+        let (mut host, device) = wire(0);
+        let mut bus = BusEmulator::new(Box::new(device));
+        bus.set_reply_delay(Duration::from_millis(0));
+
+        let second = Rc::new(RefCell::new(acceptor(11, "Second")));
+        bus.add_peripheral(2, Box::new(acceptor(2, "First")));
+        bus.add_peripheral(11, Box::new(second.clone()));
+        assert_eq!(bus.addresses(), vec![2, 11]);
+
+        for msg in [
+            request(11, HeaderType::RequestProductCode),
+            request(2, HeaderType::RequestProductCode),
+            // Echo of a reply to the host and a request to a missing device
+            request(1, HeaderType::Reply),
+            request(3, HeaderType::SimplePoll),
+            // Broadcast is never answered
+            request(BROADCAST_ADDRESS, HeaderType::ResetDevice),
+        ] {
+            host.send_message(&msg).unwrap();
+        }
+        assert_eq!(bus.process().unwrap(), 2);
+
+        let replies = host.read_messages().unwrap();
+        assert_eq!(
+            replies[0].encode(),
+            Message::new(
+                1,
+                11,
+                Payload {
+                    header: HeaderType::Reply,
+                    data: b"Second".to_vec(),
+                },
+                ChecksumType::SimpleChecksum,
+            )
+            .unwrap()
+            .encode()
+        );
+        assert_eq!(replies[1].source, Some(2));
+        assert_eq!(replies[1].payload.data, b"First".to_vec());
+
+        // Shared handle is still usable while the bus owns the peripheral
+        second.borrow_mut().add_credit(1);
+        host.send_message(&request(11, HeaderType::ReadBufferedCreditOrErrorCodes))
+            .unwrap();
+        bus.process().unwrap();
+        let replies = host.read_messages().unwrap();
+        assert_eq!(replies[0].payload.data[..3], [1, 1, 1]);
+    }
+
+    #[test]
+    fn test_crc_request() {
+        let (mut host, device) = wire(0);
+        host.set_checksum_mode(ChecksumMode::Expect(ChecksumType::CRCChecksum));
+        let mut bus = BusEmulator::new(Box::new(device));
+        bus.set_reply_delay(Duration::from_millis(0));
+        bus.add_peripheral(2, Box::new(acceptor(2, "First")));
+
+        let mut msg = request(2, HeaderType::RequestProductCode);
+        msg.checksum_type = ChecksumType::CRCChecksum;
+        host.send_message(&msg).unwrap();
+        assert_eq!(bus.process().unwrap(), 1);
+
+        let replies = host.read_messages().unwrap();
+        assert_eq!(replies[0].checksum_type, ChecksumType::CRCChecksum);
+        assert_eq!(replies[0].payload.data, b"First".to_vec());
+    }
+}
@@ -3,8 +3,9 @@ use std;
 use std::convert;
 use std::io::ErrorKind::TimedOut;
 use std::io::{Read, Write};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use crate::encryption::EncryptionLayer;
 use crate::protocol::*;
 
 #[derive(Debug)]
@@ -35,33 +36,107 @@ impl convert::From<std::io::Error> for ClientError {
 impl Clone for ClientError {
     fn clone(&self) -> Self {
         match self {
-            &ClientError::CCTalkError(ref e) => ClientError::CCTalkError(e.clone()),
-            &ClientError::IOError(ref e) => {
+            ClientError::CCTalkError(e) => ClientError::CCTalkError(*e),
+            ClientError::IOError(e) => {
                 ClientError::IOError(std::io::Error::new(e.kind(), e.to_string()))
             }
-            &ClientError::SerialError(ref e) => {
+            ClientError::SerialError(e) => {
                 ClientError::SerialError(serialport::Error::new(e.kind(), e.to_string()))
             }
         }
     }
 }
 
+/// Room for two of the largest frames
+const RX_BUFFER_SIZE: usize = 2 * MAX_FRAME_LENGTH;
+
+/// A frame not completed within this time is discarded
+const INTER_BYTE_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Receive error counters of a client
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CommsStats {
+    /// Incomplete frames and replies that were not received
+    pub rx_timeouts: u32,
+    /// Bytes dropped because the receive buffer was full or while
+    /// resynchronizing to the next frame
+    pub rx_bytes_ignored: u32,
+    pub rx_checksum_errors: u32,
+}
+
+impl CommsStats {
+    /// Reply data of `RequestCommsStatusVariables`, each counter saturates at 255
+    pub fn to_bytes(&self) -> [u8; 3] {
+        let saturate = |n: u32| n.min(255) as u8;
+        [
+            saturate(self.rx_timeouts),
+            saturate(self.rx_bytes_ignored),
+            saturate(self.rx_checksum_errors),
+        ]
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<CommsStats, ErrorType> {
+        match *data {
+            [rx_timeouts, rx_bytes_ignored, rx_checksum_errors] => Ok(CommsStats {
+                rx_timeouts: rx_timeouts as u32,
+                rx_bytes_ignored: rx_bytes_ignored as u32,
+                rx_checksum_errors: rx_checksum_errors as u32,
+            }),
+            _ => Err(ErrorType::ParseError),
+        }
+    }
+}
+
 pub trait CCTalkClient {
     fn send_and_check_reply(&mut self, msg: &Message) -> Result<Payload, ClientError>;
     fn get_address(&self) -> Address;
     fn set_bill_event(&mut self, bill_event: BillEvent);
     fn read_messages(&mut self) -> Result<Vec<Message>, ClientError>;
     fn send_message(&mut self, msg: &Message) -> Result<(), ClientError>;
+
+    /// Send raw bytes, used to emulate malformed frames
+    fn send_raw(&mut self, _data: &[u8]) -> Result<(), ClientError> {
+        Err(ClientError::IOError(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "raw sending is not supported",
+        )))
+    }
+
+    /// Receive error counters since the last clear
+    fn comms_stats(&self) -> CommsStats {
+        CommsStats::default()
+    }
+
+    fn clear_comms_stats(&mut self) {}
+
+    /// Checksum type of received frames, clients may accept either by default
+    fn set_checksum_mode(&mut self, _mode: ChecksumMode) {}
+
+    /// Encrypt frames from now on, `None` switches back to plain text
+    fn set_encryption(&mut self, _encryption: Option<EncryptionLayer>) -> Result<(), ClientError> {
+        Err(ClientError::IOError(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "encryption is not supported",
+        )))
+    }
 }
 
 pub struct SerialClient {
     port: Box<dyn serialport::SerialPort>,
     pub address: Address,
-    buffer: Vec<u8>,
+    framer: Framer,
+    // Receive messages regardless of their destination
+    promiscuous: bool,
+    stats: CommsStats,
+    last_received: Instant,
 }
 
 #[allow(dead_code)]
 impl SerialClient {
+    /// Client accepting simple checksum frames only
+    ///
+    /// Use `set_checksum_mode` for CRC devices, or `ChecksumMode::Auto` to
+    /// accept both on a mixed bus.
     pub fn new(
         port: Box<dyn serialport::SerialPort>,
         address: Address,
@@ -69,44 +144,61 @@ impl SerialClient {
         Ok(SerialClient {
             port,
             address,
-            buffer: Vec::<u8>::new(),
+            framer: Framer::new(
+                RX_BUFFER_SIZE,
+                ChecksumMode::Expect(ChecksumType::SimpleChecksum),
+            ),
+            promiscuous: false,
+            stats: CommsStats::default(),
+            last_received: Instant::now(),
         })
     }
 
+    /// Client receiving all messages on the bus, used by `BusEmulator`
+    pub fn new_promiscuous(
+        port: Box<dyn serialport::SerialPort>,
+    ) -> Result<SerialClient, ClientError> {
+        let mut client = SerialClient::new(port, 0)?;
+        client.promiscuous = true;
+        Ok(client)
+    }
+
     fn read_and_decode(
         &mut self,
         received: &mut Vec<u8>,
         messages: &mut Vec<Message>,
     ) -> Result<(), ClientError> {
         // log::debug!("Received: {:?}", received);
-        self.buffer.append(received);
-        // log::debug!("Buffer: {:?}", self.buffer);
-
-        // decode will leave the remaining stuff in the buffer
-        let decode_res = Message::decode(&mut self.buffer);
-        match decode_res {
-            Ok(message) => {
-                if message.destination == self.address {
-                    messages.push(message);
-                    Ok(())
-                } else {
-                    log::trace!(
-                        "message to another recipient {} ignored",
-                        message.destination
-                    );
-                    Ok(())
-                }
+        if received.is_empty() {
+            // A frame doesn't continue after a gap on the line
+            if !self.framer.is_empty() && self.last_received.elapsed() > INTER_BYTE_TIMEOUT {
+                log::debug!("Inter-byte timeout, {} bytes buffered", self.framer.len());
+                self.framer.gap();
             }
-            Err(ErrorType::PartialMessage) => {
-                // log::debug!("Partial message");
-                Ok(())
+        } else {
+            self.last_received = Instant::now();
+            let ignored = self.framer.push(received);
+            if ignored > 0 {
+                log::warn!("Receive buffer full, {} bytes ignored", ignored);
             }
-            Err(e) => Err(ClientError::CCTalkError(e)),
+            received.clear();
         }
+
+        while let Some(message) = self.framer.next_message() {
+            if self.promiscuous || message.destination == self.address {
+                messages.push(message);
+            } else {
+                log::trace!(
+                    "message to another recipient {} ignored",
+                    message.destination
+                );
+            }
+        }
+        Ok(())
     }
 
     fn read_from_serial(&mut self) -> Result<Vec<u8>, std::io::Error> {
-        let mut buf: [u8; 260] = [0; 260];
+        let mut buf = [0u8; MAX_FRAME_LENGTH];
         let mut rec: Vec<u8> = Vec::<u8>::new();
 
         let read_res = self.port.read(&mut buf);
@@ -124,11 +216,18 @@ impl SerialClient {
         }
     }
 
-    fn send(&mut self, msg: &Message) -> Result<(), std::io::Error> {
-        let buf: Vec<u8> = msg.encode();
+    fn send(&mut self, msg: &Message) -> Result<(), ClientError> {
         // log::debug!("Sending CCTalk message: {:?}", msg);
-        log::trace!("Sending CCTalk message encoded: {:?}", buf);
-        self.port.write_all(&buf[..])
+        if let Some(layer) = self.framer.encryption() {
+            let buf = layer.encode(msg)?;
+            log::trace!("Sending CCTalk message encrypted: {:?}", buf);
+            return Ok(self.port.write_all(&buf)?);
+        }
+
+        let mut buf = [0u8; MAX_FRAME_LENGTH];
+        let length = msg.encode_into(&mut buf)?;
+        log::trace!("Sending CCTalk message encoded: {:?}", &buf[..length]);
+        Ok(self.port.write_all(&buf[..length])?)
     }
 
     fn read(&mut self) -> Result<Vec<Message>, ClientError> {
@@ -136,7 +235,7 @@ impl SerialClient {
 
         let mut counter = 0;
 
-        while (messages.len() < 1) && (counter < 80) {
+        while messages.is_empty() && (counter < 80) {
             let mut received = self.read_from_serial()?;
             // log::debug!("Received on serial: {:?} Counter: {}", received, counter);
             self.read_and_decode(&mut received, &mut messages)?;
@@ -159,8 +258,9 @@ impl SerialClient {
 
         while !timeout {
             let mut received = self.read_from_serial()?;
+            let idle = received.is_empty();
             self.read_and_decode(&mut received, &mut messages)?;
-            if (received.len() == 0) && (self.buffer.len() == 0) {
+            if idle && self.framer.is_empty() {
                 timeout = true;
             }
         }
@@ -177,20 +277,23 @@ impl CCTalkClient for SerialClient {
 
         // log::debug!("Waiting for Reply");
         let received = self.read()?;
-        if received.len() > 0 {
-            let ref reply = received[0];
+        if !received.is_empty() {
+            let reply = &received[0];
             match reply.payload.header {
                 HeaderType::Reply => Ok(reply.payload.clone()),
+                HeaderType::Nak => Err(ClientError::CCTalkError(ErrorType::Nak)),
+                HeaderType::Busy => Err(ClientError::CCTalkError(ErrorType::Busy)),
                 _ => Err(ClientError::CCTalkError(ErrorType::NotAReply)),
             }
         } else {
-            if self.buffer.len() != 0 {
+            if !self.framer.is_empty() {
                 log::debug!(
-                    "Message not received in time, clearing partial message from buffer: {:?}",
-                    self.buffer
+                    "Message not received in time, clearing {} bytes from buffer",
+                    self.framer.len()
                 );
-                self.buffer.clear();
+                self.framer.clear();
             }
+            self.stats.rx_timeouts += 1;
             Err(ClientError::CCTalkError(ErrorType::NoResponse))
         }
     }
@@ -206,13 +309,43 @@ impl CCTalkClient for SerialClient {
     }
 
     fn send_message(&mut self, msg: &Message) -> Result<(), ClientError> {
-        let send_result = self.send(&msg);
-        self.buffer.clear();
+        let send_result = self.send(msg);
+        self.framer.clear();
+        send_result
+    }
+
+    fn send_raw(&mut self, data: &[u8]) -> Result<(), ClientError> {
+        log::trace!("Sending raw bytes: {:?}", data);
+        let send_result = self.port.write_all(data);
+        self.framer.clear();
         match send_result {
             Ok(r) => Ok(r),
             Err(e) => Err(ClientError::IOError(e)),
         }
     }
+
+    fn comms_stats(&self) -> CommsStats {
+        let framer = self.framer.stats();
+        CommsStats {
+            rx_timeouts: self.stats.rx_timeouts + framer.incomplete_frames,
+            rx_bytes_ignored: framer.overflowed_bytes + framer.dropped_bytes,
+            rx_checksum_errors: framer.checksum_errors,
+        }
+    }
+
+    fn clear_comms_stats(&mut self) {
+        self.stats = CommsStats::default();
+        self.framer.clear_stats();
+    }
+
+    fn set_checksum_mode(&mut self, mode: ChecksumMode) {
+        self.framer.set_checksum_mode(mode);
+    }
+
+    fn set_encryption(&mut self, encryption: Option<EncryptionLayer>) -> Result<(), ClientError> {
+        self.framer.set_encryption(encryption);
+        Ok(())
+    }
 }
 
 pub struct DummyClient {
@@ -221,6 +354,12 @@ pub struct DummyClient {
     changed: bool,
 }
 
+impl Default for DummyClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DummyClient {
     pub fn new() -> DummyClient {
         log::warn!("Creating MOCK CCTalk Client");
@@ -238,7 +377,7 @@ impl CCTalkClient for DummyClient {
             HeaderType::ReadBufferedBillEvents => {
                 let (byte1, byte2) = self.bill_event.to_u8();
 
-                if self.changed == true {
+                if self.changed {
                     self.counter += 1;
                     self.changed = false;
                 }
@@ -272,3 +411,243 @@ impl CCTalkClient for DummyClient {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// Port replaying received chunks, an empty chunk is an idle line
+    struct TestPort {
+        rx: VecDeque<Vec<u8>>,
+    }
+
+    impl Read for TestPort {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.rx.pop_front() {
+                Some(chunk) if !chunk.is_empty() => {
+                    buf[..chunk.len()].copy_from_slice(&chunk);
+                    Ok(chunk.len())
+                }
+                Some(_) => {
+                    std::thread::sleep(2 * INTER_BYTE_TIMEOUT);
+                    Err(TimedOut.into())
+                }
+                None => Err(TimedOut.into()),
+            }
+        }
+    }
+
+    impl Write for TestPort {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl serialport::SerialPort for TestPort {
+        fn name(&self) -> Option<String> {
+            None
+        }
+        fn baud_rate(&self) -> serialport::Result<u32> {
+            Ok(9600)
+        }
+        fn data_bits(&self) -> serialport::Result<serialport::DataBits> {
+            Ok(serialport::DataBits::Eight)
+        }
+        fn flow_control(&self) -> serialport::Result<serialport::FlowControl> {
+            Ok(serialport::FlowControl::None)
+        }
+        fn parity(&self) -> serialport::Result<serialport::Parity> {
+            Ok(serialport::Parity::None)
+        }
+        fn stop_bits(&self) -> serialport::Result<serialport::StopBits> {
+            Ok(serialport::StopBits::One)
+        }
+        fn timeout(&self) -> Duration {
+            Duration::from_millis(0)
+        }
+        fn set_baud_rate(&mut self, _: u32) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn set_data_bits(&mut self, _: serialport::DataBits) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn set_flow_control(&mut self, _: serialport::FlowControl) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn set_parity(&mut self, _: serialport::Parity) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn set_stop_bits(&mut self, _: serialport::StopBits) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn set_timeout(&mut self, _: Duration) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn write_request_to_send(&mut self, _: bool) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn write_data_terminal_ready(&mut self, _: bool) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+            Ok(true)
+        }
+        fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+            Ok(true)
+        }
+        fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+            Ok(false)
+        }
+        fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+            Ok(true)
+        }
+        fn bytes_to_read(&self) -> serialport::Result<u32> {
+            Ok(0)
+        }
+        fn bytes_to_write(&self) -> serialport::Result<u32> {
+            Ok(0)
+        }
+        fn clear(&self, _: serialport::ClearBuffer) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn try_clone(&self) -> serialport::Result<Box<dyn serialport::SerialPort>> {
+            Err(serialport::Error::new(
+                serialport::ErrorKind::Unknown,
+                "not supported",
+            ))
+        }
+        fn set_break(&self) -> serialport::Result<()> {
+            Ok(())
+        }
+        fn clear_break(&self) -> serialport::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_comms_stats() {
+        let rx = vec![
+            // Garbage cut short by a gap
+            vec![0x55, 0x01],
+            vec![],
+            // Reply with a bad checksum, then a gap
+            vec![1, 0, 2, 0, 0],
+            vec![],
+            vec![1, 0, 2, 0, 253],
+        ];
+        let port = TestPort { rx: rx.into() };
+        let mut client = SerialClient::new(Box::new(port), 1).unwrap();
+        let poll = Message::new(
+            2,
+            1,
+            Payload {
+                header: HeaderType::SimplePoll,
+                data: vec![],
+            },
+            ChecksumType::SimpleChecksum,
+        )
+        .unwrap();
+
+        assert!(client.send_and_check_reply(&poll).is_ok());
+        assert_eq!(
+            client.comms_stats(),
+            CommsStats {
+                rx_timeouts: 2,
+                rx_bytes_ignored: 7,
+                rx_checksum_errors: 1,
+            }
+        );
+
+        // No reply at all
+        assert!(matches!(
+            client.send_and_check_reply(&poll),
+            Err(ClientError::CCTalkError(ErrorType::NoResponse))
+        ));
+        assert_eq!(client.comms_stats().rx_timeouts, 3);
+        assert_eq!(client.comms_stats().to_bytes(), [3, 7, 1]);
+
+        client.clear_comms_stats();
+        assert_eq!(client.comms_stats(), CommsStats::default());
+
+        // Counters saturate on the wire
+        let stats = CommsStats {
+            rx_timeouts: 300,
+            rx_bytes_ignored: 255,
+            rx_checksum_errors: 7,
+        };
+        assert_eq!(stats.to_bytes(), [255, 255, 7]);
+    }
+
+    #[test]
+    fn test_default_checksum_mode() {
+        let reply = Message::new(
+            1,
+            2,
+            Payload {
+                header: HeaderType::Reply,
+                data: vec![],
+            },
+            ChecksumType::CRCChecksum,
+        )
+        .unwrap()
+        .encode();
+        let poll = Message::new(
+            2,
+            1,
+            Payload {
+                header: HeaderType::SimplePoll,
+                data: vec![],
+            },
+            ChecksumType::CRCChecksum,
+        )
+        .unwrap();
+
+        // CRC replies are only accepted after opting in
+        let port = TestPort {
+            rx: vec![reply.clone()].into(),
+        };
+        let mut client = SerialClient::new(Box::new(port), 1).unwrap();
+        assert!(matches!(
+            client.send_and_check_reply(&poll),
+            Err(ClientError::CCTalkError(ErrorType::NoResponse))
+        ));
+
+        let port = TestPort {
+            rx: vec![reply].into(),
+        };
+        let mut client = SerialClient::new(Box::new(port), 1).unwrap();
+        client.set_checksum_mode(ChecksumMode::Auto);
+        assert!(client.send_and_check_reply(&poll).is_ok());
+    }
+
+    #[test]
+    fn test_send_rejects_long_payload() {
+        let port = TestPort { rx: vec![].into() };
+        let mut client = SerialClient::new(Box::new(port), 1).unwrap();
+        let msg = Message {
+            destination: 2,
+            length: 0,
+            source: Some(1),
+            payload: Payload {
+                header: HeaderType::WriteDataBlock,
+                data: vec![0; 300],
+            },
+            checksum_type: ChecksumType::SimpleChecksum,
+        };
+        assert!(matches!(
+            client.send_message(&msg),
+            Err(ClientError::CCTalkError(ErrorType::PayloadTooLong))
+        ));
+    }
+
+    #[test]
+    fn test_client_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<SerialClient>();
+        assert_send::<EncryptionLayer>();
+    }
+}
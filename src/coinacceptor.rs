@@ -1,33 +1,55 @@
+use std::convert::TryInto;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
 use crate::client::*;
+use crate::encryption::*;
+use crate::firmware::{FirmwareMemory, UpgradeTarget};
+use crate::peripheral::*;
 use crate::protocol::*;
+use crate::storage::*;
+
+/// Number of sorter paths stored per coin channel
+pub const SORTER_PATHS: usize = 4;
+
+/// Number of coins to insert until teach mode completes
+pub const TEACH_MODE_COINS: u8 = 10;
+
+/// Number of events in the credit buffer
+pub const CREDIT_BUFFER_EVENTS: u8 = 5;
 
 /// Coin Configuration Information
 #[derive(Clone, Copy, Debug)]
 pub struct CoinInfo {
     inhibit: bool,
-    coin_value: &'static str,
-    sort_path: u8,
+    coin_value: CoinId,
+    sort_paths: [u8; SORTER_PATHS],
 }
 
 impl Default for CoinInfo {
     fn default() -> Self {
         CoinInfo {
             inhibit: true,
-            coin_value: "......",
-            sort_path: 0,
+            coin_value: CoinId::default(),
+            sort_paths: [0; SORTER_PATHS],
         }
     }
 }
 
 impl CoinInfo {
     /// Create active coin value
+    pub fn new(coin_value: CoinId, sort_path: u8) -> Self {
+        CoinInfo::with_sort_paths(coin_value, [sort_path, 0, 0, 0])
+    }
+
+    /// Create active coin value with all sorter paths
     ///
-    /// NB! `coin_value` field is not validated.
-    pub fn new(coin_value: &'static str, sort_path: u8) -> Self {
+    /// Path 1 is the primary path, paths 2..4 are used in order
+    /// when the previous path is overridden. Unused paths are 0.
+    pub fn with_sort_paths(coin_value: CoinId, sort_paths: [u8; SORTER_PATHS]) -> Self {
         CoinInfo {
             inhibit: false,
             coin_value,
-            sort_path,
+            sort_paths,
         }
     }
 }
@@ -47,35 +69,70 @@ impl Default for CoinTable {
 
 impl CoinTable {
     pub fn get_sort_path(&self, channel: u8) -> u8 {
-        self.slots[channel as usize].sort_path
+        self.slots[channel as usize].sort_paths[0]
+    }
+
+    pub fn get_sort_paths(&self, channel: u8) -> [u8; SORTER_PATHS] {
+        self.slots[channel as usize].sort_paths
+    }
+
+    pub fn set_sort_paths(&mut self, channel: u8, sort_paths: [u8; SORTER_PATHS]) {
+        self.slots[channel as usize].sort_paths = sort_paths;
     }
 
     pub fn get_coin_value(&self, channel: u8) -> &str {
+        self.slots[channel as usize].coin_value.as_str()
+    }
+
+    pub fn get_coin_id(&self, channel: u8) -> CoinId {
         self.slots[channel as usize].coin_value
     }
 
+    pub fn set_coin_id(&mut self, channel: u8, coin_id: CoinId) {
+        self.slots[channel as usize].coin_value = coin_id;
+    }
+
     pub fn get_inhibit(&self, channel: u8) -> bool {
         self.slots[channel as usize].inhibit
     }
 
+    pub fn set_inhibit(&mut self, channel: u8, inhibit: bool) {
+        self.slots[channel as usize].inhibit = inhibit;
+    }
+
     pub fn set_coininfo(&mut self, channel: u8, coin: CoinInfo) {
         self.slots[channel as usize] = coin;
     }
+
+    /// First channel programmed with `coin_id`
+    pub fn find_coin(&self, coin_id: &CoinId) -> Option<u8> {
+        self.slots
+            .iter()
+            .position(|coin| coin.coin_value == *coin_id)
+            .map(|channel| channel as u8)
+    }
+}
+
+/// Behaviour of the emulator on `ResetDevice`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResetModel {
+    /// Restore inhibits and sorter settings to their power-on values,
+    /// otherwise only the event buffer and master inhibit are reset.
+    /// Coin ids are kept, they are part of the coin data rather than
+    /// the configuration.
+    pub restore_configuration: bool,
+    /// Time after the ACK during which requests are not answered,
+    /// none by default
+    pub settle_time: Duration,
 }
 
-/// CCTalk Core and Core Plus Information fields
-pub struct CoreInfo {
-    // Core fields
-    pub manufacturer: &'static str,
-    // equipment_category_id is supplied with main device information
-    pub product_code: &'static str,
-    pub build_code: &'static str,
-    // Core Plus fields
-    // TODO: serial number handling is currently not really working properly
-    // There are devices that return 3 bytes as serial.
-    pub serial_number: u16,
-    pub software_revision: &'static str,
-    // TODO: comms_revision - hardcoded for now... ?
+impl Default for ResetModel {
+    fn default() -> Self {
+        ResetModel {
+            restore_configuration: true,
+            settle_time: Duration::from_millis(0),
+        }
+    }
 }
 
 /// Basic Coin Accepter implementation
@@ -83,7 +140,8 @@ pub struct CoreInfo {
 /// By default, "Coin Acceptor" devices use address=2,
 /// extra addresses include 11-17.
 pub struct CoinAcceptor {
-    client: Box<dyn CCTalkClient + 'static>,
+    // None when attached to a `BusEmulator`
+    client: Option<Box<dyn CCTalkClient + 'static>>,
     address: Address,
     checksum_type: ChecksumType,
     counter: u8,
@@ -92,40 +150,364 @@ pub struct CoinAcceptor {
     credit_buffer: Vec<u8>,
     coin_table: Box<CoinTable>,
     cctalk_info: Box<CoreInfo>,
+    // Bit N cleared means sorter path N+1 is overridden
+    cc_sorter_override: u8,
+    cc_default_sort_path: u8,
+    // Channel being taught, if teach mode was started
+    teach_channel: Option<u8>,
+    teach_coins: u8,
+    teach_status: TeachStatus,
+    insertion_counter: u32,
+    accept_counter: u32,
+    store: Box<dyn StateStore>,
+    reset_model: ResetModel,
+    // Configuration restored on reset when nothing is stored
+    power_on: StoredConfiguration,
+    settle_until: Option<Instant>,
+    data_storage: DataStorage,
+    data_blocks: Vec<u8>,
+    variables: CoinAcceptorVariables,
+    // Events the host has not read yet
+    unread_events: u8,
+    // Real time clock minus the system time, in seconds
+    rtc_offset: i64,
+    firmware: FirmwareMemory,
+    // None when encryption is not supported
+    encryption_support: Option<EncryptionSupport>,
+    bnv_algorithm: Option<BnvAlgorithm>,
+    encryption_key: Option<EncryptionKey>,
+    // Key used again after a reset
+    stored_encryption_key: Option<EncryptionKey>,
+    // The client switches to the new key after the reply
+    encryption_changed: bool,
+    handlers: Handlers<CoinAcceptor>,
 }
 
 impl CoinAcceptor {
     pub fn init(
-        client: Box<dyn CCTalkClient + 'static>,
+        mut client: Box<dyn CCTalkClient + 'static>,
         checksum_type: ChecksumType,
         coin_table: Box<CoinTable>,
         cctalk_info: Box<CoreInfo>,
     ) -> Result<CoinAcceptor, ClientError> {
-        let addr = &client.get_address();
-        Ok(CoinAcceptor {
-            client,
-            address: *addr,
-            checksum_type,
+        client.set_checksum_mode(checksum_type.into());
+        let mut acceptor = CoinAcceptor::new(client.get_address(), coin_table, cctalk_info);
+        acceptor.client = Some(client);
+        acceptor.checksum_type = checksum_type;
+        Ok(acceptor)
+    }
+
+    /// Coin acceptor without its own transport
+    ///
+    /// Used as a peripheral of a `BusEmulator`, which does the messaging.
+    pub fn new(address: Address, coin_table: Box<CoinTable>, cctalk_info: Box<CoreInfo>) -> Self {
+        let mut acceptor = CoinAcceptor {
+            client: None,
+            address,
+            checksum_type: ChecksumType::SimpleChecksum,
             counter: 0,
             cc_master_inhibit: true,
             cc_equipment_cat_id: "Coin Acceptor".to_string(),
-            credit_buffer: vec![0u8; 10],
+            credit_buffer: vec![0u8; 2 * CREDIT_BUFFER_EVENTS as usize],
             coin_table,
             cctalk_info,
-        })
+            cc_sorter_override: 0xFF,
+            cc_default_sort_path: 1,
+            teach_channel: None,
+            teach_coins: 0,
+            teach_status: TeachStatus::Completed,
+            insertion_counter: 0,
+            accept_counter: 0,
+            store: Box::new(MemoryStore::new()),
+            reset_model: ResetModel::default(),
+            power_on: StoredConfiguration {
+                coins: Vec::new(),
+                master_inhibit: true,
+                sorter_override: 0xFF,
+                default_sort_path: 1,
+                inhibit_on_full_buffer: false,
+            },
+            settle_until: None,
+            data_storage: DataStorage::default(),
+            data_blocks: Vec::new(),
+            variables: CoinAcceptorVariables {
+                inhibit_on_full_buffer: false,
+                buffer_size: CREDIT_BUFFER_EVENTS,
+            },
+            unread_events: 0,
+            rtc_offset: 0,
+            firmware: FirmwareMemory::default(),
+            encryption_support: None,
+            bnv_algorithm: None,
+            encryption_key: None,
+            stored_encryption_key: None,
+            encryption_changed: false,
+            handlers: CoinAcceptor::register_handlers(),
+        };
+        acceptor.power_on = acceptor.configuration();
+        acceptor
     }
-    fn ack(&mut self) -> Result<(), ClientError> {
-        let msg = self.create_message(Payload {
-            header: (HeaderType::Reply),
-            data: (vec![]),
+
+    pub fn set_reset_model(&mut self, reset_model: ResetModel) {
+        self.reset_model = reset_model;
+    }
+
+    /// Whether the device is still restarting after a reset
+    pub fn is_settling(&self) -> bool {
+        match self.settle_until {
+            Some(until) => Instant::now() < until,
+            None => false,
+        }
+    }
+
+    fn register_handlers() -> Handlers<CoinAcceptor> {
+        let mut handlers = Handlers::new();
+        handlers.register(HeaderType::PerformSelfcheck, |_, _| Response::Ack);
+        handlers.register(HeaderType::ResetDevice, CoinAcceptor::reset_device);
+        handlers.register(
+            HeaderType::ModifyInhibitStatus,
+            CoinAcceptor::modify_inhibit_status,
+        );
+        handlers.register(
+            HeaderType::RequestInhibitStatus,
+            CoinAcceptor::request_inhibit_status,
+        );
+        handlers.register(
+            HeaderType::ModifyMasterInhibitStatus,
+            CoinAcceptor::modify_master_inhibit_status,
+        );
+        handlers.register(
+            HeaderType::RequestMasterInhibitStatus,
+            CoinAcceptor::request_master_inhibit_status,
+        );
+        handlers.register(HeaderType::RequestCoinId, CoinAcceptor::request_coin_id);
+        handlers.register(HeaderType::ModifyCoinId, CoinAcceptor::modify_coin_id);
+        handlers.register(
+            HeaderType::ReadBufferedCreditOrErrorCodes,
+            CoinAcceptor::read_buffered_credit,
+        );
+        handlers.register(HeaderType::RequestDataStorageAvailability, |acceptor, _| {
+            Response::Reply(acceptor.data_storage.to_bytes().to_vec())
+        });
+        handlers.register(HeaderType::RequestVariableSet, |acceptor, _| {
+            Response::Reply(acceptor.variables.to_bytes())
+        });
+        handlers.register(HeaderType::ModifyVariableSet, |acceptor, request| {
+            match CoinAcceptorVariables::from_bytes(&request.data) {
+                Ok(variables) => {
+                    acceptor.variables.inhibit_on_full_buffer = variables.inhibit_on_full_buffer;
+                    Response::Ack
+                }
+                Err(_) => Response::Nak,
+            }
+        });
+        // Credits are reported as coin positions
+        handlers.register(HeaderType::RequestOptionFlags, |_, _| {
+            Response::Reply(vec![CoinAcceptorOptions::default().to_u8()])
+        });
+        handlers.register(HeaderType::RequestFirmwareUpgradeCapability, |_, _| {
+            Response::Reply(vec![1])
+        });
+        handlers.register(HeaderType::BeginFirmwareUpgrade, |acceptor, _| {
+            acceptor.firmware.begin(UpgradeTarget::Firmware);
+            Response::Ack
+        });
+        handlers.register(
+            HeaderType::UploadFirmware,
+            |acceptor, request| match acceptor
+                .firmware
+                .upload(UpgradeTarget::Firmware, &request.data)
+            {
+                true => Response::Ack,
+                false => Response::Nak,
+            },
+        );
+        // The new firmware is started like after a reset
+        handlers.register(
+            HeaderType::FinishFirmwareUpgrade,
+            |acceptor, request| match acceptor.firmware.finish(UpgradeTarget::Firmware) {
+                true => acceptor.reset_device(request),
+                false => Response::Nak,
+            },
+        );
+        handlers.register(HeaderType::CalculateROMChecksum, |acceptor, _| {
+            Response::Reply(acceptor.firmware.rom_checksum().to_le_bytes().to_vec())
+        });
+        handlers.register(
+            HeaderType::RequestEncryptionSupport,
+            |acceptor, _| match acceptor.encryption_support {
+                Some(support) => Response::Reply(support.to_bytes().to_vec()),
+                None => Response::Nak,
+            },
+        );
+        handlers.register(
+            HeaderType::SwitchEncryptionKey,
+            CoinAcceptor::switch_encryption_key,
+        );
+        handlers.register(
+            HeaderType::SwitchEncryptionCode,
+            CoinAcceptor::switch_encryption_code,
+        );
+        handlers.register(HeaderType::StoreEncryptionCode, |acceptor, _| {
+            if acceptor.encryption_support.is_none() {
+                return Response::Nak;
+            }
+            acceptor.stored_encryption_key = acceptor.encryption_key;
+            Response::Ack
+        });
+        handlers.register(HeaderType::ReadDataBlock, CoinAcceptor::read_data_block);
+        handlers.register(HeaderType::WriteDataBlock, CoinAcceptor::write_data_block);
+        handlers.register(HeaderType::ConfigurationToEEPROM, |acceptor, _| {
+            acceptor.store_configuration()
+        });
+        handlers.register(HeaderType::CountersToEEPROM, |acceptor, _| {
+            acceptor.store_counters()
+        });
+        handlers.register(HeaderType::RequestInsertionCounter, |acceptor, _| {
+            Response::Reply(acceptor.insertion_counter.to_le_bytes()[..3].to_vec())
+        });
+        handlers.register(HeaderType::RequestAcceptCounter, |acceptor, _| {
+            Response::Reply(acceptor.accept_counter.to_le_bytes()[..3].to_vec())
+        });
+        // Remote programming not supported
+        handlers.register(HeaderType::RequestDatabaseVersion, |_, _| {
+            Response::Reply(vec![0])
+        });
+        handlers.register(
+            HeaderType::RequestSorterPaths,
+            CoinAcceptor::request_sorter_paths,
+        );
+        handlers.register(
+            HeaderType::ModifySorterPaths,
+            CoinAcceptor::modify_sorter_paths,
+        );
+        handlers.register(HeaderType::RequestSorterOverrideStatus, |acceptor, _| {
+            Response::Reply(vec![acceptor.cc_sorter_override])
+        });
+        handlers.register(
+            HeaderType::ModifySorterOverrideStatus,
+            |acceptor, request| match request.data.first() {
+                Some(&mask) => {
+                    acceptor.cc_sorter_override = mask;
+                    Response::Ack
+                }
+                None => Response::Nak,
+            },
+        );
+        handlers.register(HeaderType::RequestDefaultSorterPath, |acceptor, _| {
+            Response::Reply(vec![acceptor.cc_default_sort_path])
         });
-        self.client.send_message(&msg)
+        handlers.register(
+            HeaderType::ModifyDefaultSorterPath,
+            |acceptor, request| match request.data.first() {
+                Some(&path) => {
+                    acceptor.cc_default_sort_path = path;
+                    Response::Ack
+                }
+                None => Response::Nak,
+            },
+        );
+        handlers.register(
+            HeaderType::TeachModeControl,
+            CoinAcceptor::teach_mode_control,
+        );
+        handlers.register(
+            HeaderType::RequestTeachStatus,
+            CoinAcceptor::request_teach_status,
+        );
+        handlers
     }
-    fn create_message(&mut self, payload: Payload) -> Message {
+
+    /// Attach non-volatile storage and restore the state saved in it
+    ///
+    /// Until then a `MemoryStore` keeps the state across device resets.
+    pub fn set_store(&mut self, mut store: Box<dyn StateStore>) -> Result<(), StorageError> {
+        let state = store.load()?;
+        if let Some(configuration) = &state.configuration {
+            self.apply_configuration(configuration);
+        }
+        if let Some(counters) = state.counters {
+            self.insertion_counter = counters.insertion;
+            self.accept_counter = counters.accept;
+        }
+        self.store = store;
+        if let Some(data_blocks) = state.data_blocks {
+            self.restore_data_blocks(data_blocks);
+        }
+        Ok(())
+    }
+
+    /// Set the data storage layout, all blocks are initially zero
+    ///
+    /// Permanent memory is restored from the attached store.
+    pub fn set_data_storage(&mut self, data_storage: DataStorage) -> Result<(), StorageError> {
+        self.data_storage = data_storage;
+        self.data_blocks = vec![0u8; data_storage.size()];
+        if let Some(data_blocks) = self.store.load()?.data_blocks {
+            self.restore_data_blocks(data_blocks);
+        }
+        Ok(())
+    }
+
+    fn restore_data_blocks(&mut self, data_blocks: Vec<u8>) {
+        if !self.data_storage.memory_type.is_permanent() {
+            return;
+        }
+        let len = data_blocks.len().min(self.data_blocks.len());
+        self.data_blocks[..len].copy_from_slice(&data_blocks[..len]);
+    }
+
+    fn configuration(&self) -> StoredConfiguration {
+        StoredConfiguration {
+            coins: self
+                .coin_table
+                .slots
+                .iter()
+                .map(|coin| StoredCoin {
+                    coin_id: coin.coin_value.as_str().to_string(),
+                    sort_paths: coin.sort_paths,
+                    inhibit: coin.inhibit,
+                })
+                .collect(),
+            master_inhibit: self.cc_master_inhibit,
+            sorter_override: self.cc_sorter_override,
+            default_sort_path: self.cc_default_sort_path,
+            inhibit_on_full_buffer: self.variables.inhibit_on_full_buffer,
+        }
+    }
+
+    fn apply_configuration(&mut self, configuration: &StoredConfiguration) {
+        for (slot, coin) in self.coin_table.slots.iter_mut().zip(&configuration.coins) {
+            match CoinId::new(&coin.coin_id) {
+                Ok(coin_id) => slot.coin_value = coin_id,
+                Err(_) => log::warn!("Invalid stored coin id: {:?}", coin.coin_id),
+            }
+            slot.sort_paths = coin.sort_paths;
+            slot.inhibit = coin.inhibit;
+        }
+        self.cc_master_inhibit = configuration.master_inhibit;
+        self.cc_sorter_override = configuration.sorter_override;
+        self.cc_default_sort_path = configuration.default_sort_path;
+        self.variables.inhibit_on_full_buffer = configuration.inhibit_on_full_buffer;
+    }
+
+    /// Update one section of the stored state, keeping the other
+    fn write_store<F>(&mut self, update: F) -> Result<(), StorageError>
+    where
+        F: FnOnce(&Self, &mut StoredState),
+    {
+        let mut state = self.store.load()?;
+        update(self, &mut state);
+        self.store.save(&state)
+    }
+    fn create_message(&mut self, payload: Payload) -> Result<Message, ErrorType> {
         Message::new(1u8, self.address, payload, self.checksum_type)
     }
     pub fn read_messages(&mut self) -> Vec<Message> {
-        let _received = self.client.read_messages();
+        let _received = match self.client.as_mut() {
+            Some(client) => client.read_messages(),
+            None => return vec![],
+        };
         let received = match _received {
             Ok(data) => {
                 log::trace!("Read: {:?}", data);
@@ -144,213 +526,502 @@ impl CoinAcceptor {
         received
     }
     pub fn reply_message(&mut self, message: &Message) -> Result<(), ClientError> {
-        let header = message.payload.header;
-        match header {
-            HeaderType::RequestEquipmentCategoryId => {
-                let msg: Message = self.create_message(Payload {
-                    header: (HeaderType::Reply),
-                    data: (self.cc_equipment_cat_id.as_bytes().to_vec()),
-                });
-                log::trace!("Sent: {:?}", msg);
-                self.client.send_message(&msg)
-            }
-            HeaderType::SimplePoll | HeaderType::PerformSelfcheck => self.ack(),
-            HeaderType::RequestProductCode => {
-                let msg: Message = self.create_message(Payload {
-                    header: (HeaderType::Reply),
-                    data: (self.cctalk_info.product_code.as_bytes().to_vec()),
-                });
-                log::trace!("Sent: {:?}", msg);
-                self.client.send_message(&msg)
-            }
-            HeaderType::RequestBuildCode => {
-                let msg: Message = self.create_message(Payload {
-                    header: (HeaderType::Reply),
-                    data: (self.cctalk_info.build_code.as_bytes().to_vec()),
-                });
-                log::trace!("Sent: {:?}", msg);
-                self.client.send_message(&msg)
-            }
-            HeaderType::RequestManufacturerId => {
-                let msg: Message = self.create_message(Payload {
-                    header: (HeaderType::Reply),
-                    data: (self.cctalk_info.manufacturer.as_bytes().to_vec()),
-                });
-                log::trace!("Sent: {:?}", msg);
-                self.client.send_message(&msg)
-            }
-            HeaderType::RequestSerialNumber => {
-                // TODO: Fix this to take arbitrary bytes
-                let mut serial: Vec<u8> = Vec::new();
-                let cc_serial = self.cctalk_info.serial_number;
-                serial.push(cc_serial.checked_rem_euclid(256).expect("SN error") as u8);
-                serial.push(cc_serial.checked_div_euclid(256).expect("SN error") as u8);
-                serial.push(0u8);
-
-                let msg: Message = self.create_message(Payload {
-                    header: (HeaderType::Reply),
-                    data: (serial),
-                });
-                log::trace!("Sent: {:?}", msg);
-                self.client.send_message(&msg)
+        match dispatch(self, &message.payload).into_payload() {
+            Some(payload) => {
+                let msg = self.create_message(payload)?;
+                log::trace!("Sent: {}", msg);
+                match self.client.as_mut() {
+                    Some(client) => client.send_message(&msg)?,
+                    None => {
+                        return Err(ClientError::IOError(std::io::Error::new(
+                            std::io::ErrorKind::NotConnected,
+                            "coin acceptor has no client",
+                        )))
+                    }
+                }
+                if self.encryption_changed {
+                    self.apply_encryption()?;
+                }
+                Ok(())
             }
-            HeaderType::RequestSoftwareRevision => {
-                let msg: Message = self.create_message(Payload {
-                    header: (HeaderType::Reply),
-                    data: (self.cctalk_info.software_revision.as_bytes().to_vec()),
-                });
-                log::trace!("Sent: {:?}", msg);
-                self.client.send_message(&msg)
+            None => Ok(()),
+        }
+    }
+
+    /// Accept the encryption commands, BNV codes only with `bnv_algorithm`
+    pub fn set_encryption_support(
+        &mut self,
+        support: EncryptionSupport,
+        bnv_algorithm: Option<BnvAlgorithm>,
+    ) {
+        self.encryption_support = Some(support);
+        self.bnv_algorithm = bnv_algorithm;
+    }
+
+    /// Key stored in the device, used from now on and after resets
+    ///
+    /// BNV codes fail with `NoBnvAlgorithm` unless `set_encryption_support`
+    /// was given the BNV algorithm.
+    pub fn set_encryption_key(&mut self, key: Option<EncryptionKey>) -> Result<(), ClientError> {
+        if let (Some(EncryptionKey::Bnv(_)), None) = (key, self.bnv_algorithm) {
+            return Err(ClientError::CCTalkError(ErrorType::NoBnvAlgorithm));
+        }
+        self.encryption_key = key;
+        self.stored_encryption_key = key;
+        self.apply_encryption()
+    }
+
+    fn apply_encryption(&mut self) -> Result<(), ClientError> {
+        self.encryption_changed = false;
+        let layer = match self.encryption_key {
+            Some(EncryptionKey::Des(key)) => Some(EncryptionLayer::des_test(key)),
+            Some(EncryptionKey::Bnv(code)) => match self.bnv_algorithm {
+                Some(algorithm) => Some(EncryptionLayer::bnv(&code, algorithm)),
+                None => return Err(ClientError::CCTalkError(ErrorType::NoBnvAlgorithm)),
+            },
+            None => None,
+        };
+        match self.client.as_mut() {
+            Some(client) => client.set_encryption(layer),
+            None => Ok(()),
+        }
+    }
+
+    fn switch_encryption_key(&mut self, request: &Payload) -> Response {
+        match (self.encryption_support, request.data[..].try_into()) {
+            (Some(_), Ok(key)) => {
+                self.encryption_key = Some(EncryptionKey::Des(key));
+                self.encryption_changed = true;
+                Response::Ack
             }
-            HeaderType::RequestCommsRevision => {
-                let msg: Message = self.create_message(Payload {
-                    header: (HeaderType::Reply),
-                    data: (vec![1u8, 4u8, 4u8]),
-                });
-                log::trace!("Sent: {:?}", msg);
-                self.client.send_message(&msg)
+            _ => Response::Nak,
+        }
+    }
+
+    fn switch_encryption_code(&mut self, request: &Payload) -> Response {
+        match (self.bnv_algorithm, BnvCode::from_bytes(&request.data)) {
+            (Some(_), Ok(code)) if self.encryption_support.is_some() => {
+                self.encryption_key = Some(EncryptionKey::Bnv(code));
+                self.encryption_changed = true;
+                Response::Ack
             }
-            HeaderType::ModifyInhibitStatus => {
-                let bitmask =
-                    u16::from_le_bytes([message.payload.data[0], message.payload.data[1]]);
-                for i in 0..16 {
-                    if bitmask & (1 << i) != 0 {
-                        self.coin_table.slots[i].inhibit = false;
-                    } else {
-                        self.coin_table.slots[i].inhibit = true;
-                    }
+            _ => Response::Nak,
+        }
+    }
+
+    /// Coin channel index from the coin position (1..=16) in the request
+    fn coin_position(request: &Payload) -> Option<u8> {
+        match request.data.first() {
+            Some(&position @ 1..=16) => Some(position - 1),
+            _ => None,
+        }
+    }
+
+    fn reset_device(&mut self, _request: &Payload) -> Response {
+        // Event counter is 0 only after power-up or reset
+        self.counter = 0;
+        self.credit_buffer = vec![0u8; 2 * CREDIT_BUFFER_EVENTS as usize];
+        self.unread_events = 0;
+        self.teach_channel = None;
+        self.teach_coins = 0;
+        self.teach_status = TeachStatus::Completed;
+        if self.data_storage.memory_type == MemoryType::VolatileOnReset {
+            self.data_blocks.iter_mut().for_each(|byte| *byte = 0);
+        }
+        if self.encryption_key != self.stored_encryption_key {
+            self.encryption_key = self.stored_encryption_key;
+            self.encryption_changed = true;
+        }
+
+        if self.reset_model.restore_configuration {
+            // Configuration is reloaded from EEPROM like after power-up
+            let stored = match self.store.load() {
+                Ok(state) => state.configuration,
+                Err(e) => {
+                    log::error!("Failed to load stored state: {:?}", e);
+                    None
                 }
-                self.ack()
+            };
+            let configuration = stored.unwrap_or_else(|| self.power_on.clone());
+            let coin_ids: Vec<CoinId> = self
+                .coin_table
+                .slots
+                .iter()
+                .map(|coin| coin.coin_value)
+                .collect();
+            self.apply_configuration(&configuration);
+            for (slot, coin_id) in self.coin_table.slots.iter_mut().zip(coin_ids) {
+                slot.coin_value = coin_id;
             }
-            HeaderType::RequestInhibitStatus => {
-                let mut bitmask: u16 = 0;
-                for i in 0..16 {
-                    if !self.coin_table.get_inhibit(i) {
-                        bitmask |= 1 << i;
-                    }
-                }
-                let msg = self.create_message(Payload {
-                    header: (HeaderType::Reply),
-                    data: (bitmask.to_le_bytes().to_vec()),
-                });
-                log::trace!("Sent: {:?}", msg);
-                self.client.send_message(&msg)
+        }
+        // Master inhibit is always active after a reset
+        self.cc_master_inhibit = true;
+
+        // ACK is sent before the restart
+        self.settle_until = Some(Instant::now() + self.reset_model.settle_time);
+        Response::Ack
+    }
+
+    fn modify_inhibit_status(&mut self, request: &Payload) -> Response {
+        let bitmask = match request.data[..] {
+            [low, high, ..] => u16::from_le_bytes([low, high]),
+            _ => return Response::Nak,
+        };
+        for i in 0..16 {
+            self.coin_table.slots[i].inhibit = bitmask & (1 << i) == 0;
+        }
+        Response::Ack
+    }
+
+    fn request_inhibit_status(&mut self, _request: &Payload) -> Response {
+        let mut bitmask: u16 = 0;
+        for i in 0..16 {
+            if !self.coin_table.get_inhibit(i) {
+                bitmask |= 1 << i;
             }
-            HeaderType::RequestMasterInhibitStatus => {
-                let status: u8;
-                if self.cc_master_inhibit {
-                    status = 0u8;
-                } else {
-                    status = 1u8;
-                }
-                let msg = self.create_message(Payload {
-                    header: (HeaderType::Reply),
-                    data: (vec![status]),
-                });
-                self.client.send_message(&msg)
+        }
+        Response::Reply(bitmask.to_le_bytes().to_vec())
+    }
+
+    fn modify_master_inhibit_status(&mut self, request: &Payload) -> Response {
+        match request.data.first() {
+            Some(status) => {
+                self.cc_master_inhibit = status & 1u8 == 0;
+                Response::Ack
             }
-            HeaderType::ModifyMasterInhibitStatus => {
-                if message.payload.data[0] & 1u8 != 0 {
-                    self.cc_master_inhibit = false;
-                } else {
-                    self.cc_master_inhibit = true;
-                }
-                self.ack()
+            None => Response::Nak,
+        }
+    }
+
+    fn request_master_inhibit_status(&mut self, _request: &Payload) -> Response {
+        let status: u8 = if self.cc_master_inhibit { 0u8 } else { 1u8 };
+        Response::Reply(vec![status])
+    }
+
+    fn request_coin_id(&mut self, request: &Payload) -> Response {
+        match CoinAcceptor::coin_position(request) {
+            Some(channel) => {
+                Response::Reply(self.coin_table.get_coin_value(channel).as_bytes().to_vec())
             }
-            HeaderType::RequestCoinId => {
-                let msg = self.create_message(Payload {
-                    header: (HeaderType::Reply),
-                    data: (self
-                        .coin_table
-                        .get_coin_value(message.payload.data[0] - 1)
-                        .as_bytes()
-                        .to_vec()),
-                });
-                self.client.send_message(&msg)
+            None => Response::Nak,
+        }
+    }
+
+    fn modify_coin_id(&mut self, request: &Payload) -> Response {
+        let channel = match CoinAcceptor::coin_position(request) {
+            Some(channel) => channel,
+            None => return Response::Nak,
+        };
+        match CoinId::from_bytes(&request.data[1..]) {
+            Ok(coin_id) if coin_id.is_valid() => {
+                self.coin_table.set_coin_id(channel, coin_id);
+                Response::Ack
             }
-            HeaderType::ReadBufferedCreditOrErrorCodes => {
-                let mut data: Vec<u8> = vec![];
-                let mut buffer = self.credit_buffer.clone();
-                data.push(self.counter);
-                data.append(&mut buffer);
-
-                //println!("Data: {:?}", data);
-                //println!("CB: {:?}", self.credit_buffer);
-                let msg = self.create_message(Payload {
-                    header: (HeaderType::Reply),
-                    data: (data),
-                });
-                log::trace!("Sent: {:?}", msg);
-                self.client.send_message(&msg)
+            _ => {
+                log::warn!("Invalid coin id: {:?}", request);
+                Response::Nak
             }
-            HeaderType::RequestDataStorageAvailability => {
-                let data: Vec<u8> = vec![0, 0, 0, 0, 0];
-                let msg = self.create_message(Payload {
-                    header: (HeaderType::Reply),
-                    data: (data),
-                });
-                self.client.send_message(&msg)
+        }
+    }
+
+    fn read_buffered_credit(&mut self, _request: &Payload) -> Response {
+        self.unread_events = 0;
+        let mut data: Vec<u8> = vec![self.counter];
+        data.extend_from_slice(&self.credit_buffer);
+        Response::Reply(data)
+    }
+
+    fn store_configuration(&mut self) -> Response {
+        let result = self.write_store(|acceptor, state| {
+            state.configuration = Some(acceptor.configuration());
+        });
+        match result {
+            Ok(()) => Response::Ack,
+            Err(e) => {
+                log::error!("Failed to store configuration: {:?}", e);
+                Response::Nak
             }
-            HeaderType::ResetDevice => {
-                self.counter = 0;
-                self.credit_buffer = vec![0u8; 10];
-                self.cc_master_inhibit = true;
-                self.ack()
+        }
+    }
+
+    fn store_counters(&mut self) -> Response {
+        let result = self.write_store(|acceptor, state| {
+            state.counters = Some(StoredCounters {
+                insertion: acceptor.insertion_counter,
+                accept: acceptor.accept_counter,
+            });
+        });
+        match result {
+            Ok(()) => Response::Ack,
+            Err(e) => {
+                log::error!("Failed to store counters: {:?}", e);
+                Response::Nak
             }
-            HeaderType::RequestPollingPriority => {
-                // Polling in 200ms intervals
-                let data: Vec<u8> = vec![2, 20];
-                let msg = self.create_message(Payload {
-                    header: (HeaderType::Reply),
-                    data: (data),
-                });
-                self.client.send_message(&msg)
+        }
+    }
+
+    fn request_sorter_paths(&mut self, request: &Payload) -> Response {
+        match CoinAcceptor::coin_position(request) {
+            Some(channel) => Response::Reply(self.coin_table.get_sort_paths(channel).to_vec()),
+            None => Response::Nak,
+        }
+    }
+
+    fn modify_sorter_paths(&mut self, request: &Payload) -> Response {
+        let channel = match CoinAcceptor::coin_position(request) {
+            Some(channel) if request.data.len() > 1 => channel,
+            _ => return Response::Nak,
+        };
+        // Paths not supplied by the host are left untouched
+        let mut sort_paths = self.coin_table.get_sort_paths(channel);
+        for (path, new_path) in sort_paths.iter_mut().zip(&request.data[1..]) {
+            *path = *new_path;
+        }
+        self.coin_table.set_sort_paths(channel, sort_paths);
+        Response::Ack
+    }
+
+    fn teach_mode_control(&mut self, request: &Payload) -> Response {
+        match CoinAcceptor::coin_position(request) {
+            Some(channel) => {
+                self.teach_channel = Some(channel);
+                self.teach_coins = 0;
+                self.teach_status = TeachStatus::InProgress;
+                Response::Ack
             }
-            HeaderType::RequestDatabaseVersion => {
-                // Remote programming not supported
-                let data: Vec<u8> = vec![0];
-                let msg = self.create_message(Payload {
-                    header: (HeaderType::Reply),
-                    data: (data),
-                });
-                self.client.send_message(&msg)
+            None => Response::Nak,
+        }
+    }
+
+    fn request_teach_status(&mut self, request: &Payload) -> Response {
+        // Mode 1 aborts teach mode, 0 only reports the status
+        if request.data.first() == Some(&1) && self.teach_status == TeachStatus::InProgress {
+            self.teach_channel = None;
+            self.teach_status = TeachStatus::Aborted;
+        }
+        Response::Reply(vec![self.teach_coins, self.teach_status.to_u8()])
+    }
+
+    fn read_data_block(&mut self, request: &Payload) -> Response {
+        let storage = self.data_storage;
+        let size = storage.read_bytes_per_block as usize;
+        match request.data[..] {
+            [block] if block < storage.read_blocks && size > 0 => {
+                let start = block as usize * size;
+                Response::Reply(self.data_blocks[start..start + size].to_vec())
             }
-            HeaderType::RequestSorterPaths => {
-                let msg = self.create_message(Payload {
-                    header: (HeaderType::Reply),
-                    data: (vec![self.coin_table.get_sort_path(message.payload.data[0] - 1)]),
-                });
-                self.client.send_message(&msg)
+            _ => Response::Nak,
+        }
+    }
+
+    fn write_data_block(&mut self, request: &Payload) -> Response {
+        let storage = self.data_storage;
+        let size = storage.write_bytes_per_block as usize;
+        let (block, data) = match request.data.split_first() {
+            Some((&block, data)) if block < storage.write_blocks && data.len() == size => {
+                (block, data)
             }
-            _ => {
-                log::warn!("Received unimplemented packet: {:?}", message.payload);
-                Ok(())
+            _ => return Response::Nak,
+        };
+        let start = block as usize * size;
+        self.data_blocks[start..start + size].copy_from_slice(data);
+
+        if storage.memory_type.is_permanent() {
+            let result = self.write_store(|acceptor, state| {
+                state.data_blocks = Some(acceptor.data_blocks.clone());
+            });
+            if let Err(e) = result {
+                log::error!("Failed to store data block: {:?}", e);
+                return Response::Nak;
             }
         }
+        Response::Ack
     }
+
     pub fn add_credit(&mut self, channel: u8) {
+        if self.variables.inhibit_on_full_buffer && self.unread_events >= CREDIT_BUFFER_EVENTS {
+            log::info!("Credit buffer full, coin on channel {} rejected", channel);
+            return;
+        }
+        self.count_insertion();
+        // Counters are 3 bytes on the wire
+        self.accept_counter = (self.accept_counter + 1) & 0xFF_FFFF;
+        let sort_path = self.effective_sort_path(channel - 1);
+        self.push_event(channel, sort_path);
+    }
+
+    /// Report an error event, read by the host as credit 0
+    ///
+    /// Rejected coins count as inserted, but not as accepted.
+    pub fn add_error(&mut self, error: CoinAcceptorError) {
+        let rejected = match error {
+            CoinAcceptorError::RejectSlug | CoinAcceptorError::RejectCoinRepeatedly => true,
+            error => error.severity() == Severity::Reject,
+        };
+        if rejected {
+            self.count_insertion();
+        }
+        self.push_event(0, error.to_u8());
+    }
+
+    fn count_insertion(&mut self) {
+        self.insertion_counter = (self.insertion_counter + 1) & 0xFF_FFFF;
+    }
+
+    fn push_event(&mut self, result_a: u8, result_b: u8) {
         let (cnt, roll) = self.counter.overflowing_add(1);
         if roll {
             self.counter = 1u8;
         } else {
             self.counter = cnt;
         }
+        self.unread_events = self.unread_events.saturating_add(1);
+        self.credit_buffer.insert(0, result_b);
+        self.credit_buffer.insert(0, result_a);
         self.credit_buffer
-            .insert(0, self.coin_table.get_sort_path(channel - 1));
-        self.credit_buffer.insert(0, channel);
-        self.credit_buffer.truncate(10);
+            .truncate(2 * CREDIT_BUFFER_EVENTS as usize);
+    }
+
+    /// Sorter path a coin on `channel` is routed to
+    ///
+    /// The first configured path which is not overridden is used,
+    /// falling back to the default sorter path.
+    pub fn effective_sort_path(&self, channel: u8) -> u8 {
+        self.coin_table
+            .get_sort_paths(channel)
+            .iter()
+            .copied()
+            .find(|&path| {
+                (1..=8).contains(&path) && self.cc_sorter_override & (1 << (path - 1)) != 0
+            })
+            .unwrap_or(self.cc_default_sort_path)
+    }
+
+    /// Insert a coin while teach mode is active
+    ///
+    /// Teach mode completes after `TEACH_MODE_COINS` coins,
+    /// and the taught channel is enabled.
+    pub fn teach_coin(&mut self) {
+        let channel = match self.teach_channel {
+            Some(channel) => channel,
+            None => {
+                log::warn!("Teach mode is not active");
+                return;
+            }
+        };
+        self.teach_coins += 1;
+        if self.teach_coins >= TEACH_MODE_COINS {
+            self.coin_table.slots[channel as usize].inhibit = false;
+            self.teach_channel = None;
+            self.teach_status = TeachStatus::Completed;
+        }
     }
 
     pub fn get_master_inhibit(&mut self) -> bool {
         self.cc_master_inhibit
     }
+
+    pub fn set_master_inhibit(&mut self, inhibit: bool) {
+        self.cc_master_inhibit = inhibit;
+    }
+
+    /// Firmware image, replaced by firmware upgrades
+    pub fn firmware(&self) -> &[u8] {
+        self.firmware.firmware()
+    }
+
+    pub fn set_firmware(&mut self, firmware: Vec<u8>) {
+        self.firmware = FirmwareMemory::new(firmware);
+    }
+
+    pub fn coin_table(&self) -> &CoinTable {
+        &self.coin_table
+    }
+
+    pub fn coin_table_mut(&mut self) -> &mut CoinTable {
+        &mut self.coin_table
+    }
+
+    /// Reset as if `ResetDevice` was received
+    pub fn reset(&mut self) {
+        let request = Payload {
+            header: HeaderType::ResetDevice,
+            data: vec![],
+        };
+        self.reset_device(&request);
+        if self.encryption_changed {
+            if let Err(e) = self.apply_encryption() {
+                log::error!("Failed to restore encryption: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Seconds since the Unix epoch
+fn system_time() -> i64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => elapsed.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    }
+}
+
+impl Peripheral for CoinAcceptor {
+    fn core_info(&self) -> &CoreInfo {
+        &self.cctalk_info
+    }
+
+    fn comms_stats(&self) -> CommsStats {
+        match self.client.as_ref() {
+            Some(client) => client.comms_stats(),
+            None => CommsStats::default(),
+        }
+    }
+
+    fn clear_comms_stats(&mut self) {
+        if let Some(client) = self.client.as_mut() {
+            client.clear_comms_stats();
+        }
+    }
+
+    fn real_time_clock(&self) -> Option<RealTimeClock> {
+        let seconds = (system_time() + self.rtc_offset).clamp(0, u32::MAX as i64);
+        Some(RealTimeClock::new(seconds as u32))
+    }
+
+    fn set_real_time_clock(&mut self, rtc: RealTimeClock) -> bool {
+        self.rtc_offset = rtc.seconds() as i64 - system_time();
+        true
+    }
+
+    fn intercept(&mut self, request: &Payload) -> Option<Response> {
+        if self.is_settling() {
+            log::debug!("Restarting, ignoring {:?}", request.header);
+            return Some(Response::NoReply);
+        }
+        self.settle_until = None;
+        None
+    }
+
+    fn equipment_category(&self) -> &str {
+        &self.cc_equipment_cat_id
+    }
+
+    fn handlers(&self) -> &Handlers<Self> {
+        &self.handlers
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::device::CCTalkDevice;
+    use crate::peripheral::wire::wire;
+    use crate::peripheral::LoopbackClient;
     use crate::protocol::Message;
 
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
     use std::sync::mpsc;
     use std::sync::mpsc::{Receiver, Sender};
 
@@ -378,7 +1049,7 @@ mod tests {
         fn set_bill_event(&mut self, _bill_event: BillEvent) {}
         fn read_messages(&mut self) -> Result<Vec<Message>, ClientError> {
             let mut buf = self.buf.recv().unwrap();
-            let msg = Message::decode(&mut buf)?;
+            let msg = Message::decode(&mut buf, ChecksumMode::Auto)?;
             Ok(vec![msg])
         }
         fn send_message(&mut self, msg: &Message) -> Result<(), ClientError> {
@@ -396,28 +1067,59 @@ mod tests {
         }};
     }
 
-    fn fullflow_cctalk_info() -> CoreInfo {
-        // Core Information for fullflow test below
-        CoreInfo {
-            manufacturer: "CPS",
-            product_code: "Colibri",
-            build_code: "DE0",
-            software_revision: "412-005",
-            // TODO: This needs to be fixed..
-            serial_number: 123u16,
-        }
+    fn request(header: HeaderType, data: Vec<u8>) -> Vec<u8> {
+        Message::new(2, 1, Payload { header, data }, ChecksumType::SimpleChecksum)
+            .unwrap()
+            .encode()
+    }
+
+    /// Acceptor with the test coin table, talking through the returned
+    /// request sender and reply receiver
+    fn acceptor() -> (CoinAcceptor, Sender<Vec<u8>>, Receiver<Message>) {
+        let (btx, brx): (Sender<Vec<u8>>, Receiver<Vec<u8>>) = mpsc::channel();
+        let (mtx, mrx): (Sender<Message>, Receiver<Message>) = mpsc::channel();
+
+        let client = MPSCTestClient::new(brx, mtx);
+        let cctalk = CoinAcceptor::init(
+            Box::new(client),
+            ChecksumType::SimpleChecksum,
+            Box::new(fullflow_cointable()),
+            Box::new(test_core_info()),
+        )
+        .unwrap();
+        (cctalk, btx, mrx)
+    }
+
+    /// Host API answered directly by an acceptor with the test coin table
+    fn loopback(core_info: CoreInfo) -> (CCTalkDevice, Rc<RefCell<CoinAcceptor>>) {
+        let acceptor = Rc::new(RefCell::new(CoinAcceptor::new(
+            2,
+            Box::new(fullflow_cointable()),
+            Box::new(core_info),
+        )));
+        let client = LoopbackClient::new(Box::new(acceptor.clone()));
+        let host = CCTalkDevice::new(Box::new(client), 2, ChecksumType::SimpleChecksum).unwrap();
+        (host, acceptor)
     }
+
+    fn coin(coin_id: &str) -> CoinId {
+        CoinId::new(coin_id).unwrap()
+    }
+
     fn fullflow_cointable() -> CoinTable {
         let mut table = CoinTable::default();
 
-        table.set_coininfo(0, CoinInfo::new("EU020A", 3u8));
-        table.set_coininfo(1, CoinInfo::new("EU050A", 1u8));
-        table.set_coininfo(2, CoinInfo::new("EU100A", 2u8));
-        table.set_coininfo(3, CoinInfo::new("EU200A", 1u8));
-        table.set_coininfo(4, CoinInfo::new("SE100C", 1u8));
-        table.set_coininfo(5, CoinInfo::new("SE200B", 1u8));
-        table.set_coininfo(6, CoinInfo::new("SE500B", 1u8));
-        table.set_coininfo(7, CoinInfo::new("SE1K0A", 1u8));
+        table.set_coininfo(0, CoinInfo::with_sort_paths(coin("EU020A"), [3, 4, 4, 4]));
+        table.set_coininfo(1, CoinInfo::with_sort_paths(coin("EU050A"), [1, 4, 4, 4]));
+        table.set_coininfo(2, CoinInfo::with_sort_paths(coin("EU100A"), [2, 4, 4, 4]));
+        table.set_coininfo(3, CoinInfo::with_sort_paths(coin("EU200A"), [1, 4, 4, 4]));
+        table.set_coininfo(4, CoinInfo::with_sort_paths(coin("SE100C"), [1, 4, 4, 4]));
+        table.set_coininfo(5, CoinInfo::with_sort_paths(coin("SE200B"), [1, 4, 4, 4]));
+        table.set_coininfo(6, CoinInfo::with_sort_paths(coin("SE500B"), [1, 4, 4, 4]));
+        table.set_coininfo(7, CoinInfo::with_sort_paths(coin("SE1K0A"), [1, 4, 4, 4]));
+        for channel in 8..16 {
+            table.set_sort_paths(channel, [1, 4, 4, 4]);
+        }
         table
     }
 
@@ -431,7 +1133,7 @@ mod tests {
             Box::new(client),
             ChecksumType::SimpleChecksum,
             Box::new(fullflow_cointable()),
-            Box::new(fullflow_cctalk_info()),
+            Box::new(test_core_info()),
         )
         .unwrap();
 
@@ -445,13 +1147,12 @@ mod tests {
         );
 
         // Request serial number
-        /* TODO: Implement it properly..
         let resp = send!(cctalk, channels, vec![2, 0, 1, 242, 11]);
         assert_eq!(
             resp.encode(),
+            // 149 + 48 * 256 + 16 * 65536 = 1061013
             vec![1, 3, 2, 0, 149, 48, 16, 37]
         );
-        */
 
         // Modify master inhibit status: inhibit active
         let resp = send!(cctalk, channels, vec![2, 1, 1, 228, 0, 24]);
@@ -461,7 +1162,7 @@ mod tests {
             vec![1, 0, 2, 0, 253]
         );
         assert_eq!(cctalk.get_master_inhibit(), cctalk.cc_master_inhibit);
-        assert_eq!(cctalk.get_master_inhibit(), true);
+        assert!(cctalk.get_master_inhibit());
 
         // Request polling priority
         let resp = send!(cctalk, channels, vec![2, 0, 1, 249, 4]);
@@ -517,7 +1218,7 @@ mod tests {
             // Master inhibit active
             vec![1, 1, 2, 0, 0, 252]
         );
-        assert_eq!(cctalk.get_master_inhibit(), true);
+        assert!(cctalk.get_master_inhibit());
 
         // Request channel inhibit status
         let resp = send!(cctalk, channels, vec![2, 0, 1, 230, 23]);
@@ -528,11 +1229,11 @@ mod tests {
         );
         for i in 0..=7 {
             let cc = &cctalk.coin_table.slots[i];
-            assert_eq!(cc.inhibit, false);
+            assert!(!cc.inhibit);
         }
         for i in 8..cctalk.coin_table.slots.len() {
             let cc = &cctalk.coin_table.slots[i];
-            assert_eq!(cc.inhibit, true);
+            assert!(cc.inhibit);
         }
 
         // Request coin id from channel 1
@@ -609,41 +1310,19 @@ mod tests {
             );
         }
 
-        /*
-         TODO...
-        2 1 1 209 1 42 ; Request sorter paths
-        1 4 2 0 3 4 4 4 234
-        2 1 1 209 2 41 ; Request sorter paths
-        1 4 2 0 1 4 4 4 236
-        2 1 1 209 3 40 ; Request sorter paths
-        1 4 2 0 2 4 4 4 235
-        2 1 1 209 4 39 ; Request sorter paths
-        1 4 2 0 1 4 4 4 236
-        2 1 1 209 5 38 ; Request sorter paths
-        1 4 2 0 1 4 4 4 236
-        2 1 1 209 6 37 ; Request sorter paths
-        1 4 2 0 1 4 4 4 236
-        2 1 1 209 7 36 ; Request sorter paths
-        1 4 2 0 1 4 4 4 236
-        2 1 1 209 8 35 ; Request sorter paths
-        1 4 2 0 1 4 4 4 236
-        2 1 1 209 9 34 ; Request sorter paths
-        1 4 2 0 1 4 4 4 236
-        2 1 1 209 10 33 ; Request sorter paths
-        1 4 2 0 1 4 4 4 236
-        2 1 1 209 11 32 ; Request sorter paths
-        1 4 2 0 1 4 4 4 236
-        2 1 1 209 12 31 ; Request sorter paths
-        1 4 2 0 1 4 4 4 236
-        2 1 1 209 13 30 ; Request sorter paths
-        1 4 2 0 1 4 4 4 236
-        2 1 1 209 14 29 ; Request sorter paths
-        1 4 2 0 1 4 4 4 236
-        2 1 1 209 15 28 ; Request sorter paths
-        1 4 2 0 1 4 4 4 236
-        2 1 1 209 16 27 ; Request sorter paths
-        1 4 2 0 1 4 4 4 236
-        */
+        // Request sorter paths from channel 1
+        let resp = send!(cctalk, channels, vec![2, 1, 1, 209, 1, 42]);
+        assert_eq!(resp.encode(), vec![1, 4, 2, 0, 3, 4, 4, 4, 234]);
+
+        // Request sorter paths from channel 3
+        let resp = send!(cctalk, channels, vec![2, 1, 1, 209, 3, 40]);
+        assert_eq!(resp.encode(), vec![1, 4, 2, 0, 2, 4, 4, 4, 235]);
+
+        // Request sorter paths from the rest of the channels
+        for i in [2, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16] {
+            let resp = send!(cctalk, channels, vec![2, 1, 1, 209, i, 43 - i]);
+            assert_eq!(resp.encode(), vec![1, 4, 2, 0, 1, 4, 4, 4, 236]);
+        }
 
         // Modify inhibit status (enables all configured coin channels 0..7)
         let resp = send!(cctalk, channels, vec![2, 2, 1, 231, 255, 0, 21]);
@@ -654,10 +1333,7 @@ mod tests {
         );
         for (i, cc) in cctalk.coin_table.slots.iter().enumerate() {
             // Channels 0..=7 are enabled
-            let status = match i {
-                0..=7 => false,
-                _ => true,
-            };
+            let status = !matches!(i, 0..=7);
             assert_eq!(cc.inhibit, status);
         }
 
@@ -687,7 +1363,7 @@ mod tests {
             Box::new(client),
             ChecksumType::SimpleChecksum,
             Box::new(fullflow_cointable()),
-            Box::new(fullflow_cctalk_info()),
+            Box::new(test_core_info()),
         )
         .unwrap();
 
@@ -767,8 +1443,8 @@ mod tests {
         let mut cctalk = CoinAcceptor::init(
             Box::new(client),
             ChecksumType::SimpleChecksum,
-            Box::new(CoinTable::default()),
-            Box::new(fullflow_cctalk_info()),
+            Box::default(),
+            Box::new(test_core_info()),
         )
         .unwrap();
 
@@ -784,4 +1460,664 @@ mod tests {
         cctalk.add_credit(1);
         assert_eq!(cctalk.counter, 1);
     }
+
+    #[test]
+    fn test_sorter_paths() {
+        let (mut cctalk, btx, mrx) = acceptor();
+
+        let channels = (&btx, &mrx);
+
+        // Modify sorter paths of channel 2 to 2, 3, 4
+        let resp = send!(cctalk, channels, vec![2, 4, 1, 210, 2, 2, 3, 4, 28]);
+        assert_eq!(resp.encode(), vec![1, 0, 2, 0, 253]);
+        assert_eq!(cctalk.coin_table.get_sort_paths(1), [2, 3, 4, 4]);
+
+        // Request sorter paths from channel 2
+        let resp = send!(cctalk, channels, vec![2, 1, 1, 209, 2, 41]);
+        assert_eq!(resp.encode(), vec![1, 4, 2, 0, 2, 3, 4, 4, 236]);
+
+        // Request sorter override status: no overrides
+        let resp = send!(cctalk, channels, vec![2, 0, 1, 221, 32]);
+        assert_eq!(resp.encode(), vec![1, 1, 2, 0, 255, 253]);
+
+        // Modify sorter override status: paths 2 and 3 overridden
+        let resp = send!(cctalk, channels, vec![2, 1, 1, 222, 249, 37]);
+        assert_eq!(resp.encode(), vec![1, 0, 2, 0, 253]);
+        assert_eq!(cctalk.effective_sort_path(1), 4);
+        assert_eq!(cctalk.effective_sort_path(0), 4);
+
+        // Modify default sorter path to 5
+        let resp = send!(cctalk, channels, vec![2, 1, 1, 189, 5, 58]);
+        assert_eq!(resp.encode(), vec![1, 0, 2, 0, 253]);
+
+        // Request default sorter path
+        let resp = send!(cctalk, channels, vec![2, 0, 1, 188, 65]);
+        assert_eq!(resp.encode(), vec![1, 1, 2, 0, 5, 247]);
+
+        // All paths overridden: coins go to the default path
+        let resp = send!(cctalk, channels, vec![2, 1, 1, 222, 0, 30]);
+        assert_eq!(resp.encode(), vec![1, 0, 2, 0, 253]);
+        cctalk.add_credit(2);
+        assert_eq!(cctalk.credit_buffer[..2], [2, 5]);
+    }
+
+    #[test]
+    fn test_coin_id_teach_mode() {
+        let (mut cctalk, btx, mrx) = acceptor();
+
+        let channels = (&btx, &mrx);
+
+        // Modify coin id of channel 9 to "GB100A"
+        let resp = send!(
+            cctalk,
+            channels,
+            vec![2, 7, 1, 185, 9, 71, 66, 49, 48, 48, 65, 217]
+        );
+        assert_eq!(resp.encode(), vec![1, 0, 2, 0, 253]);
+
+        // Request coin id from channel 9
+        let resp = send!(cctalk, channels, vec![2, 1, 1, 184, 9, 59]);
+        assert_eq!(resp.encode(), vec![1, 6, 2, 0, 71, 66, 49, 48, 48, 65, 156]);
+
+        // Coin ids outside the allowed character set are NAK-ed
+        let resp = send!(
+            cctalk,
+            channels,
+            request(HeaderType::ModifyCoinId, b"\x09GB!00A".to_vec())
+        );
+        assert!(matches!(resp.payload.header, HeaderType::Nak));
+        let resp = send!(cctalk, channels, vec![2, 1, 1, 184, 9, 59]);
+        assert_eq!(resp.encode(), vec![1, 6, 2, 0, 71, 66, 49, 48, 48, 65, 156]);
+
+        // Start teach mode on channel 9
+        let resp = send!(cctalk, channels, vec![2, 1, 1, 202, 9, 41]);
+        assert_eq!(resp.encode(), vec![1, 0, 2, 0, 253]);
+
+        // Request teach status: no coins entered, in progress
+        let resp = send!(cctalk, channels, vec![2, 1, 1, 201, 0, 51]);
+        assert_eq!(resp.encode(), vec![1, 2, 2, 0, 0, 254, 253]);
+
+        for _i in 0..TEACH_MODE_COINS {
+            cctalk.teach_coin();
+        }
+
+        // Request teach status: completed
+        let resp = send!(cctalk, channels, vec![2, 1, 1, 201, 0, 51]);
+        assert_eq!(resp.encode(), vec![1, 2, 2, 0, 10, 255, 242]);
+        assert!(!cctalk.coin_table.get_inhibit(8));
+
+        // Restart and abort teach mode after 3 coins
+        send!(cctalk, channels, vec![2, 1, 1, 202, 9, 41]);
+        for _i in 0..3 {
+            cctalk.teach_coin();
+        }
+        let resp = send!(cctalk, channels, vec![2, 1, 1, 201, 1, 50]);
+        assert_eq!(resp.encode(), vec![1, 2, 2, 0, 3, 252, 252]);
+    }
+
+    #[test]
+    fn test_configuration_to_eeprom() {
+        let (mut cctalk, btx, mrx) = acceptor();
+
+        let channels = (&btx, &mrx);
+
+        // Modify inhibit status (enables channels 0..3), master inhibit off
+        send!(cctalk, channels, vec![2, 2, 1, 231, 15, 0, 5]);
+        send!(
+            cctalk,
+            channels,
+            request(HeaderType::ModifyMasterInhibitStatus, vec![1])
+        );
+
+        // Configuration to EEPROM
+        let resp = send!(cctalk, channels, vec![2, 0, 1, 199, 54]);
+        assert_eq!(resp.encode(), vec![1, 0, 2, 0, 253]);
+
+        // Modify inhibit status (enables channels 0..7)
+        send!(cctalk, channels, vec![2, 2, 1, 231, 255, 0, 21]);
+
+        // Reset device restores the stored inhibits, the master inhibit
+        // is active regardless of the stored value
+        send!(cctalk, channels, vec![2, 0, 1, 1, 252]);
+        let resp = send!(cctalk, channels, vec![2, 0, 1, 230, 23]);
+        assert_eq!(resp.encode(), vec![1, 2, 2, 0, 15, 0, 236]);
+        assert!(cctalk.get_master_inhibit());
+
+        // Counters to EEPROM
+        cctalk.add_credit(1);
+        cctalk.add_credit(2);
+        let resp = send!(cctalk, channels, vec![2, 0, 1, 198, 55]);
+        assert_eq!(resp.encode(), vec![1, 0, 2, 0, 253]);
+
+        // Request accept counter
+        let resp = send!(cctalk, channels, vec![2, 0, 1, 225, 28]);
+        assert_eq!(resp.encode(), vec![1, 3, 2, 0, 2, 0, 0, 248]);
+
+        // Rejected coins are only counted as inserted
+        cctalk.add_error(CoinAcceptorError::RejectCoin);
+        cctalk.add_error(CoinAcceptorError::InhibitedCoinType(3));
+        cctalk.add_error(CoinAcceptorError::FlightDeckOpen);
+        let resp = send!(
+            cctalk,
+            channels,
+            request(HeaderType::RequestInsertionCounter, vec![])
+        );
+        assert_eq!(resp.payload.data, vec![4, 0, 0]);
+        let resp = send!(
+            cctalk,
+            channels,
+            request(HeaderType::RequestAcceptCounter, vec![])
+        );
+        assert_eq!(resp.payload.data, vec![2, 0, 0]);
+
+        // Failed writes are NAK-ed
+        cctalk.set_store(Box::new(ReadOnlyStore)).unwrap();
+        let resp = send!(cctalk, channels, vec![2, 0, 1, 199, 54]);
+        assert_eq!(resp.encode(), vec![1, 0, 2, 5, 248]);
+        let resp = send!(cctalk, channels, vec![2, 0, 1, 198, 55]);
+        assert_eq!(resp.encode(), vec![1, 0, 2, 5, 248]);
+    }
+
+    /// Store whose writes always fail
+    struct ReadOnlyStore;
+
+    impl StateStore for ReadOnlyStore {
+        fn load(&mut self) -> Result<StoredState, StorageError> {
+            Ok(StoredState::default())
+        }
+
+        fn save(&mut self, _state: &StoredState) -> Result<(), StorageError> {
+            Err(StorageError::FormatError("read only".to_string()))
+        }
+    }
+
+    #[test]
+    fn test_reset_device() {
+        let (mut cctalk, btx, mrx) = acceptor();
+        cctalk.set_reset_model(ResetModel {
+            restore_configuration: true,
+            settle_time: Duration::from_millis(50),
+        });
+
+        let channels = (&btx, &mrx);
+
+        // Inhibit all channels, change default sorter path, reprogram
+        // channel 1 and insert a coin
+        send!(cctalk, channels, vec![2, 2, 1, 231, 0, 0, 20]);
+        send!(cctalk, channels, vec![2, 1, 1, 189, 3, 60]);
+        send!(
+            cctalk,
+            channels,
+            request(HeaderType::ModifyCoinId, b"\x01GB100A".to_vec())
+        );
+        cctalk.add_credit(1);
+
+        // Reset is ACK-ed before the restart
+        let resp = send!(cctalk, channels, vec![2, 0, 1, 1, 252]);
+        assert_eq!(resp.encode(), vec![1, 0, 2, 0, 253]);
+        assert!(cctalk.is_settling());
+
+        // No reply while restarting
+        btx.send(vec![2, 0, 1, 254, 255]).unwrap();
+        let msg = cctalk.read_messages().pop().unwrap();
+        cctalk.reply_message(&msg).unwrap();
+        assert!(mrx.try_recv().is_err());
+
+        std::thread::sleep(Duration::from_millis(60));
+        let resp = send!(cctalk, channels, vec![2, 0, 1, 254, 255]);
+        assert_eq!(resp.encode(), vec![1, 0, 2, 0, 253]);
+
+        // Power-on inhibits and sorter path, event counter back to 0
+        let resp = send!(cctalk, channels, vec![2, 0, 1, 230, 23]);
+        assert_eq!(resp.encode(), vec![1, 2, 2, 0, 255, 0, 252]);
+        let resp = send!(cctalk, channels, vec![2, 0, 1, 188, 65]);
+        assert_eq!(resp.payload.data, vec![1]);
+        let resp = send!(cctalk, channels, vec![2, 0, 1, 229, 24]);
+        assert_eq!(resp.payload.data, vec![0; 11]);
+        assert!(cctalk.get_master_inhibit());
+
+        // Programmed coin ids are kept
+        assert_eq!(cctalk.coin_table.get_coin_value(0), "GB100A");
+    }
+
+    #[test]
+    fn test_data_blocks() {
+        let (mut cctalk, btx, mrx) = acceptor();
+        let mut layout = DataStorage {
+            memory_type: MemoryType::PermanentUnlimited,
+            read_blocks: 2,
+            read_bytes_per_block: 4,
+            write_blocks: 2,
+            write_bytes_per_block: 4,
+        };
+        cctalk.set_data_storage(layout).unwrap();
+
+        let channels = (&btx, &mrx);
+        let read = |block| request(HeaderType::ReadDataBlock, vec![block]);
+
+        let resp = send!(
+            cctalk,
+            channels,
+            request(HeaderType::RequestDataStorageAvailability, vec![])
+        );
+        assert_eq!(resp.payload.data, vec![3, 2, 4, 2, 4]);
+
+        let resp = send!(
+            cctalk,
+            channels,
+            request(HeaderType::WriteDataBlock, vec![1, 1, 2, 3, 4])
+        );
+        assert_eq!(resp.payload.header.to_u8(), 0);
+        assert_eq!(
+            send!(cctalk, channels, read(1)).payload.data,
+            vec![1, 2, 3, 4]
+        );
+        assert_eq!(send!(cctalk, channels, read(0)).payload.data, vec![0; 4]);
+
+        // Out of range block and wrong block size
+        let resp = send!(cctalk, channels, read(2));
+        assert_eq!(resp.payload.header.to_u8(), 5);
+        let resp = send!(
+            cctalk,
+            channels,
+            request(HeaderType::WriteDataBlock, vec![0, 1, 2])
+        );
+        assert_eq!(resp.payload.header.to_u8(), 5);
+
+        // Permanent memory is restored from the store
+        cctalk.set_data_storage(layout).unwrap();
+        assert_eq!(
+            send!(cctalk, channels, read(1)).payload.data,
+            vec![1, 2, 3, 4]
+        );
+
+        // Volatile memory is lost on reset
+        layout.memory_type = MemoryType::VolatileOnReset;
+        cctalk.set_data_storage(layout).unwrap();
+        assert_eq!(send!(cctalk, channels, read(1)).payload.data, vec![0; 4]);
+        send!(
+            cctalk,
+            channels,
+            request(HeaderType::WriteDataBlock, vec![0, 9, 9, 9, 9])
+        );
+        cctalk.reset();
+        assert_eq!(send!(cctalk, channels, read(0)).payload.data, vec![0; 4]);
+    }
+
+    #[test]
+    fn test_variable_set() {
+        let (mut cctalk, btx, mrx) = acceptor();
+        let channels = (&btx, &mrx);
+
+        let resp = send!(
+            cctalk,
+            channels,
+            request(HeaderType::RequestVariableSet, vec![])
+        );
+        assert_eq!(resp.payload.data, vec![0, 5]);
+        let resp = send!(
+            cctalk,
+            channels,
+            request(HeaderType::RequestOptionFlags, vec![])
+        );
+        assert_eq!(resp.payload.data, vec![0]);
+
+        let resp = send!(
+            cctalk,
+            channels,
+            request(HeaderType::ModifyVariableSet, vec![1])
+        );
+        assert_eq!(resp.payload.header.to_u8(), 0);
+        let resp = send!(
+            cctalk,
+            channels,
+            request(HeaderType::RequestVariableSet, vec![])
+        );
+        assert_eq!(resp.payload.data, vec![1, 5]);
+
+        // Sixth coin is rejected until the host reads the buffer
+        for _ in 0..6 {
+            cctalk.add_credit(1);
+        }
+        let credit = request(HeaderType::ReadBufferedCreditOrErrorCodes, vec![]);
+        assert_eq!(send!(cctalk, channels, credit.clone()).payload.data[0], 5);
+        cctalk.add_credit(1);
+        assert_eq!(send!(cctalk, channels, credit).payload.data[0], 6);
+    }
+
+    #[test]
+    fn test_real_time_clock() {
+        let mut cctalk = CoinAcceptor::new(
+            2,
+            Box::new(fullflow_cointable()),
+            Box::new(test_core_info()),
+        );
+        let rtc =
+            RealTimeClock::from_date_time(DateCode::new(2020, 5, 19).unwrap(), 20, 34, 56).unwrap();
+        let modify = Payload {
+            header: HeaderType::ModifyRealTimeClock,
+            data: rtc.to_bytes().to_vec(),
+        };
+        assert_eq!(dispatch(&mut cctalk, &modify), Response::Ack);
+
+        let request = Payload {
+            header: HeaderType::RequestRealTimeClock,
+            data: vec![],
+        };
+        let data = match dispatch(&mut cctalk, &request) {
+            Response::Reply(data) => data,
+            response => panic!("unexpected response {:?}", response),
+        };
+        // The clock keeps running
+        let elapsed = RealTimeClock::from_bytes(&data).unwrap().seconds() - rtc.seconds();
+        assert!(elapsed <= 1);
+    }
+
+    /// Stand-in for the licensed BNV algorithm
+    struct XorCipher(u8);
+
+    impl Cipher for XorCipher {
+        fn encrypt(&self, data: &mut [u8]) {
+            data.iter_mut().for_each(|byte| *byte ^= self.0);
+        }
+        fn decrypt(&self, data: &mut [u8]) {
+            self.encrypt(data);
+        }
+    }
+
+    fn xor_bnv(code: &BnvCode) -> Box<dyn Cipher + Send + Sync> {
+        Box::new(XorCipher(
+            code.to_bytes().iter().fold(0x5a, |key, b| key ^ b),
+        ))
+    }
+
+    #[test]
+    fn test_serial_number() {
+        let (mut host, device) = wire(2);
+        let core_info = CoreInfo {
+            serial_number: SerialNumber::new(0x12_34_56).unwrap(),
+            ..test_core_info()
+        };
+        let mut cctalk = CoinAcceptor::init(
+            Box::new(device),
+            ChecksumType::SimpleChecksum,
+            Box::new(fullflow_cointable()),
+            Box::new(core_info),
+        )
+        .unwrap();
+        host.set_peer(move || {
+            for request in cctalk.read_messages() {
+                cctalk.reply_message(&request)?;
+            }
+            Ok(())
+        });
+        let mut host = CCTalkDevice::new(Box::new(host), 2, ChecksumType::SimpleChecksum).unwrap();
+
+        let serial = host.request_serial_number().unwrap();
+        assert_eq!(serial.value(), 0x12_34_56);
+        assert_eq!(serial.to_bytes(), [0x56, 0x34, 0x12]);
+    }
+
+    #[test]
+    fn test_encryption() {
+        let (mut host, device) = wire(2);
+        let mut cctalk = CoinAcceptor::init(
+            Box::new(device),
+            ChecksumType::SimpleChecksum,
+            Box::new(fullflow_cointable()),
+            Box::new(test_core_info()),
+        )
+        .unwrap();
+        cctalk.set_encryption_support(EncryptionSupport::default(), Some(xor_bnv));
+        host.set_peer(move || {
+            for request in cctalk.read_messages() {
+                cctalk.reply_message(&request)?;
+            }
+            Ok(())
+        });
+        let mut host = CCTalkDevice::new(Box::new(host), 2, ChecksumType::SimpleChecksum).unwrap();
+
+        assert_eq!(
+            host.request_encryption_support().unwrap(),
+            EncryptionSupport::default()
+        );
+
+        host.switch_encryption_key([1, 2, 3, 4, 5, 6, 7, 8])
+            .unwrap();
+        assert!(host.simple_poll().is_ok());
+
+        let code = BnvCode::new("123456").unwrap();
+        host.switch_encryption_code(&code, xor_bnv).unwrap();
+        assert!(host.simple_poll().is_ok());
+        host.store_encryption_code().unwrap();
+
+        // The device doesn't understand plain text any more
+        host.set_encryption(None).unwrap();
+        assert!(matches!(
+            host.simple_poll(),
+            Err(ClientError::CCTalkError(ErrorType::NoResponse))
+        ));
+
+        // After a reset the stored code is used again
+        host.set_encryption(Some(EncryptionLayer::bnv(&code, xor_bnv)))
+            .unwrap();
+        host.switch_encryption_key([8, 7, 6, 5, 4, 3, 2, 1])
+            .unwrap();
+        host.reset().unwrap();
+        assert!(host.simple_poll().is_err());
+        host.set_encryption(Some(EncryptionLayer::bnv(&code, xor_bnv)))
+            .unwrap();
+        assert!(host.simple_poll().is_ok());
+    }
+
+    #[test]
+    fn test_bnv_key_without_algorithm() {
+        let (_, device) = wire(2);
+        let mut cctalk = CoinAcceptor::init(
+            Box::new(device),
+            ChecksumType::SimpleChecksum,
+            Box::new(fullflow_cointable()),
+            Box::new(test_core_info()),
+        )
+        .unwrap();
+        cctalk.set_encryption_support(EncryptionSupport::default(), None);
+
+        let code = BnvCode::new("123456").unwrap();
+        assert!(matches!(
+            cctalk.set_encryption_key(Some(EncryptionKey::Bnv(code))),
+            Err(ClientError::CCTalkError(ErrorType::NoBnvAlgorithm))
+        ));
+        assert!(cctalk
+            .set_encryption_key(Some(EncryptionKey::Des([1, 2, 3, 4, 5, 6, 7, 8])))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_host_coin_id_teach_mode() {
+        let (mut host, acceptor) = loopback(test_core_info());
+
+        host.modify_coin_id(9, coin("GB100A")).unwrap();
+        assert_eq!(host.request_coin_id(9).unwrap(), coin("GB100A"));
+        assert!(matches!(
+            host.modify_coin_id(9, coin("GB!00A")),
+            Err(ClientError::CCTalkError(ErrorType::Nak))
+        ));
+        assert_eq!(host.request_coin_id(9).unwrap(), coin("GB100A"));
+
+        host.teach_mode_control(9).unwrap();
+        assert_eq!(
+            host.request_teach_status(false).unwrap(),
+            (0, TeachStatus::InProgress)
+        );
+        for _i in 0..TEACH_MODE_COINS {
+            acceptor.borrow_mut().teach_coin();
+        }
+        assert_eq!(
+            host.request_teach_status(false).unwrap(),
+            (TEACH_MODE_COINS, TeachStatus::Completed)
+        );
+
+        host.teach_mode_control(9).unwrap();
+        acceptor.borrow_mut().teach_coin();
+        assert_eq!(
+            host.request_teach_status(true).unwrap(),
+            (1, TeachStatus::Aborted)
+        );
+    }
+
+    #[test]
+    fn test_host_comms_status() {
+        let (mut host, device) = wire(2);
+        let core_info = CoreInfo {
+            comms_revision: [1, 4, 6],
+            ..test_core_info()
+        };
+        let mut cctalk = CoinAcceptor::init(
+            Box::new(device),
+            ChecksumType::SimpleChecksum,
+            Box::new(fullflow_cointable()),
+            Box::new(core_info),
+        )
+        .unwrap();
+        host.set_peer(move || {
+            for request in cctalk.read_messages() {
+                cctalk.reply_message(&request)?;
+            }
+            Ok(())
+        });
+        // Simple poll with a broken checksum, seen with the next request
+        host.send_raw(&[2, 0, 1, 254, 0]).unwrap();
+        let mut host = CCTalkDevice::new(Box::new(host), 2, ChecksumType::SimpleChecksum).unwrap();
+
+        let stats = host.request_comms_status_variables().unwrap();
+        assert_eq!(stats.rx_checksum_errors, 1);
+        assert!(stats.rx_bytes_ignored > 0);
+
+        host.clear_comms_status_variables().unwrap();
+        assert_eq!(
+            host.request_comms_status_variables().unwrap(),
+            CommsStats::default()
+        );
+        assert_eq!(host.request_comms_revision().unwrap(), [1, 4, 6]);
+    }
+
+    #[test]
+    fn test_host_data_blocks() {
+        let (mut host, acceptor) = loopback(test_core_info());
+        let layout = DataStorage {
+            memory_type: MemoryType::PermanentUnlimited,
+            read_blocks: 2,
+            read_bytes_per_block: 4,
+            write_blocks: 2,
+            write_bytes_per_block: 4,
+        };
+        acceptor.borrow_mut().set_data_storage(layout).unwrap();
+
+        assert_eq!(host.request_data_storage_availability().unwrap(), layout);
+        host.write_data_block(1, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(host.read_data_block(1).unwrap(), vec![1, 2, 3, 4]);
+        assert_eq!(host.read_data_block(0).unwrap(), vec![0; 4]);
+
+        assert!(matches!(
+            host.write_data_block(0, &[9, 9, 9]),
+            Err(ClientError::CCTalkError(ErrorType::Nak))
+        ));
+        assert_eq!(host.read_data_block(0).unwrap(), vec![0; 4]);
+    }
+
+    #[test]
+    fn test_host_variable_set() {
+        let (mut host, acceptor) = loopback(test_core_info());
+
+        let variables: CoinAcceptorVariables = host.request_variable_set().unwrap();
+        assert_eq!(
+            variables,
+            CoinAcceptorVariables {
+                inhibit_on_full_buffer: false,
+                buffer_size: 5,
+            }
+        );
+        assert_eq!(
+            CoinAcceptorOptions::from_u8(host.request_option_flags().unwrap()),
+            CoinAcceptorOptions::default()
+        );
+
+        // The read-only buffer size is not sent
+        host.modify_variable_set(&CoinAcceptorVariables {
+            inhibit_on_full_buffer: true,
+            buffer_size: 9,
+        })
+        .unwrap();
+        let variables: CoinAcceptorVariables = host.request_variable_set().unwrap();
+        assert!(variables.inhibit_on_full_buffer);
+        assert_eq!(variables.buffer_size, 5);
+        assert!(acceptor.borrow().variables.inhibit_on_full_buffer);
+    }
+
+    #[test]
+    fn test_host_dates_and_clock() {
+        let (mut host, _acceptor) = loopback(CoreInfo {
+            base_year: 2000,
+            creation_date: Some(DateCode::new(2019, 3, 14).unwrap()),
+            modification_date: Some(DateCode::new(2020, 1, 2).unwrap()),
+            ..test_core_info()
+        });
+
+        let base_year = host.request_base_year().unwrap();
+        assert_eq!(base_year, 2000);
+        assert_eq!(
+            host.request_creation_date(base_year).unwrap(),
+            DateCode::new(2019, 3, 14).unwrap()
+        );
+        assert_eq!(
+            host.request_last_modification_date(base_year).unwrap(),
+            DateCode::new(2020, 1, 2).unwrap()
+        );
+
+        let rtc =
+            RealTimeClock::from_date_time(DateCode::new(2020, 5, 19).unwrap(), 20, 34, 56).unwrap();
+        host.modify_real_time_clock(rtc).unwrap();
+        // The clock keeps running
+        let elapsed = host.request_real_time_clock().unwrap().seconds() - rtc.seconds();
+        assert!(elapsed <= 1);
+    }
+
+    #[test]
+    fn test_host_polling_priority_and_address_mode() {
+        let polling_priority = PollingPriority::new(PollingUnit::Seconds, 2);
+        let address_mode = AddressMode {
+            eeprom: true,
+            serial_non_volatile: true,
+            ..AddressMode::default()
+        };
+        let (mut host, _acceptor) = loopback(CoreInfo {
+            polling_priority,
+            address_mode: Some(address_mode),
+            ..test_core_info()
+        });
+
+        assert_ne!(polling_priority, DEFAULT_POLLING_PRIORITY);
+        assert_eq!(host.request_polling_priority().unwrap(), polling_priority);
+        assert_eq!(host.request_address_mode().unwrap(), address_mode);
+    }
+
+    #[test]
+    fn test_encrypted_commands_unsupported() {
+        let mut cctalk = CoinAcceptor::new(
+            2,
+            Box::new(fullflow_cointable()),
+            Box::new(test_core_info()),
+        );
+        for header in [
+            HeaderType::ReadEncryptedEvents,
+            HeaderType::RequestEncryptedMonetaryId,
+            HeaderType::RequestEncryptedHopperStatus,
+        ] {
+            let request = Payload {
+                header,
+                data: vec![0; 8],
+            };
+            assert_eq!(dispatch(&mut cctalk, &request), Response::Nak);
+        }
+    }
 }
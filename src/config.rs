@@ -0,0 +1,245 @@
+use std::convert;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::coinacceptor::{CoinInfo, CoinTable, SORTER_PATHS};
+use crate::peripheral::CoreInfo;
+use crate::protocol::{CoinId, DataStorage};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    IOError(io::Error),
+    FormatError(String),
+    InvalidChannel(u8),
+    /// More than one coin for the channel
+    DuplicateChannel(u8),
+    InvalidCoinId(String),
+    InvalidSortPaths(u8),
+}
+
+impl convert::From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> ConfigError {
+        ConfigError::IOError(e)
+    }
+}
+
+/// Coin channel entry of a device configuration
+#[derive(Debug, Clone, Deserialize)]
+pub struct CoinConfig {
+    /// Coin position, 1..=16
+    pub channel: u8,
+    pub coin_id: String,
+    /// Up to 4 sorter paths, missing paths are 0
+    #[serde(default)]
+    pub sort_paths: Vec<u8>,
+    #[serde(default)]
+    pub inhibit: bool,
+}
+
+/// Emulated coin acceptor description
+///
+/// Example TOML:
+///
+/// ```toml
+/// [core]
+/// manufacturer = "CPS"
+/// product_code = "Colibri"
+/// build_code = "DE0"
+/// serial_number = 123
+/// software_revision = "412-005"
+/// polling_priority = { unit = "TenMilliseconds", value = 20 }
+/// address_mode = { eeprom = true, serial_non_volatile = true }
+///
+/// [[coins]]
+/// channel = 1
+/// coin_id = "EU020A"
+/// sort_paths = [3, 4, 4, 4]
+///
+/// [data_storage]
+/// memory_type = "PermanentUnlimited"
+/// read_blocks = 4
+/// read_bytes_per_block = 16
+/// write_blocks = 4
+/// write_bytes_per_block = 16
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceConfig {
+    pub core: CoreInfo,
+    #[serde(default)]
+    pub coins: Vec<CoinConfig>,
+    /// Data storage blocks, none if missing
+    #[serde(default)]
+    pub data_storage: Option<DataStorage>,
+}
+
+impl DeviceConfig {
+    pub fn from_toml(raw: &str) -> Result<DeviceConfig, ConfigError> {
+        toml::from_str(raw).map_err(|e| ConfigError::FormatError(e.to_string()))
+    }
+
+    pub fn from_json(raw: &str) -> Result<DeviceConfig, ConfigError> {
+        serde_json::from_str(raw).map_err(|e| ConfigError::FormatError(e.to_string()))
+    }
+
+    /// Load configuration file, format is selected by the extension
+    ///
+    /// Files ending in `.json` are parsed as JSON, everything else as TOML.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<DeviceConfig, ConfigError> {
+        let raw = fs::read_to_string(&path)?;
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("json") => DeviceConfig::from_json(&raw),
+            _ => DeviceConfig::from_toml(&raw),
+        }
+    }
+
+    /// Build the coin table, validating channels, coin ids and sorter paths
+    pub fn coin_table(&self) -> Result<CoinTable, ConfigError> {
+        let mut table = CoinTable::default();
+        let mut configured = 0u16;
+
+        for coin in &self.coins {
+            if !(1..=16).contains(&coin.channel) {
+                return Err(ConfigError::InvalidChannel(coin.channel));
+            }
+            let mask = 1 << (coin.channel - 1);
+            if configured & mask != 0 {
+                return Err(ConfigError::DuplicateChannel(coin.channel));
+            }
+            configured |= mask;
+            let coin_id = CoinId::new(&coin.coin_id)
+                .ok()
+                .filter(CoinId::is_valid)
+                .ok_or_else(|| ConfigError::InvalidCoinId(coin.coin_id.clone()))?;
+            if coin.sort_paths.len() > SORTER_PATHS {
+                return Err(ConfigError::InvalidSortPaths(coin.channel));
+            }
+            let mut sort_paths = [0u8; SORTER_PATHS];
+            sort_paths[..coin.sort_paths.len()].copy_from_slice(&coin.sort_paths);
+
+            let channel = coin.channel - 1;
+            table.set_coininfo(channel, CoinInfo::with_sort_paths(coin_id, sort_paths));
+            table.set_inhibit(channel, coin.inhibit);
+        }
+
+        Ok(table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{DateCode, DEFAULT_POLLING_PRIORITY};
+    use std::time::Duration;
+
+    const COLIBRI: &str = r#"
+        [core]
+        manufacturer = "CPS"
+        product_code = "Colibri"
+        build_code = "DE0"
+        serial_number = 123
+        software_revision = "412-005"
+        creation_date = "2019-05-17"
+        polling_priority = { unit = "Seconds", value = 2 }
+        address_mode = { eeprom = true, serial_non_volatile = true }
+
+        [[coins]]
+        channel = 1
+        coin_id = "EU020A"
+        sort_paths = [3, 4, 4, 4]
+
+        [[coins]]
+        channel = 8
+        coin_id = "SE1K0A"
+        sort_paths = [1]
+        inhibit = true
+
+        [data_storage]
+        memory_type = "PermanentUnlimited"
+        read_blocks = 4
+        read_bytes_per_block = 16
+        write_blocks = 4
+        write_bytes_per_block = 16
+    "#;
+
+    #[test]
+    fn test_load_toml() {
+        let config = DeviceConfig::from_toml(COLIBRI).unwrap();
+        assert_eq!(config.core.product_code, "Colibri");
+        assert_eq!(config.core.serial_number.value(), 123);
+        assert_eq!(config.data_storage.unwrap().size(), 64);
+        assert_eq!(config.core.base_year, 2000);
+        assert_eq!(
+            config.core.creation_date,
+            Some(DateCode::new(2019, 5, 17).unwrap())
+        );
+        assert_eq!(
+            config.core.polling_priority.interval(),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(config.core.address_mode.unwrap().to_u8(), 0b1000_0100);
+
+        let table = config.coin_table().unwrap();
+        assert_eq!(table.get_coin_value(0), "EU020A");
+        assert_eq!(table.get_sort_paths(0), [3, 4, 4, 4]);
+        assert!(!table.get_inhibit(0));
+        assert_eq!(table.get_coin_value(7), "SE1K0A");
+        assert_eq!(table.get_sort_paths(7), [1, 0, 0, 0]);
+        assert!(table.get_inhibit(7));
+        assert_eq!(table.get_coin_value(1), "......");
+        assert!(table.get_inhibit(1));
+    }
+
+    #[test]
+    fn test_load_json() {
+        let config = DeviceConfig::from_json(
+            r#"{
+                "core": {
+                    "manufacturer": "NONE",
+                    "product_code": "Test",
+                    "build_code": "A0",
+                    "serial_number": 1,
+                    "software_revision": "000-000"
+                },
+                "coins": [{ "channel": 16, "coin_id": "GB.50A" }]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.core.polling_priority, DEFAULT_POLLING_PRIORITY);
+        assert_eq!(config.core.address_mode, None);
+        let table = config.coin_table().unwrap();
+        assert_eq!(table.get_coin_value(15), "GB.50A");
+    }
+
+    #[test]
+    fn test_invalid_coins() {
+        let mut config = DeviceConfig::from_toml(COLIBRI).unwrap();
+        config.coins[0].coin_id = "eu020A".to_string();
+        assert!(matches!(
+            config.coin_table(),
+            Err(ConfigError::InvalidCoinId(_))
+        ));
+
+        config.coins[0].coin_id = "EU020".to_string();
+        assert!(matches!(
+            config.coin_table(),
+            Err(ConfigError::InvalidCoinId(_))
+        ));
+
+        config.coins[0].coin_id = "EU020A".to_string();
+        config.coins[0].channel = 17;
+        assert!(matches!(
+            config.coin_table(),
+            Err(ConfigError::InvalidChannel(17))
+        ));
+
+        config.coins[0].channel = 8;
+        assert!(matches!(
+            config.coin_table(),
+            Err(ConfigError::DuplicateChannel(8))
+        ));
+    }
+}
@@ -1,7 +1,9 @@
 use crate::client::*;
+use crate::encryption::*;
 use crate::protocol::*;
 // Reimports
-pub use crate::coinacceptor::{CoinAcceptor, CoinInfo, CoinTable, CoreInfo};
+pub use crate::coinacceptor::{CoinAcceptor, CoinInfo, CoinTable, ResetModel};
+pub use crate::peripheral::CoreInfo;
 
 pub struct CCTalkDevice {
     pub client: Box<dyn CCTalkClient + 'static>,
@@ -12,10 +14,11 @@ pub struct CCTalkDevice {
 
 impl CCTalkDevice {
     pub fn new(
-        client: Box<dyn CCTalkClient>,
+        mut client: Box<dyn CCTalkClient>,
         address: Address,
         checksum_type: ChecksumType,
     ) -> Result<CCTalkDevice, ClientError> {
+        client.set_checksum_mode(checksum_type.into());
         Ok(CCTalkDevice {
             client,
             address,
@@ -28,20 +31,20 @@ impl CCTalkDevice {
         self.client.set_bill_event(bill_event);
     }
 
-    pub fn create_message(&mut self, payload: Payload) -> Message {
-        Message::new(
+    pub fn create_message(&mut self, payload: Payload) -> Result<Message, ClientError> {
+        Ok(Message::new(
             self.address,
             self.client.get_address(),
             payload,
             self.checksum_type,
-        )
+        )?)
     }
 
     pub fn reset(&mut self) -> Result<Payload, ClientError> {
         let message = self.create_message(Payload {
             header: HeaderType::ResetDevice,
             data: Vec::<u8>::new(),
-        });
+        })?;
         self.client.send_and_check_reply(&message)
     }
 
@@ -49,7 +52,7 @@ impl CCTalkDevice {
         let message = self.create_message(Payload {
             header: HeaderType::SimplePoll,
             data: Vec::<u8>::new(),
-        });
+        })?;
         self.client.send_and_check_reply(&message)
     }
 
@@ -60,7 +63,7 @@ impl CCTalkDevice {
         let message = self.create_message(Payload {
             header: HeaderType::ModifyInhibitStatus,
             data: inhibit_status,
-        });
+        })?;
         self.client.send_and_check_reply(&message)
     }
 
@@ -71,7 +74,7 @@ impl CCTalkDevice {
         let message = self.create_message(Payload {
             header: HeaderType::ModifyMasterInhibitStatus,
             data: vec![inhibit_status],
-        });
+        })?;
         self.client.send_and_check_reply(&message)
     }
 
@@ -79,7 +82,7 @@ impl CCTalkDevice {
         let message = self.create_message(Payload {
             header: HeaderType::ReadBufferedCreditOrErrorCodes,
             data: Vec::<u8>::new(),
-        });
+        })?;
         self.client.send_and_check_reply(&message)
     }
 
@@ -98,7 +101,7 @@ impl CCTalkDevice {
         let message = self.create_message(Payload {
             header: HeaderType::ReadBufferedBillEvents,
             data: Vec::<u8>::new(),
-        });
+        })?;
         let response = self.client.send_and_check_reply(&message)?;
         let res = CCTalkDevice::interpret_buffered_bill_payload(response)?;
         Ok(res)
@@ -108,7 +111,7 @@ impl CCTalkDevice {
         let message = self.create_message(Payload {
             header: HeaderType::ModifyBillOperatingMode,
             data: vec![mode],
-        });
+        })?;
         self.client.send_and_check_reply(&message)
     }
 
@@ -116,7 +119,7 @@ impl CCTalkDevice {
         let message = self.create_message(Payload {
             header: HeaderType::RouteBill,
             data: vec![route],
-        });
+        })?;
         self.client.send_and_check_reply(&message)
     }
 
@@ -124,7 +127,7 @@ impl CCTalkDevice {
         let message = self.create_message(Payload {
             header: HeaderType::RequestEquipmentCategoryId,
             data: Vec::<u8>::new(),
-        });
+        })?;
         let payload = self.client.send_and_check_reply(&message);
 
         match payload {
@@ -133,12 +136,334 @@ impl CCTalkDevice {
         }
     }
 
+    pub fn request_serial_number(&mut self) -> Result<SerialNumber, ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::RequestSerialNumber,
+            data: Vec::<u8>::new(),
+        })?;
+        let payload = self.client.send_and_check_reply(&message)?;
+        Ok(SerialNumber::from_bytes(&payload.data)?)
+    }
+
+    /// Receive error counters of the local client
+    pub fn comms_stats(&self) -> CommsStats {
+        self.client.comms_stats()
+    }
+
+    /// Receive error counters of the device
+    pub fn request_comms_status_variables(&mut self) -> Result<CommsStats, ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::RequestCommsStatusVariables,
+            data: Vec::<u8>::new(),
+        })?;
+        let payload = self.client.send_and_check_reply(&message)?;
+        Ok(CommsStats::from_bytes(&payload.data)?)
+    }
+
+    pub fn clear_comms_status_variables(&mut self) -> Result<Payload, ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::ClearCommsStatusVariables,
+            data: Vec::<u8>::new(),
+        })?;
+        self.client.send_and_check_reply(&message)
+    }
+
+    /// Returns the release, major and minor ccTalk revision
+    pub fn request_comms_revision(&mut self) -> Result<[u8; 3], ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::RequestCommsRevision,
+            data: Vec::<u8>::new(),
+        })?;
+        let payload = self.client.send_and_check_reply(&message)?;
+        match payload.data[..] {
+            [release, major, minor] => Ok([release, major, minor]),
+            _ => Err(ClientError::CCTalkError(ErrorType::ParseError)),
+        }
+    }
+
+    /// Recommended polling interval, see `PollingPriority::interval`
+    pub fn request_polling_priority(&mut self) -> Result<PollingPriority, ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::RequestPollingPriority,
+            data: Vec::<u8>::new(),
+        })?;
+        let payload = self.client.send_and_check_reply(&message)?;
+        Ok(PollingPriority::from_bytes(&payload.data)?)
+    }
+
+    pub fn request_address_mode(&mut self) -> Result<AddressMode, ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::RequestAddressMode,
+            data: Vec::<u8>::new(),
+        })?;
+        let payload = self.client.send_and_check_reply(&message)?;
+        match payload.data.first() {
+            Some(&flags) => Ok(AddressMode::from_u8(flags)),
+            None => Err(ClientError::CCTalkError(ErrorType::ParseError)),
+        }
+    }
+
+    pub fn request_data_storage_availability(&mut self) -> Result<DataStorage, ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::RequestDataStorageAvailability,
+            data: Vec::<u8>::new(),
+        })?;
+        let payload = self.client.send_and_check_reply(&message)?;
+        Ok(DataStorage::from_bytes(&payload.data)?)
+    }
+
+    pub fn read_data_block(&mut self, block: u8) -> Result<Data, ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::ReadDataBlock,
+            data: vec![block],
+        })?;
+        let payload = self.client.send_and_check_reply(&message)?;
+        Ok(payload.data)
+    }
+
+    /// Write a block, `data` must be exactly one write block long
+    pub fn write_data_block(&mut self, block: u8, data: &[u8]) -> Result<Payload, ClientError> {
+        let mut request = vec![block];
+        request.extend_from_slice(data);
+        let message = self.create_message(Payload {
+            header: HeaderType::WriteDataBlock,
+            data: request,
+        })?;
+        self.client.send_and_check_reply(&message)
+    }
+
+    /// Read the variable set, typed by equipment category
+    pub fn request_variable_set<V: VariableSet>(&mut self) -> Result<V, ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::RequestVariableSet,
+            data: Vec::<u8>::new(),
+        })?;
+        let payload = self.client.send_and_check_reply(&message)?;
+        Ok(V::from_bytes(&payload.data)?)
+    }
+
+    /// Write the modifiable variables, read-only ones are not sent
+    pub fn modify_variable_set<V: VariableSet>(
+        &mut self,
+        variables: &V,
+    ) -> Result<Payload, ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::ModifyVariableSet,
+            data: variables.to_modify_bytes(),
+        })?;
+        self.client.send_and_check_reply(&message)
+    }
+
+    /// Raw option flags, decode with e.g. `CoinAcceptorOptions::from_u8`
+    pub fn request_option_flags(&mut self) -> Result<u8, ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::RequestOptionFlags,
+            data: Vec::<u8>::new(),
+        })?;
+        let payload = self.client.send_and_check_reply(&message)?;
+        match payload.data.first() {
+            Some(&flags) => Ok(flags),
+            None => Err(ClientError::CCTalkError(ErrorType::ParseError)),
+        }
+    }
+
+    /// Base year of the creation and modification dates
+    pub fn request_base_year(&mut self) -> Result<u16, ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::RequestBaseYear,
+            data: Vec::<u8>::new(),
+        })?;
+        let payload = self.client.send_and_check_reply(&message)?;
+        Ok(parse_base_year(&payload.data)?)
+    }
+
+    /// Creation date, `base_year` is the reply of `request_base_year`
+    pub fn request_creation_date(&mut self, base_year: u16) -> Result<DateCode, ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::RequestCreationDate,
+            data: Vec::<u8>::new(),
+        })?;
+        let payload = self.client.send_and_check_reply(&message)?;
+        Ok(DateCode::from_bytes(&payload.data, base_year)?)
+    }
+
+    /// Last modification date, `base_year` is the reply of `request_base_year`
+    pub fn request_last_modification_date(
+        &mut self,
+        base_year: u16,
+    ) -> Result<DateCode, ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::RequestLastModificationDate,
+            data: Vec::<u8>::new(),
+        })?;
+        let payload = self.client.send_and_check_reply(&message)?;
+        Ok(DateCode::from_bytes(&payload.data, base_year)?)
+    }
+
+    pub fn request_real_time_clock(&mut self) -> Result<RealTimeClock, ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::RequestRealTimeClock,
+            data: Vec::<u8>::new(),
+        })?;
+        let payload = self.client.send_and_check_reply(&message)?;
+        Ok(RealTimeClock::from_bytes(&payload.data)?)
+    }
+
+    pub fn modify_real_time_clock(&mut self, rtc: RealTimeClock) -> Result<Payload, ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::ModifyRealTimeClock,
+            data: rtc.to_bytes().to_vec(),
+        })?;
+        self.client.send_and_check_reply(&message)
+    }
+
+    /// Non-zero if the firmware can be upgraded over ccTalk
+    pub fn request_firmware_upgrade_capability(&mut self) -> Result<u8, ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::RequestFirmwareUpgradeCapability,
+            data: Vec::<u8>::new(),
+        })?;
+        let payload = self.client.send_and_check_reply(&message)?;
+        match payload.data.first() {
+            Some(&capability) => Ok(capability),
+            None => Err(ClientError::CCTalkError(ErrorType::ParseError)),
+        }
+    }
+
+    pub fn begin_firmware_upgrade(&mut self) -> Result<Payload, ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::BeginFirmwareUpgrade,
+            data: Vec::<u8>::new(),
+        })?;
+        self.client.send_and_check_reply(&message)
+    }
+
+    pub fn upload_firmware(
+        &mut self,
+        block: u8,
+        line: u8,
+        data: &[u8],
+    ) -> Result<Payload, ClientError> {
+        let mut request = vec![block, line];
+        request.extend_from_slice(data);
+        let message = self.create_message(Payload {
+            header: HeaderType::UploadFirmware,
+            data: request,
+        })?;
+        self.client.send_and_check_reply(&message)
+    }
+
+    pub fn finish_firmware_upgrade(&mut self) -> Result<Payload, ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::FinishFirmwareUpgrade,
+            data: Vec::<u8>::new(),
+        })?;
+        self.client.send_and_check_reply(&message)
+    }
+
+    pub fn begin_bill_table_upgrade(&mut self) -> Result<Payload, ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::BeginBillTableUpgrade,
+            data: Vec::<u8>::new(),
+        })?;
+        self.client.send_and_check_reply(&message)
+    }
+
+    pub fn upload_bill_tables(
+        &mut self,
+        block: u8,
+        line: u8,
+        data: &[u8],
+    ) -> Result<Payload, ClientError> {
+        let mut request = vec![block, line];
+        request.extend_from_slice(data);
+        let message = self.create_message(Payload {
+            header: HeaderType::UploadBillTables,
+            data: request,
+        })?;
+        self.client.send_and_check_reply(&message)
+    }
+
+    pub fn finish_bill_table_upgrade(&mut self) -> Result<Payload, ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::FinishBillTableUpgrade,
+            data: Vec::<u8>::new(),
+        })?;
+        self.client.send_and_check_reply(&message)
+    }
+
+    /// Device specific 4 byte ROM checksum, LSB first
+    pub fn calculate_rom_checksum(&mut self) -> Result<u32, ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::CalculateROMChecksum,
+            data: Vec::<u8>::new(),
+        })?;
+        let payload = self.client.send_and_check_reply(&message)?;
+        match payload.data[..] {
+            [b0, b1, b2, b3] => Ok(u32::from_le_bytes([b0, b1, b2, b3])),
+            _ => Err(ClientError::CCTalkError(ErrorType::ParseError)),
+        }
+    }
+
+    pub fn request_encryption_support(&mut self) -> Result<EncryptionSupport, ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::RequestEncryptionSupport,
+            data: Vec::<u8>::new(),
+        })?;
+        let payload = self.client.send_and_check_reply(&message)?;
+        Ok(EncryptionSupport::from_bytes(&payload.data)?)
+    }
+
+    /// Encryption the device currently uses, e.g. its stored key after power-up
+    pub fn set_encryption(
+        &mut self,
+        encryption: Option<EncryptionLayer>,
+    ) -> Result<(), ClientError> {
+        self.client.set_encryption(encryption)
+    }
+
+    /// Switch to a new DES key, the request itself uses the current one
+    ///
+    /// Frames are then encrypted with `DesTestCipher`, so this only works
+    /// with the emulators of this crate.
+    pub fn switch_encryption_key(&mut self, key: DesKey) -> Result<(), ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::SwitchEncryptionKey,
+            data: key.to_vec(),
+        })?;
+        self.client.send_and_check_reply(&message)?;
+        self.set_encryption(Some(EncryptionLayer::des_test(key)))
+    }
+
+    /// Switch to a new BNV code, the request itself uses the current one
+    pub fn switch_encryption_code(
+        &mut self,
+        code: &BnvCode,
+        algorithm: BnvAlgorithm,
+    ) -> Result<(), ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::SwitchEncryptionCode,
+            data: code.to_bytes().to_vec(),
+        })?;
+        self.client.send_and_check_reply(&message)?;
+        self.set_encryption(Some(EncryptionLayer::bnv(code, algorithm)))
+    }
+
+    /// Keep the current code or key after a reset
+    pub fn store_encryption_code(&mut self) -> Result<Payload, ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::StoreEncryptionCode,
+            data: Vec::<u8>::new(),
+        })?;
+        self.client.send_and_check_reply(&message)
+    }
+
     pub fn read_coin_ids(&mut self) -> Result<(), ClientError> {
         for x in 1..10 {
             let message = self.create_message(Payload {
                 header: HeaderType::RequestCoinId,
                 data: vec![x],
-            });
+            })?;
             let payload = self.client.send_and_check_reply(&message);
 
             match payload {
@@ -152,12 +477,52 @@ impl CCTalkDevice {
         Ok(())
     }
 
+    pub fn request_coin_id(&mut self, channel: u8) -> Result<CoinId, ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::RequestCoinId,
+            data: vec![channel],
+        })?;
+        let payload = self.client.send_and_check_reply(&message)?;
+        Ok(CoinId::from_bytes(&payload.data)?)
+    }
+
+    pub fn modify_coin_id(&mut self, channel: u8, coin_id: CoinId) -> Result<Payload, ClientError> {
+        let mut data = vec![channel];
+        data.extend_from_slice(coin_id.as_bytes());
+        let message = self.create_message(Payload {
+            header: HeaderType::ModifyCoinId,
+            data,
+        })?;
+        self.client.send_and_check_reply(&message)
+    }
+
+    pub fn teach_mode_control(&mut self, channel: u8) -> Result<Payload, ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::TeachModeControl,
+            data: vec![channel],
+        })?;
+        self.client.send_and_check_reply(&message)
+    }
+
+    /// Returns the number of coins entered and the teach status
+    pub fn request_teach_status(&mut self, abort: bool) -> Result<(u8, TeachStatus), ClientError> {
+        let message = self.create_message(Payload {
+            header: HeaderType::RequestTeachStatus,
+            data: vec![abort as u8],
+        })?;
+        let payload = self.client.send_and_check_reply(&message)?;
+        if payload.data.len() < 2 {
+            return Err(ClientError::CCTalkError(ErrorType::ParseError));
+        }
+        Ok((payload.data[0], TeachStatus::from_u8(payload.data[1])))
+    }
+
     pub fn read_bill_ids(&mut self) -> Result<(), ClientError> {
         for x in 1..10 {
             let message = self.create_message(Payload {
                 header: HeaderType::RequestBillId,
                 data: vec![x],
-            });
+            })?;
             let payload = self.client.send_and_check_reply(&message);
 
             match payload {
@@ -174,7 +539,7 @@ impl CCTalkDevice {
         let message = self.create_message(Payload {
             header: HeaderType::RequestCountryScalingFactor,
             data: Vec::<u8>::new(),
-        });
+        })?;
         self.client.send_and_check_reply(&message)
     }
 }
@@ -0,0 +1,188 @@
+//! Pretty printing of request and reply pairs for bus logs
+
+use alloc::{format, string::String, string::ToString, vec, vec::Vec};
+
+use crate::protocol::*;
+
+/// Both messages as analyser lines, followed by the decoded fields indented
+pub fn dissect(request: &Message, reply: &Message) -> String {
+    let mut text = format!("{}\n{}", request, reply);
+    for field in fields(&request.payload, &reply.payload) {
+        text.push_str("\n    ");
+        text.push_str(&field);
+    }
+    text
+}
+
+/// Decoded fields of an exchange, empty for headers without special
+/// knowledge and replies that aren't ACKs
+pub fn fields(request: &Payload, reply: &Payload) -> Vec<String> {
+    if !matches!(reply.header, HeaderType::Reply) {
+        return Vec::new();
+    }
+    let data = &reply.data[..];
+    match request.header {
+        HeaderType::ModifyInhibitStatus => inhibit_mask(&request.data),
+        HeaderType::RequestInhibitStatus => inhibit_mask(data),
+        HeaderType::ModifyMasterInhibitStatus => master_inhibit(&request.data),
+        HeaderType::RequestMasterInhibitStatus => master_inhibit(data),
+        HeaderType::ReadBufferedCreditOrErrorCodes => event_buffer(data, |a, b| {
+            if a != 0 {
+                return format!("credit channel {}, sorter path {}", a, b);
+            }
+            match CoinAcceptorError::from_u8(b) {
+                CoinAcceptorError::NullEvent => "null event".to_string(),
+                error => format!("error {:?}", error),
+            }
+        }),
+        HeaderType::ReadBufferedBillEvents => {
+            event_buffer(data, |a, b| format!("{:?}", BillEvent::from_u8((a, b))))
+        }
+        HeaderType::RequestCoinId | HeaderType::RequestBillId => match request.data.first() {
+            Some(channel) => vec![format!("channel {}: {}", channel, ascii(data))],
+            None => Vec::new(),
+        },
+        HeaderType::RequestManufacturerId
+        | HeaderType::RequestEquipmentCategoryId
+        | HeaderType::RequestProductCode
+        | HeaderType::RequestSoftwareRevision
+        | HeaderType::RequestBuildCode => vec![ascii(data)],
+        HeaderType::RequestPollingPriority => match PollingPriority::from_bytes(data) {
+            Ok(priority) => match priority.interval() {
+                Some(interval) => vec![format!("polling interval {:?}", interval)],
+                None => vec![format!("polling {:?}", priority)],
+            },
+            Err(_) => Vec::new(),
+        },
+        HeaderType::RequestAddressMode => match data.first() {
+            Some(&flags) => vec![format!("{:?}", AddressMode::from_u8(flags))],
+            None => Vec::new(),
+        },
+        HeaderType::RequestSerialNumber => match SerialNumber::from_bytes(data) {
+            Ok(serial_number) => vec![format!("serial number {}", serial_number)],
+            Err(_) => Vec::new(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+fn ascii(data: &[u8]) -> String {
+    format!("{:?}", String::from_utf8_lossy(data))
+}
+
+/// Channel bits LSB first, a set bit enables the channel
+fn inhibit_mask(mask: &[u8]) -> Vec<String> {
+    let channels: Vec<String> = mask
+        .iter()
+        .enumerate()
+        .flat_map(|(i, byte)| {
+            (0..8)
+                .filter(move |bit| byte & (1 << bit) != 0)
+                .map(move |bit| i * 8 + bit + 1)
+        })
+        .map(|channel| channel.to_string())
+        .collect();
+    if channels.is_empty() {
+        vec!["enabled channels: none".to_string()]
+    } else {
+        vec![format!("enabled channels: {}", channels.join(" "))]
+    }
+}
+
+fn master_inhibit(data: &[u8]) -> Vec<String> {
+    match data.first() {
+        Some(status) if status & 1 == 1 => vec!["master inhibit off, accepting".to_string()],
+        Some(_) => vec!["master inhibit on".to_string()],
+        None => Vec::new(),
+    }
+}
+
+/// Event counter followed by 5 two byte results, newest first
+fn event_buffer(data: &[u8], result: impl Fn(u8, u8) -> String) -> Vec<String> {
+    let (counter, results) = match data.split_first() {
+        Some(split) => split,
+        None => return Vec::new(),
+    };
+    let mut fields = vec![format!("event counter {}", counter)];
+    for (i, pair) in results.chunks_exact(2).enumerate() {
+        fields.push(format!("result {}: {}", i + 1, result(pair[0], pair[1])));
+    }
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(destination: Address, source: Address, header: HeaderType, data: &[u8]) -> Message {
+        let payload = Payload {
+            header,
+            data: data.to_vec(),
+        };
+        Message::new(destination, source, payload, ChecksumType::SimpleChecksum).unwrap()
+    }
+
+    #[test]
+    fn test_display() {
+        let poll = message(2, 1, HeaderType::ReadBufferedCreditOrErrorCodes, &[]);
+        assert_eq!(
+            poll.to_string(),
+            "2 <- 1 [229 ReadBufferedCreditOrErrorCodes] len=0 sum=ok"
+        );
+        let unknown = message(1, 2, HeaderType::Unknown(100), &[0x0a, 0xff]);
+        assert_eq!(
+            unknown.to_string(),
+            "1 <- 2 [100 Unknown] len=2 0a ff sum=ok"
+        );
+
+        // CRC frames have no source
+        let mut crc = message(2, 1, HeaderType::SimplePoll, &[]);
+        crc.checksum_type = ChecksumType::CRCChecksum;
+        let mut raw = crc.encode();
+        let decoded = Message::decode(&mut raw.clone(), ChecksumMode::Auto).unwrap();
+        assert_eq!(decoded.to_string(), "2 <- ? [254 SimplePoll] len=0 crc=ok");
+
+        // Received frames show their own checksum status
+        let received = MessageRef::decode(&raw, ChecksumMode::Auto).unwrap();
+        assert_eq!(received.to_string(), "2 <- ? [254 SimplePoll] len=0 crc=ok");
+        raw[4] ^= 1;
+        let corrupted = MessageRef::unchecked(&raw, ChecksumType::CRCChecksum).unwrap();
+        assert_eq!(
+            corrupted.to_string(),
+            "2 <- ? [254 SimplePoll] len=0 crc=bad"
+        );
+    }
+
+    #[test]
+    fn test_dissect() {
+        let request = message(2, 1, HeaderType::ReadBufferedCreditOrErrorCodes, &[]);
+        let reply = message(1, 2, HeaderType::Reply, &[7, 3, 1, 0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            dissect(&request, &reply),
+            "2 <- 1 [229 ReadBufferedCreditOrErrorCodes] len=0 sum=ok\n\
+             1 <- 2 [0 Reply] len=11 07 03 01 00 01 00 00 00 00 00 00 sum=ok\n    \
+             event counter 7\n    \
+             result 1: credit channel 3, sorter path 1\n    \
+             result 2: error RejectCoin\n    \
+             result 3: null event\n    \
+             result 4: null event\n    \
+             result 5: null event"
+        );
+
+        let inhibit = message(2, 1, HeaderType::ModifyInhibitStatus, &[0b101, 0x80]);
+        let ack = message(1, 2, HeaderType::Reply, &[]);
+        assert_eq!(
+            fields(&inhibit.payload, &ack.payload),
+            ["enabled channels: 1 3 16"]
+        );
+        let nak = message(1, 2, HeaderType::Nak, &[]);
+        assert!(fields(&inhibit.payload, &nak.payload).is_empty());
+
+        let request = message(2, 1, HeaderType::RequestCoinId, &[4]);
+        let reply = message(1, 2, HeaderType::Reply, b"EU200A");
+        assert_eq!(
+            fields(&request.payload, &reply.payload),
+            [r#"channel 4: "EU200A""#]
+        );
+    }
+}
@@ -0,0 +1,312 @@
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::fmt;
+use des::cipher::generic_array::GenericArray;
+use des::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+
+use crate::protocol::*;
+
+pub type DesKey = [u8; 8];
+
+/// Cipher applied to frames by an `EncryptionLayer`
+///
+/// Frames are encrypted in place, the cipher text has the length of the
+/// plain text, whatever the length is.
+pub trait Cipher {
+    fn encrypt(&self, data: &mut [u8]);
+    fn decrypt(&self, data: &mut [u8]);
+}
+
+/// DES based test cipher, not the DES scheme of the ccTalk specification
+///
+/// Each whole 8 byte block is encrypted with DES on its own, the bytes
+/// after the last whole block are XOR-ed with the encrypted last cipher
+/// block, or with an encrypted zero block in frames shorter than a block.
+/// Real DES devices won't understand it, it only lets the host API and the
+/// emulators of this crate talk to each other encrypted.
+pub struct DesTestCipher(des::Des);
+
+impl DesTestCipher {
+    pub fn new(key: DesKey) -> DesTestCipher {
+        DesTestCipher(des::Des::new(GenericArray::from_slice(&key)))
+    }
+
+    fn residual_key(&self, data: &[u8], blocks: usize) -> [u8; 8] {
+        let mut key = [0u8; 8];
+        if blocks > 0 {
+            key.copy_from_slice(&data[blocks - 8..blocks]);
+        }
+        self.0.encrypt_block(GenericArray::from_mut_slice(&mut key));
+        key
+    }
+}
+
+impl Cipher for DesTestCipher {
+    fn encrypt(&self, data: &mut [u8]) {
+        let blocks = data.len() / 8 * 8;
+        for block in data[..blocks].chunks_mut(8) {
+            self.0.encrypt_block(GenericArray::from_mut_slice(block));
+        }
+        let key = self.residual_key(data, blocks);
+        for (byte, key) in data[blocks..].iter_mut().zip(&key) {
+            *byte ^= key;
+        }
+    }
+
+    fn decrypt(&self, data: &mut [u8]) {
+        let blocks = data.len() / 8 * 8;
+        let key = self.residual_key(data, blocks);
+        for (byte, key) in data[blocks..].iter_mut().zip(&key) {
+            *byte ^= key;
+        }
+        for block in data[..blocks].chunks_mut(8) {
+            self.0.decrypt_block(GenericArray::from_mut_slice(block));
+        }
+    }
+}
+
+/// Six digit code of the BNV scheme
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BnvCode([u8; 6]);
+
+impl BnvCode {
+    pub fn new(code: &str) -> Result<BnvCode, ErrorType> {
+        let mut digits = [0u8; 6];
+        if code.len() != digits.len() {
+            return Err(ErrorType::ParseError);
+        }
+        for (digit, c) in digits.iter_mut().zip(code.chars()) {
+            *digit = c.to_digit(10).ok_or(ErrorType::ParseError)? as u8;
+        }
+        Ok(BnvCode(digits))
+    }
+
+    /// Code sent by `SwitchEncryptionCode`, packed BCD with the first digit
+    /// in the high nibble of the first byte
+    pub fn from_bytes(raw: &[u8]) -> Result<BnvCode, ErrorType> {
+        if raw.len() != 3 {
+            return Err(ErrorType::ParseError);
+        }
+        let mut digits = [0u8; 6];
+        for (i, byte) in raw.iter().enumerate() {
+            digits[2 * i] = byte >> 4;
+            digits[2 * i + 1] = byte & 0x0f;
+        }
+        if digits.iter().any(|&digit| digit > 9) {
+            return Err(ErrorType::ParseError);
+        }
+        Ok(BnvCode(digits))
+    }
+
+    pub fn to_bytes(&self) -> [u8; 3] {
+        let d = self.0;
+        [d[0] << 4 | d[1], d[2] << 4 | d[3], d[4] << 4 | d[5]]
+    }
+
+    pub fn digits(&self) -> &[u8; 6] {
+        &self.0
+    }
+}
+
+impl fmt::Display for BnvCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for digit in self.0.iter() {
+            write!(f, "{}", digit)?;
+        }
+        Ok(())
+    }
+}
+
+/// Creates the cipher of a BNV code
+///
+/// The BNV algorithm is only distributed to ccTalk licensees, so it isn't
+/// part of this crate and has to be provided by the user.
+pub type BnvAlgorithm = fn(&BnvCode) -> Box<dyn Cipher + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncryptionKey {
+    Des(DesKey),
+    Bnv(BnvCode),
+}
+
+/// Reply of `RequestEncryptionSupport`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EncryptionSupport {
+    pub protocol_level: u8,
+    pub command_level: u8,
+    pub protocol_key_size: u8,
+    pub command_key_size: u8,
+    pub command_block_size: u8,
+    pub trusted_mode: u8,
+}
+
+impl EncryptionSupport {
+    /// Trailing bytes of newer revisions are ignored
+    pub fn from_bytes(raw: &[u8]) -> Result<EncryptionSupport, ErrorType> {
+        if raw.len() < 6 {
+            return Err(ErrorType::ParseError);
+        }
+        Ok(EncryptionSupport {
+            protocol_level: raw[0],
+            command_level: raw[1],
+            protocol_key_size: raw[2],
+            command_key_size: raw[3],
+            command_block_size: raw[4],
+            trusted_mode: raw[5],
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; 6] {
+        [
+            self.protocol_level,
+            self.command_level,
+            self.protocol_key_size,
+            self.command_key_size,
+            self.command_block_size,
+            self.trusted_mode,
+        ]
+    }
+}
+
+/// Encrypts frames after the destination address and length
+///
+/// The source, header, data and checksum bytes are encrypted in place, so
+/// encrypted frames are framed by their length byte like plain ones.
+///
+/// Only the frame layer is covered. The payload level commands
+/// `ReadEncryptedEvents`, `RequestEncryptedMonetaryId` and
+/// `RequestEncryptedHopperStatus` have no host methods and are NAK-ed by
+/// the emulators.
+#[derive(Clone)]
+pub struct EncryptionLayer {
+    cipher: Arc<dyn Cipher + Send + Sync>,
+}
+
+impl EncryptionLayer {
+    pub fn new(cipher: Box<dyn Cipher + Send + Sync>) -> EncryptionLayer {
+        EncryptionLayer {
+            cipher: Arc::from(cipher),
+        }
+    }
+
+    /// Layer with a `DesTestCipher`, not interoperable with real devices
+    pub fn des_test(key: DesKey) -> EncryptionLayer {
+        EncryptionLayer::new(Box::new(DesTestCipher::new(key)))
+    }
+
+    pub fn bnv(code: &BnvCode, algorithm: BnvAlgorithm) -> EncryptionLayer {
+        EncryptionLayer::new(algorithm(code))
+    }
+
+    pub fn encode(&self, msg: &Message) -> Result<Vec<u8>, ErrorType> {
+        let mut raw = vec![0u8; msg.encoded_len()];
+        msg.encode_into(&mut raw)?;
+        self.encrypt(&mut raw);
+        Ok(raw)
+    }
+
+    /// Like `Message::decode`, the frame is removed from `raw`
+    pub fn decode(&self, raw: &mut Vec<u8>, mode: ChecksumMode) -> Result<Message, ErrorType> {
+        let frame_length = match raw.get(1) {
+            Some(&data_length) if raw.len() >= data_length as usize + 5 => data_length as usize + 5,
+            _ => return Err(ErrorType::PartialMessage),
+        };
+        let mut frame: Vec<u8> = raw.drain(..frame_length).collect();
+        self.decrypt(&mut frame);
+        Message::decode(&mut frame, mode)
+    }
+
+    /// Encrypt a whole frame in place
+    pub fn encrypt(&self, frame: &mut [u8]) {
+        if let Some(data) = frame.get_mut(2..) {
+            self.cipher.encrypt(data);
+        }
+    }
+
+    /// Decrypt a whole frame in place
+    pub fn decrypt(&self, frame: &mut [u8]) {
+        if let Some(data) = frame.get_mut(2..) {
+            self.cipher.decrypt(data);
+        }
+    }
+}
+
+impl fmt::Debug for EncryptionLayer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EncryptionLayer").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bnv_code() {
+        let code = BnvCode::new("123456").unwrap();
+        assert_eq!(code.to_bytes(), [0x12, 0x34, 0x56]);
+        assert_eq!(BnvCode::from_bytes(&[0x12, 0x34, 0x56]).unwrap(), code);
+        assert_eq!(code.to_string(), "123456");
+        assert!(BnvCode::new("12345").is_err());
+        assert!(BnvCode::new("12345a").is_err());
+        assert!(BnvCode::from_bytes(&[0x1a, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_des_test_layer() {
+        let layer = EncryptionLayer::des_test([1, 2, 3, 4, 5, 6, 7, 8]);
+        let msg = Message::new(
+            2,
+            1,
+            Payload {
+                header: HeaderType::ModifyInhibitStatus,
+                data: vec![0xff, 0xff],
+            },
+            ChecksumType::SimpleChecksum,
+        )
+        .unwrap();
+
+        let mut raw = layer.encode(&msg).unwrap();
+        // Destination and length in clear, no padding
+        assert_eq!(raw.len(), 7);
+        assert_eq!(raw[..2], [2, 2]);
+        assert_ne!(raw[2..], msg.encode()[2..]);
+
+        raw.push(0xAA);
+        let mode = ChecksumType::SimpleChecksum.into();
+        assert_eq!(
+            layer.decode(&mut raw[..5].to_vec(), mode).unwrap_err(),
+            ErrorType::PartialMessage
+        );
+        let decoded = layer.decode(&mut raw, mode).unwrap();
+        assert_eq!(decoded.payload.data, vec![0xff, 0xff]);
+        assert_eq!(raw, vec![0xAA]);
+
+        let other = EncryptionLayer::des_test([8, 7, 6, 5, 4, 3, 2, 1]);
+        assert!(other
+            .decode(&mut layer.encode(&msg).unwrap(), mode)
+            .is_err());
+    }
+
+    #[test]
+    fn test_des_block() {
+        // Whole blocks are plain DES, FIPS 46 example vector
+        let cipher = DesTestCipher::new([0x13, 0x34, 0x57, 0x79, 0x9B, 0xBC, 0xDF, 0xF1]);
+        let mut data = [0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF];
+        cipher.encrypt(&mut data);
+        assert_eq!(data, [0x85, 0xE8, 0x13, 0x54, 0x0F, 0x0A, 0xB4, 0x05]);
+    }
+
+    #[test]
+    fn test_des_any_length() {
+        let cipher = DesTestCipher::new([1, 2, 3, 4, 5, 6, 7, 8]);
+        for length in [3, 8, 11, 16, 258] {
+            let plain: Vec<u8> = (0..length).map(|i| i as u8).collect();
+            let mut data = plain.clone();
+            cipher.encrypt(&mut data);
+            assert_eq!(data.len(), length);
+            assert_ne!(data, plain);
+            cipher.decrypt(&mut data);
+            assert_eq!(data, plain);
+        }
+    }
+}
@@ -0,0 +1,387 @@
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+
+use crate::client::*;
+use crate::encryption::EncryptionLayer;
+use crate::protocol::*;
+
+/// Misbehaviour applied to a reply
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Send the reply late, e.g. beyond the host timeout
+    Delay(Duration),
+    /// Don't send the reply at all
+    Drop,
+    /// Send the reply with an invalid checksum
+    CorruptChecksum,
+    /// Send only the first N bytes of the reply
+    Partial(usize),
+    /// Reply BUSY instead of the real reply
+    Busy,
+    /// Send the reply twice
+    Duplicate,
+}
+
+/// When a rule fires, counted over the replies matching its header
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trigger {
+    Always,
+    /// The next N matching replies
+    Times(usize),
+    /// Only the N-th matching reply, counting from 1
+    Nth(usize),
+    /// Each matching reply with the given probability (0.0..=1.0)
+    Probability(f64),
+}
+
+#[derive(Debug, Clone)]
+pub struct FaultRule {
+    /// Request header the rule applies to, `None` matches every request
+    pub header: Option<HeaderType>,
+    pub fault: Fault,
+    pub trigger: Trigger,
+    matched: usize,
+}
+
+impl FaultRule {
+    pub fn new(header: Option<HeaderType>, fault: Fault, trigger: Trigger) -> FaultRule {
+        FaultRule {
+            header,
+            fault,
+            trigger,
+            matched: 0,
+        }
+    }
+}
+
+/// Fault injecting wrapper around the client of an emulated device
+///
+/// Requests are passed through unchanged, replies are matched with the
+/// request they answer, and the first firing rule for that request
+/// header decides the fault. Rules are checked in the order they were added,
+/// a reply claimed by a rule is not counted by the rules after it.
+///
+/// Corrupt and partial frames are written with `CCTalkClient::send_raw`,
+/// encrypted first while encryption is on.
+pub struct FaultInjector {
+    client: Box<dyn CCTalkClient + 'static>,
+    // Copy of the layer of the client, raw frames bypass it
+    encryption: Option<EncryptionLayer>,
+    rules: Vec<FaultRule>,
+    // Last request header by destination, replies come from that address
+    requests: HashMap<Address, HeaderType>,
+    rng_state: u64,
+}
+
+impl FaultInjector {
+    pub fn new(client: Box<dyn CCTalkClient + 'static>) -> FaultInjector {
+        FaultInjector::with_seed(client, 0x853c_49e6_748f_ea9b)
+    }
+
+    /// Injector with a fixed seed for reproducible probabilistic rules
+    pub fn with_seed(client: Box<dyn CCTalkClient + 'static>, seed: u64) -> FaultInjector {
+        FaultInjector {
+            client,
+            encryption: None,
+            rules: Vec::new(),
+            requests: HashMap::new(),
+            // xorshift state must not be zero
+            rng_state: seed.max(1),
+        }
+    }
+
+    pub fn add_rule(&mut self, rule: FaultRule) {
+        self.rules.push(rule);
+    }
+
+    pub fn clear_rules(&mut self) {
+        self.rules.clear();
+    }
+
+    fn random(&mut self) -> f64 {
+        // xorshift64*
+        self.rng_state ^= self.rng_state >> 12;
+        self.rng_state ^= self.rng_state << 25;
+        self.rng_state ^= self.rng_state >> 27;
+        let value = self.rng_state.wrapping_mul(0x2545_f491_4f6c_dd1d);
+        (value >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn next_fault(&mut self, header: Option<HeaderType>) -> Option<Fault> {
+        for i in 0..self.rules.len() {
+            let matches = match (self.rules[i].header, header) {
+                (None, _) => true,
+                (Some(rule_header), Some(header)) => rule_header.to_u8() == header.to_u8(),
+                (Some(_), None) => false,
+            };
+            if !matches {
+                continue;
+            }
+
+            self.rules[i].matched += 1;
+            let matched = self.rules[i].matched;
+            let fire = match self.rules[i].trigger {
+                Trigger::Always => true,
+                Trigger::Times(n) => matched <= n,
+                Trigger::Nth(n) => matched == n,
+                Trigger::Probability(p) => self.random() < p,
+            };
+            if fire {
+                return Some(self.rules[i].fault);
+            }
+        }
+        None
+    }
+
+    /// Frame as the client would send it
+    fn encode(&self, msg: &Message) -> Result<Vec<u8>, ClientError> {
+        match &self.encryption {
+            Some(layer) => Ok(layer.encode(msg)?),
+            None => Ok(msg.encode()),
+        }
+    }
+}
+
+impl CCTalkClient for FaultInjector {
+    fn send_and_check_reply(&mut self, msg: &Message) -> Result<Payload, ClientError> {
+        self.client.send_and_check_reply(msg)
+    }
+
+    fn get_address(&self) -> Address {
+        self.client.get_address()
+    }
+
+    fn set_bill_event(&mut self, bill_event: BillEvent) {
+        self.client.set_bill_event(bill_event)
+    }
+
+    fn read_messages(&mut self) -> Result<Vec<Message>, ClientError> {
+        let messages = self.client.read_messages()?;
+        for message in messages.iter() {
+            self.requests
+                .insert(message.destination, message.payload.header);
+        }
+        Ok(messages)
+    }
+
+    fn send_message(&mut self, msg: &Message) -> Result<(), ClientError> {
+        let header = self.requests.remove(&msg.source_address());
+        let fault = match self.next_fault(header) {
+            Some(fault) => fault,
+            None => return self.client.send_message(msg),
+        };
+        log::debug!("Injecting {:?} into reply to {:?}", fault, header);
+
+        match fault {
+            Fault::Delay(delay) => {
+                thread::sleep(delay);
+                self.client.send_message(msg)
+            }
+            Fault::Drop => Ok(()),
+            Fault::CorruptChecksum => {
+                let mut raw = self.encode(msg)?;
+                // Last byte is part of the checksum in both checksum modes
+                let last = raw.len() - 1;
+                raw[last] ^= 0xFF;
+                self.client.send_raw(&raw)
+            }
+            Fault::Partial(len) => {
+                let mut raw = self.encode(msg)?;
+                raw.truncate(len.min(raw.len() - 1));
+                self.client.send_raw(&raw)
+            }
+            Fault::Busy => {
+                let busy = Message::new(
+                    msg.destination,
+                    msg.source_address(),
+                    Payload {
+                        header: HeaderType::Busy,
+                        data: vec![],
+                    },
+                    msg.checksum_type,
+                )?;
+                self.client.send_message(&busy)
+            }
+            Fault::Duplicate => {
+                self.client.send_message(msg)?;
+                self.client.send_message(msg)
+            }
+        }
+    }
+
+    fn send_raw(&mut self, data: &[u8]) -> Result<(), ClientError> {
+        self.client.send_raw(data)
+    }
+
+    fn comms_stats(&self) -> CommsStats {
+        self.client.comms_stats()
+    }
+
+    fn clear_comms_stats(&mut self) {
+        self.client.clear_comms_stats()
+    }
+
+    fn set_checksum_mode(&mut self, mode: ChecksumMode) {
+        self.client.set_checksum_mode(mode)
+    }
+
+    fn set_encryption(&mut self, encryption: Option<EncryptionLayer>) -> Result<(), ClientError> {
+        self.client.set_encryption(encryption.clone())?;
+        self.encryption = encryption;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::peripheral::wire::{wire, WireClient};
+
+    fn message(destination: Address, source: Address, header: HeaderType) -> Message {
+        Message::new(
+            destination,
+            source,
+            Payload {
+                header,
+                data: vec![],
+            },
+            ChecksumType::SimpleChecksum,
+        )
+        .unwrap()
+    }
+
+    /// Poll the injector with `header` and return what went on the wire
+    fn exchange(
+        faults: &mut FaultInjector,
+        host: &mut WireClient,
+        header: HeaderType,
+    ) -> Vec<Vec<u8>> {
+        host.send_message(&message(2, 1, header)).unwrap();
+        faults.read_messages().unwrap();
+        faults
+            .send_message(&message(1, 2, HeaderType::Reply))
+            .unwrap();
+        host.take_chunks()
+    }
+
+    #[test]
+    fn test_fault_rules() {
+        let (mut host, device) = wire(2);
+        let mut faults = FaultInjector::new(Box::new(device));
+        let ack = vec![1, 0, 2, 0, 253];
+
+        faults.add_rule(FaultRule::new(
+            Some(HeaderType::SimplePoll),
+            Fault::Drop,
+            Trigger::Times(2),
+        ));
+        faults.add_rule(FaultRule::new(
+            Some(HeaderType::SimplePoll),
+            Fault::Busy,
+            Trigger::Nth(2),
+        ));
+        faults.add_rule(FaultRule::new(
+            Some(HeaderType::RequestStatus),
+            Fault::CorruptChecksum,
+            Trigger::Always,
+        ));
+        faults.add_rule(FaultRule::new(
+            Some(HeaderType::PerformSelfcheck),
+            Fault::Partial(3),
+            Trigger::Always,
+        ));
+        faults.add_rule(FaultRule::new(
+            Some(HeaderType::ResetDevice),
+            Fault::Duplicate,
+            Trigger::Always,
+        ));
+
+        // Dropped twice, the busy rule only counts polls the drop rule let through
+        assert!(exchange(&mut faults, &mut host, HeaderType::SimplePoll).is_empty());
+        assert!(exchange(&mut faults, &mut host, HeaderType::SimplePoll).is_empty());
+        assert_eq!(
+            exchange(&mut faults, &mut host, HeaderType::SimplePoll),
+            vec![ack.clone()]
+        );
+        assert_eq!(
+            exchange(&mut faults, &mut host, HeaderType::SimplePoll),
+            vec![vec![1, 0, 2, 6, 247]]
+        );
+        assert_eq!(
+            exchange(&mut faults, &mut host, HeaderType::SimplePoll),
+            vec![ack.clone()]
+        );
+
+        let corrupt = exchange(&mut faults, &mut host, HeaderType::RequestStatus);
+        assert!(!Message::validate_checksum(&corrupt[0]));
+
+        assert_eq!(
+            exchange(&mut faults, &mut host, HeaderType::PerformSelfcheck),
+            vec![vec![1, 0, 2]]
+        );
+        assert_eq!(
+            exchange(&mut faults, &mut host, HeaderType::ResetDevice),
+            vec![ack.clone(), ack.clone()]
+        );
+
+        // No rule for this header
+        assert_eq!(
+            exchange(&mut faults, &mut host, HeaderType::RequestProductCode),
+            vec![ack]
+        );
+    }
+
+    #[test]
+    fn test_fault_probability() {
+        let (mut host, device) = wire(2);
+        let mut faults = FaultInjector::with_seed(Box::new(device), 42);
+        faults.add_rule(FaultRule::new(None, Fault::Drop, Trigger::Probability(0.5)));
+
+        let dropped = (0..1000)
+            .filter(|_| exchange(&mut faults, &mut host, HeaderType::SimplePoll).is_empty())
+            .count();
+        assert!(dropped > 400 && dropped < 600, "dropped {}", dropped);
+
+        faults.clear_rules();
+        faults.add_rule(FaultRule::new(None, Fault::Drop, Trigger::Probability(0.0)));
+        assert!(!exchange(&mut faults, &mut host, HeaderType::SimplePoll).is_empty());
+    }
+
+    #[test]
+    fn test_encrypted_faults() {
+        let (mut host, device) = wire(2);
+        let layer = EncryptionLayer::des_test([1, 2, 3, 4, 5, 6, 7, 8]);
+        host.set_encryption(Some(layer.clone())).unwrap();
+        let mut faults = FaultInjector::new(Box::new(device));
+        faults.set_encryption(Some(layer.clone())).unwrap();
+        faults.add_rule(FaultRule::new(
+            Some(HeaderType::RequestStatus),
+            Fault::CorruptChecksum,
+            Trigger::Always,
+        ));
+        faults.add_rule(FaultRule::new(
+            Some(HeaderType::PerformSelfcheck),
+            Fault::Partial(3),
+            Trigger::Always,
+        ));
+        let ack = layer.encode(&message(1, 2, HeaderType::Reply)).unwrap();
+        assert_ne!(ack, vec![1, 0, 2, 0, 253]);
+
+        // Corrupted after encryption, the host decrypts a bad checksum
+        let mut corrupt = exchange(&mut faults, &mut host, HeaderType::RequestStatus).remove(0);
+        assert_eq!(corrupt[..4], ack[..4]);
+        layer.decrypt(&mut corrupt);
+        assert_eq!(corrupt, vec![1, 0, 2, 0, 253 ^ 0xFF]);
+
+        assert_eq!(
+            exchange(&mut faults, &mut host, HeaderType::PerformSelfcheck),
+            vec![ack[..3].to_vec()]
+        );
+        assert_eq!(
+            exchange(&mut faults, &mut host, HeaderType::SimplePoll),
+            vec![ack]
+        );
+    }
+}
@@ -0,0 +1,487 @@
+use std::collections::BTreeMap;
+use std::convert;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use crate::client::ClientError;
+use crate::device::CCTalkDevice;
+use crate::protocol::*;
+
+/// Largest line, the upload request also carries the block and line number
+pub const MAX_LINE_SIZE: usize = 253;
+
+/// Lines in a block, the line number is a single byte
+const LINES_PER_BLOCK: usize = 256;
+
+#[derive(Debug)]
+pub enum UpgradeError {
+    IOError(io::Error),
+    ClientError(ClientError),
+    /// The device reports no firmware upgrade capability
+    NotSupported,
+    /// The image doesn't fit into 256 blocks of 256 lines
+    ImageTooLarge(usize),
+    ChecksumMismatch {
+        expected: u32,
+        actual: u32,
+    },
+}
+
+impl convert::From<io::Error> for UpgradeError {
+    fn from(e: io::Error) -> UpgradeError {
+        UpgradeError::IOError(e)
+    }
+}
+
+impl convert::From<ClientError> for UpgradeError {
+    fn from(e: ClientError) -> UpgradeError {
+        UpgradeError::ClientError(e)
+    }
+}
+
+/// What is being upgraded, selects the ccTalk headers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpgradeTarget {
+    /// Begin (139), upload (140) and finish (138) firmware upgrade
+    Firmware,
+    /// Begin (143), upload (144) and finish (142) bill table upgrade
+    BillTables,
+}
+
+/// Default ROM checksum, the wrapping 32-bit sum of all bytes
+///
+/// The algorithm behind `CalculateROMChecksum` is manufacturer specific,
+/// use `FirmwareUpgrade::set_expected_checksum` for other devices.
+pub fn rom_checksum(data: &[u8]) -> u32 {
+    data.iter()
+        .fold(0u32, |sum, &byte| sum.wrapping_add(byte as u32))
+}
+
+/// Firmware image, uploaded as is
+///
+/// Manufacturer file formats have to be unpacked by the caller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FirmwareImage {
+    data: Vec<u8>,
+}
+
+impl FirmwareImage {
+    pub fn new(data: Vec<u8>) -> FirmwareImage {
+        FirmwareImage { data }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<FirmwareImage, UpgradeError> {
+        Ok(FirmwareImage::new(fs::read(path)?))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Split into lines of at most `line_size` bytes
+    pub fn lines(&self, line_size: usize) -> Result<Vec<Line<'_>>, UpgradeError> {
+        let lines: Vec<&[u8]> = self.data.chunks(line_size).collect();
+        if lines.len() > 256 * LINES_PER_BLOCK {
+            return Err(UpgradeError::ImageTooLarge(self.data.len()));
+        }
+        Ok(lines
+            .into_iter()
+            .enumerate()
+            .map(|(i, data)| Line {
+                block: (i / LINES_PER_BLOCK) as u8,
+                line: (i % LINES_PER_BLOCK) as u8,
+                data,
+            })
+            .collect())
+    }
+}
+
+/// Part of an image sent with one upload request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Line<'a> {
+    pub block: u8,
+    pub line: u8,
+    pub data: &'a [u8],
+}
+
+/// Upload progress, passed to the progress callback after every line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub sent_bytes: usize,
+    pub total_bytes: usize,
+}
+
+/// Host side firmware or bill table upgrade
+///
+/// Checks the upgrade capability, uploads the image line by line,
+/// retrying failed lines, and verifies the firmware with
+/// `CalculateROMChecksum` once the device restarted.
+pub struct FirmwareUpgrade {
+    image: FirmwareImage,
+    target: UpgradeTarget,
+    line_size: usize,
+    retries: usize,
+    restart_delay: Duration,
+    expected_checksum: Option<u32>,
+    progress: Option<Box<dyn FnMut(Progress)>>,
+}
+
+impl FirmwareUpgrade {
+    pub fn new(image: FirmwareImage, target: UpgradeTarget) -> FirmwareUpgrade {
+        FirmwareUpgrade {
+            image,
+            target,
+            line_size: 128,
+            retries: 3,
+            restart_delay: Duration::from_secs(1),
+            expected_checksum: None,
+            progress: None,
+        }
+    }
+
+    /// Bytes per uploaded line, default is 128, at most `MAX_LINE_SIZE`
+    pub fn set_line_size(&mut self, line_size: usize) {
+        self.line_size = line_size.clamp(1, MAX_LINE_SIZE);
+    }
+
+    /// Additional attempts for every request, default is 3
+    ///
+    /// Finishing a bill table upgrade is not retried, a lost reply
+    /// can't be told apart from a failed upgrade.
+    pub fn set_retries(&mut self, retries: usize) {
+        self.retries = retries;
+    }
+
+    /// Time to wait for the device restart before verifying, default is 1s
+    pub fn set_restart_delay(&mut self, restart_delay: Duration) {
+        self.restart_delay = restart_delay;
+    }
+
+    /// ROM checksum of the new firmware, `rom_checksum` of the image by default
+    pub fn set_expected_checksum(&mut self, checksum: u32) {
+        self.expected_checksum = Some(checksum);
+    }
+
+    pub fn set_progress<F: FnMut(Progress) + 'static>(&mut self, progress: F) {
+        self.progress = Some(Box::new(progress));
+    }
+
+    /// Run the whole upgrade
+    pub fn run(&mut self, device: &mut CCTalkDevice) -> Result<(), UpgradeError> {
+        let lines = self.image.lines(self.line_size)?;
+
+        if self.retry(|| device.request_firmware_upgrade_capability())? == 0 {
+            return Err(UpgradeError::NotSupported);
+        }
+
+        let target = self.target;
+        self.retry(|| match target {
+            UpgradeTarget::Firmware => device.begin_firmware_upgrade(),
+            UpgradeTarget::BillTables => device.begin_bill_table_upgrade(),
+        })?;
+
+        let total_bytes = self.image.as_bytes().len();
+        let mut sent_bytes = 0;
+        for Line { block, line, data } in lines {
+            self.retry(|| match target {
+                UpgradeTarget::Firmware => device.upload_firmware(block, line, data),
+                UpgradeTarget::BillTables => device.upload_bill_tables(block, line, data),
+            })?;
+            sent_bytes += data.len();
+            if let Some(progress) = self.progress.as_mut() {
+                progress(Progress {
+                    sent_bytes,
+                    total_bytes,
+                });
+            }
+        }
+
+        // Finishing again after a lost reply is NAK-ed, only firmware can
+        // be verified afterwards, so bill tables are not retried
+        let retries = match target {
+            UpgradeTarget::Firmware => self.retries,
+            UpgradeTarget::BillTables => 0,
+        };
+        let (finished, resent) = self.attempt(retries, || match target {
+            UpgradeTarget::Firmware => device.finish_firmware_upgrade(),
+            UpgradeTarget::BillTables => device.finish_bill_table_upgrade(),
+        });
+        match finished {
+            Ok(_) => {}
+            // The ROM checksum tells if a previous attempt succeeded
+            Err(ClientError::CCTalkError(ErrorType::Nak)) if resent => {
+                log::warn!("Resent finish rejected, verifying the firmware");
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        // Bill tables are not part of the ROM checksum
+        if target == UpgradeTarget::Firmware {
+            if !self.restart_delay.is_zero() {
+                thread::sleep(self.restart_delay);
+            }
+            let expected = self
+                .expected_checksum
+                .unwrap_or_else(|| rom_checksum(self.image.as_bytes()));
+            let actual = self.retry(|| device.calculate_rom_checksum())?;
+            if actual != expected {
+                return Err(UpgradeError::ChecksumMismatch { expected, actual });
+            }
+        }
+        Ok(())
+    }
+
+    fn retry<T, F>(&self, request: F) -> Result<T, ClientError>
+    where
+        F: FnMut() -> Result<T, ClientError>,
+    {
+        self.attempt(self.retries, request).0
+    }
+
+    /// Result of the last attempt and whether the request was resent
+    fn attempt<T, F>(&self, retries: usize, mut request: F) -> (Result<T, ClientError>, bool)
+    where
+        F: FnMut() -> Result<T, ClientError>,
+    {
+        let mut attempt = 0;
+        loop {
+            match request() {
+                // Rejected requests are not worth repeating
+                Err(ClientError::CCTalkError(ErrorType::Nak)) => {
+                    return (Err(ClientError::CCTalkError(ErrorType::Nak)), attempt > 0)
+                }
+                Err(e) if attempt < retries => {
+                    log::warn!("Upgrade request failed, retrying: {:?}", e);
+                    attempt += 1;
+                }
+                result => return (result, attempt > 0),
+            }
+        }
+    }
+}
+
+/// Upload in progress, lines by block and line number
+type Upload = (UpgradeTarget, BTreeMap<(u8, u8), Vec<u8>>);
+
+/// Emulated firmware and bill table memory
+///
+/// Uploaded lines are collected until the upgrade is finished, then
+/// they replace the memory if no line is missing.
+#[derive(Debug, Default)]
+pub struct FirmwareMemory {
+    firmware: Vec<u8>,
+    bill_tables: Vec<u8>,
+    upload: Option<Upload>,
+}
+
+impl FirmwareMemory {
+    pub fn new(firmware: Vec<u8>) -> FirmwareMemory {
+        FirmwareMemory {
+            firmware,
+            ..FirmwareMemory::default()
+        }
+    }
+
+    pub fn firmware(&self) -> &[u8] {
+        &self.firmware
+    }
+
+    pub fn bill_tables(&self) -> &[u8] {
+        &self.bill_tables
+    }
+
+    pub fn rom_checksum(&self) -> u32 {
+        rom_checksum(&self.firmware)
+    }
+
+    /// Start collecting lines, an unfinished upload is discarded
+    pub fn begin(&mut self, target: UpgradeTarget) {
+        self.upload = Some((target, BTreeMap::new()));
+    }
+
+    /// Store a line, `data` is the request data with block and line number
+    pub fn upload(&mut self, target: UpgradeTarget, data: &[u8]) -> bool {
+        match (self.upload.as_mut(), data) {
+            (Some((upload_target, lines)), [block, line, line_data @ ..])
+                if *upload_target == target && !line_data.is_empty() =>
+            {
+                lines.insert((*block, *line), line_data.to_vec());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Replace the memory with the uploaded lines
+    pub fn finish(&mut self, target: UpgradeTarget) -> bool {
+        let lines = match self.upload.take() {
+            Some((upload_target, lines)) if upload_target == target => lines,
+            upload => {
+                self.upload = upload;
+                return false;
+            }
+        };
+
+        let mut image = Vec::new();
+        for (i, ((block, line), data)) in lines.into_iter().enumerate() {
+            if block as usize * LINES_PER_BLOCK + line as usize != i {
+                log::warn!("Upload is missing line {} of block {}", line, block);
+                return false;
+            }
+            image.extend_from_slice(&data);
+        }
+        match target {
+            UpgradeTarget::Firmware => self.firmware = image,
+            UpgradeTarget::BillTables => self.bill_tables = image,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::CCTalkClient;
+    use crate::device::{CoinAcceptor, CoinTable};
+    use crate::peripheral::{test_core_info, LoopbackClient};
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Fails every n-th request without a reply
+    struct FlakyClient {
+        client: LoopbackClient,
+        requests: usize,
+        fail_every: usize,
+        // The request reaches the device, only the reply is lost
+        drop_replies: bool,
+    }
+
+    impl CCTalkClient for FlakyClient {
+        fn send_and_check_reply(&mut self, msg: &Message) -> Result<Payload, ClientError> {
+            self.requests += 1;
+            if self.requests % self.fail_every == 0 {
+                if self.drop_replies {
+                    let _ = self.client.send_and_check_reply(msg);
+                }
+                return Err(ClientError::CCTalkError(ErrorType::NoResponse));
+            }
+            self.client.send_and_check_reply(msg)
+        }
+        fn get_address(&self) -> Address {
+            1
+        }
+        fn set_bill_event(&mut self, _bill_event: BillEvent) {}
+        fn read_messages(&mut self) -> Result<Vec<Message>, ClientError> {
+            Ok(Vec::new())
+        }
+        fn send_message(&mut self, _msg: &Message) -> Result<(), ClientError> {
+            Ok(())
+        }
+    }
+
+    fn acceptor() -> Rc<RefCell<CoinAcceptor>> {
+        let acceptor =
+            CoinAcceptor::new(2, Box::<CoinTable>::default(), Box::new(test_core_info()));
+        Rc::new(RefCell::new(acceptor))
+    }
+
+    #[test]
+    fn test_lines() {
+        let image = FirmwareImage::new(vec![7; 600]);
+        let lines = image.lines(2).unwrap();
+        assert_eq!(lines.len(), 300);
+        assert_eq!(lines[255].block, 0);
+        assert_eq!((lines[256].block, lines[256].line), (1, 0));
+        assert_eq!((lines[299].block, lines[299].line), (1, 43));
+        assert_eq!(lines[299].data, &[7, 7]);
+
+        let image = FirmwareImage::new(vec![0; 256 * 256 + 1]);
+        assert!(matches!(
+            image.lines(1),
+            Err(UpgradeError::ImageTooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn test_firmware_upgrade() {
+        let acceptor = acceptor();
+        let client = FlakyClient {
+            client: LoopbackClient::new(Box::new(acceptor.clone())),
+            requests: 0,
+            fail_every: 4,
+            drop_replies: false,
+        };
+        let mut device =
+            CCTalkDevice::new(Box::new(client), 2, ChecksumType::SimpleChecksum).unwrap();
+
+        let image: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+        let mut upgrade =
+            FirmwareUpgrade::new(FirmwareImage::new(image.clone()), UpgradeTarget::Firmware);
+        upgrade.set_line_size(100);
+        upgrade.set_restart_delay(Duration::from_millis(0));
+        let progress = Rc::new(RefCell::new(Vec::new()));
+        let seen = progress.clone();
+        upgrade.set_progress(move |p| seen.borrow_mut().push(p.sent_bytes));
+
+        upgrade.run(&mut device).unwrap();
+        assert_eq!(acceptor.borrow().firmware(), &image[..]);
+        assert_eq!(progress.borrow().len(), 10);
+        assert_eq!(progress.borrow()[9], 1000);
+
+        // Verification fails for a different checksum
+        upgrade.set_expected_checksum(1);
+        assert!(matches!(
+            upgrade.run(&mut device),
+            Err(UpgradeError::ChecksumMismatch { expected: 1, .. })
+        ));
+
+        // Bill tables are not supported by coin acceptors
+        let mut upgrade =
+            FirmwareUpgrade::new(FirmwareImage::new(image), UpgradeTarget::BillTables);
+        assert!(matches!(
+            upgrade.run(&mut device),
+            Err(UpgradeError::ClientError(ClientError::CCTalkError(
+                ErrorType::Nak
+            )))
+        ));
+    }
+
+    #[test]
+    fn test_lost_finish_reply() {
+        let acceptor = acceptor();
+        // Capability, begin and 10 lines, the 13th request is the finish
+        let client = FlakyClient {
+            client: LoopbackClient::new(Box::new(acceptor.clone())),
+            requests: 0,
+            fail_every: 13,
+            drop_replies: true,
+        };
+        let mut device =
+            CCTalkDevice::new(Box::new(client), 2, ChecksumType::SimpleChecksum).unwrap();
+
+        let image: Vec<u8> = (0..1000u32).map(|i| (i * 3) as u8).collect();
+        let mut upgrade =
+            FirmwareUpgrade::new(FirmwareImage::new(image.clone()), UpgradeTarget::Firmware);
+        upgrade.set_line_size(100);
+        upgrade.set_restart_delay(Duration::from_millis(0));
+
+        // The resent finish is NAK-ed, the checksum verifies the upgrade
+        upgrade.run(&mut device).unwrap();
+        assert_eq!(acceptor.borrow().firmware(), &image[..]);
+    }
+
+    #[test]
+    fn test_missing_line() {
+        let mut memory = FirmwareMemory::new(vec![1, 2, 3]);
+        memory.begin(UpgradeTarget::Firmware);
+        assert!(memory.upload(UpgradeTarget::Firmware, &[0, 0, 4, 5]));
+        assert!(memory.upload(UpgradeTarget::Firmware, &[0, 2, 6]));
+        assert!(!memory.upload(UpgradeTarget::BillTables, &[0, 1, 6]));
+        assert!(!memory.finish(UpgradeTarget::Firmware));
+        assert_eq!(memory.firmware(), &[1, 2, 3]);
+    }
+}
@@ -1,5 +1,31 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod bus;
+#[cfg(feature = "std")]
 pub mod client;
+#[cfg(feature = "serde")]
+pub mod config;
+#[cfg(feature = "std")]
 pub mod device;
+#[cfg(feature = "alloc")]
+pub mod dissector;
+#[cfg(feature = "encryption")]
+pub mod encryption;
+#[cfg(feature = "std")]
+pub mod fault;
+#[cfg(feature = "std")]
+pub mod firmware;
+#[cfg(feature = "std")]
+pub mod peripheral;
 pub mod protocol;
+#[cfg(feature = "std")]
+pub mod scenario;
+#[cfg(feature = "std")]
+pub mod storage;
 // Device implementations are re-exported as devices::*
+#[cfg(feature = "std")]
 mod coinacceptor;
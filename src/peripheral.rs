@@ -0,0 +1,585 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::client::{CCTalkClient, ClientError, CommsStats};
+use crate::protocol::*;
+
+/// ccTalk release, major and minor revision implemented by default
+pub const DEFAULT_COMMS_REVISION: [u8; 3] = [1, 4, 4];
+
+#[cfg(feature = "serde")]
+fn default_comms_revision() -> [u8; 3] {
+    DEFAULT_COMMS_REVISION
+}
+
+#[cfg(feature = "serde")]
+fn default_base_year() -> u16 {
+    DEFAULT_BASE_YEAR
+}
+
+#[cfg(feature = "serde")]
+fn default_polling_priority() -> PollingPriority {
+    DEFAULT_POLLING_PRIORITY
+}
+
+/// CCTalk Core and Core Plus Information fields
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CoreInfo {
+    // Core fields
+    pub manufacturer: String,
+    // equipment_category_id is supplied with main device information
+    pub product_code: String,
+    pub build_code: String,
+    // Core Plus fields
+    pub serial_number: SerialNumber,
+    pub software_revision: String,
+    /// Release, major and minor ccTalk revision
+    #[cfg_attr(feature = "serde", serde(default = "default_comms_revision"))]
+    pub comms_revision: [u8; 3],
+    /// Base year of the creation and modification dates
+    #[cfg_attr(feature = "serde", serde(default = "default_base_year"))]
+    pub base_year: u16,
+    /// Product creation date, NAK-ed if not set
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub creation_date: Option<DateCode>,
+    /// Last firmware modification date, NAK-ed if not set
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub modification_date: Option<DateCode>,
+    #[cfg_attr(feature = "serde", serde(default = "default_polling_priority"))]
+    pub polling_priority: PollingPriority,
+    /// NAK-ed if not set
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub address_mode: Option<AddressMode>,
+}
+
+impl CoreInfo {
+    /// Core info with the optional Core Plus fields left at their defaults
+    pub fn new(
+        manufacturer: &str,
+        product_code: &str,
+        build_code: &str,
+        serial_number: SerialNumber,
+        software_revision: &str,
+    ) -> CoreInfo {
+        CoreInfo {
+            manufacturer: manufacturer.to_string(),
+            product_code: product_code.to_string(),
+            build_code: build_code.to_string(),
+            serial_number,
+            software_revision: software_revision.to_string(),
+            comms_revision: DEFAULT_COMMS_REVISION,
+            base_year: DEFAULT_BASE_YEAR,
+            creation_date: None,
+            modification_date: None,
+            polling_priority: DEFAULT_POLLING_PRIORITY,
+            address_mode: None,
+        }
+    }
+}
+
+impl Default for CoreInfo {
+    fn default() -> Self {
+        CoreInfo::new("", "", "", SerialNumber::default(), "")
+    }
+}
+
+/// Core info shared by the emulator tests
+#[cfg(test)]
+pub(crate) fn test_core_info() -> CoreInfo {
+    CoreInfo::new(
+        "CPS",
+        "Colibri",
+        "DE0",
+        SerialNumber::new(1061013).unwrap(),
+        "412-005",
+    )
+}
+
+/// Response of an emulated peripheral to a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    Ack,
+    Reply(Data),
+    Nak,
+    Busy,
+    /// Nothing is sent, the host will time out
+    NoReply,
+}
+
+impl Response {
+    /// Reply payload to be sent to the host, if any
+    pub fn into_payload(self) -> Option<Payload> {
+        let (header, data) = match self {
+            Response::Ack => (HeaderType::Reply, vec![]),
+            Response::Reply(data) => (HeaderType::Reply, data),
+            Response::Nak => (HeaderType::Nak, vec![]),
+            Response::Busy => (HeaderType::Busy, vec![]),
+            Response::NoReply => return None,
+        };
+        Some(Payload { header, data })
+    }
+}
+
+/// Device specific request handler
+pub type Handler<P> = fn(&mut P, &Payload) -> Response;
+
+/// Handlers registered by a device type, keyed by header
+pub struct Handlers<P> {
+    handlers: HashMap<u8, Handler<P>>,
+}
+
+impl<P> Default for Handlers<P> {
+    fn default() -> Self {
+        Handlers {
+            handlers: HashMap::new(),
+        }
+    }
+}
+
+impl<P> Handlers<P> {
+    pub fn new() -> Self {
+        Handlers::default()
+    }
+
+    /// Register a handler, replacing the previous handler or core response
+    pub fn register(&mut self, header: HeaderType, handler: Handler<P>) {
+        self.handlers.insert(header.to_u8(), handler);
+    }
+
+    pub fn get(&self, header: HeaderType) -> Option<Handler<P>> {
+        self.handlers.get(&header.to_u8()).copied()
+    }
+}
+
+/// Emulated ccTalk peripheral
+///
+/// Core and Core Plus headers are answered by `dispatch` from `core_info`,
+/// device types only need to register handlers for their own headers.
+pub trait Peripheral {
+    fn core_info(&self) -> &CoreInfo;
+
+    /// Equipment category id, e.g. "Coin Acceptor"
+    fn equipment_category(&self) -> &str;
+
+    fn handlers(&self) -> &Handlers<Self>
+    where
+        Self: Sized;
+
+    /// Answer a request before the handlers see it, e.g. while restarting
+    fn intercept(&mut self, _request: &Payload) -> Option<Response> {
+        None
+    }
+
+    /// Receive error counters of the transport the device is attached to
+    fn comms_stats(&self) -> CommsStats {
+        CommsStats::default()
+    }
+
+    fn clear_comms_stats(&mut self) {}
+
+    /// Current time of the real time clock, `None` if there is none
+    fn real_time_clock(&self) -> Option<RealTimeClock> {
+        None
+    }
+
+    /// Set the real time clock, returns false if there is none
+    fn set_real_time_clock(&mut self, _rtc: RealTimeClock) -> bool {
+        false
+    }
+}
+
+/// Date code reply, NAK if the date is missing or before the base year
+fn date_reply(date: Option<DateCode>, base_year: u16) -> Response {
+    match date.map(|date| date.to_bytes(base_year)) {
+        Some(Ok(raw)) => Response::Reply(raw.to_vec()),
+        Some(Err(_)) => {
+            log::warn!(
+                "Date {:?} can't be encoded with base year {}",
+                date,
+                base_year
+            );
+            Response::Nak
+        }
+        None => Response::Nak,
+    }
+}
+
+/// Answer a request with the registered handler or the core response
+///
+/// Unsupported headers are answered with NAK.
+pub fn dispatch<P: Peripheral>(device: &mut P, request: &Payload) -> Response {
+    if let Some(response) = device.intercept(request) {
+        return response;
+    }
+    if let Some(handler) = device.handlers().get(request.header) {
+        return handler(device, request);
+    }
+
+    let core_info = device.core_info();
+    match request.header {
+        HeaderType::SimplePoll => Response::Ack,
+        HeaderType::RequestManufacturerId => {
+            Response::Reply(core_info.manufacturer.as_bytes().to_vec())
+        }
+        HeaderType::RequestEquipmentCategoryId => {
+            Response::Reply(device.equipment_category().as_bytes().to_vec())
+        }
+        HeaderType::RequestProductCode => {
+            Response::Reply(core_info.product_code.as_bytes().to_vec())
+        }
+        HeaderType::RequestBuildCode => Response::Reply(core_info.build_code.as_bytes().to_vec()),
+        HeaderType::RequestSerialNumber => {
+            Response::Reply(core_info.serial_number.to_bytes().to_vec())
+        }
+        HeaderType::RequestSoftwareRevision => {
+            Response::Reply(core_info.software_revision.as_bytes().to_vec())
+        }
+        HeaderType::RequestCommsRevision => Response::Reply(core_info.comms_revision.to_vec()),
+        HeaderType::RequestPollingPriority => {
+            Response::Reply(core_info.polling_priority.to_bytes().to_vec())
+        }
+        HeaderType::RequestAddressMode => match core_info.address_mode {
+            Some(address_mode) => Response::Reply(vec![address_mode.to_u8()]),
+            None => Response::Nak,
+        },
+        HeaderType::RequestCommsStatusVariables => {
+            Response::Reply(device.comms_stats().to_bytes().to_vec())
+        }
+        HeaderType::ClearCommsStatusVariables => {
+            device.clear_comms_stats();
+            Response::Ack
+        }
+        HeaderType::RequestBaseYear => {
+            Response::Reply(format!("{:04}", core_info.base_year).into_bytes())
+        }
+        HeaderType::RequestCreationDate => date_reply(core_info.creation_date, core_info.base_year),
+        HeaderType::RequestLastModificationDate => {
+            date_reply(core_info.modification_date, core_info.base_year)
+        }
+        HeaderType::RequestRealTimeClock => match device.real_time_clock() {
+            Some(rtc) => Response::Reply(rtc.to_bytes().to_vec()),
+            None => Response::Nak,
+        },
+        HeaderType::ModifyRealTimeClock => match RealTimeClock::from_bytes(&request.data) {
+            Ok(rtc) if device.set_real_time_clock(rtc) => Response::Ack,
+            _ => Response::Nak,
+        },
+        _ => {
+            log::warn!("Received unsupported packet: {:?}", request);
+            Response::Nak
+        }
+    }
+}
+
+/// Object safe interface of an emulated peripheral
+///
+/// Implemented for every `Peripheral`, and for shared handles to them,
+/// so the device can still be driven while a `BusEmulator` owns it.
+pub trait Respond {
+    fn respond(&mut self, request: &Payload) -> Response;
+}
+
+impl<P: Peripheral> Respond for P {
+    fn respond(&mut self, request: &Payload) -> Response {
+        dispatch(self, request)
+    }
+}
+
+impl<R: Respond> Respond for Rc<RefCell<R>> {
+    fn respond(&mut self, request: &Payload) -> Response {
+        self.borrow_mut().respond(request)
+    }
+}
+
+/// Host side client answered directly by an emulated peripheral
+///
+/// Lets a `CCTalkDevice` talk to an emulator without a serial port.
+pub struct LoopbackClient {
+    peripheral: Box<dyn Respond>,
+}
+
+impl LoopbackClient {
+    pub fn new(peripheral: Box<dyn Respond>) -> LoopbackClient {
+        LoopbackClient { peripheral }
+    }
+}
+
+impl CCTalkClient for LoopbackClient {
+    fn send_and_check_reply(&mut self, msg: &Message) -> Result<Payload, ClientError> {
+        match self.peripheral.respond(&msg.payload) {
+            Response::Ack => Ok(Payload {
+                header: HeaderType::Reply,
+                data: vec![],
+            }),
+            Response::Reply(data) => Ok(Payload {
+                header: HeaderType::Reply,
+                data,
+            }),
+            Response::Nak => Err(ClientError::CCTalkError(ErrorType::Nak)),
+            Response::Busy => Err(ClientError::CCTalkError(ErrorType::Busy)),
+            Response::NoReply => Err(ClientError::CCTalkError(ErrorType::NoResponse)),
+        }
+    }
+
+    fn get_address(&self) -> Address {
+        1
+    }
+
+    fn set_bill_event(&mut self, _bill_event: BillEvent) {}
+
+    fn read_messages(&mut self) -> Result<Vec<Message>, ClientError> {
+        Ok(Vec::new())
+    }
+
+    fn send_message(&mut self, _msg: &Message) -> Result<(), ClientError> {
+        Ok(())
+    }
+}
+
+/// In-memory serial line between a host and a device end, for tests
+#[cfg(test)]
+pub(crate) mod wire {
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    use crate::client::{CCTalkClient, ClientError, CommsStats};
+    use crate::encryption::EncryptionLayer;
+    use crate::protocol::*;
+
+    type Line = Rc<RefCell<VecDeque<Vec<u8>>>>;
+    type Peer = Box<dyn FnMut() -> Result<(), ClientError>>;
+
+    /// One end of the line, every send arrives at the other end as one chunk
+    pub(crate) struct WireClient {
+        rx: Line,
+        tx: Line,
+        address: Address,
+        framer: Framer,
+        // Lets the other end reply while the host waits for it
+        peer: Option<Peer>,
+    }
+
+    /// Host end at address 1 and device end at `address`
+    pub(crate) fn wire(address: Address) -> (WireClient, WireClient) {
+        let (a, b) = (Line::default(), Line::default());
+        let end = |rx: &Line, tx: &Line, address| WireClient {
+            rx: rx.clone(),
+            tx: tx.clone(),
+            address,
+            framer: Framer::new(520, ChecksumMode::Expect(ChecksumType::SimpleChecksum)),
+            peer: None,
+        };
+        (end(&a, &b, 1), end(&b, &a, address))
+    }
+
+    impl WireClient {
+        /// Run `peer` after each request of `send_and_check_reply`
+        pub(crate) fn set_peer(&mut self, peer: impl FnMut() -> Result<(), ClientError> + 'static) {
+            self.peer = Some(Box::new(peer));
+        }
+
+        /// Received chunks as they were sent, without decoding them
+        pub(crate) fn take_chunks(&mut self) -> Vec<Vec<u8>> {
+            self.rx.borrow_mut().drain(..).collect()
+        }
+    }
+
+    impl CCTalkClient for WireClient {
+        fn send_and_check_reply(&mut self, msg: &Message) -> Result<Payload, ClientError> {
+            self.send_message(msg)?;
+            if let Some(peer) = self.peer.as_mut() {
+                peer()?;
+            }
+            match self.read_messages()?.pop() {
+                Some(reply) => match reply.payload.header {
+                    HeaderType::Reply => Ok(reply.payload),
+                    HeaderType::Nak => Err(ClientError::CCTalkError(ErrorType::Nak)),
+                    _ => Err(ClientError::CCTalkError(ErrorType::NotAReply)),
+                },
+                None => Err(ClientError::CCTalkError(ErrorType::NoResponse)),
+            }
+        }
+
+        fn get_address(&self) -> Address {
+            self.address
+        }
+
+        fn set_bill_event(&mut self, _bill_event: BillEvent) {}
+
+        fn comms_stats(&self) -> CommsStats {
+            let framer = self.framer.stats();
+            CommsStats {
+                rx_timeouts: framer.incomplete_frames,
+                rx_bytes_ignored: framer.overflowed_bytes + framer.dropped_bytes,
+                rx_checksum_errors: framer.checksum_errors,
+            }
+        }
+
+        fn clear_comms_stats(&mut self) {
+            self.framer.clear_stats();
+        }
+
+        fn set_checksum_mode(&mut self, mode: ChecksumMode) {
+            self.framer.set_checksum_mode(mode);
+        }
+
+        fn read_messages(&mut self) -> Result<Vec<Message>, ClientError> {
+            for chunk in self.take_chunks() {
+                self.framer.push(&chunk);
+            }
+            // Everything sent so far has arrived
+            self.framer.gap();
+            let mut messages = vec![];
+            while let Some(message) = self.framer.next_message() {
+                messages.push(message);
+            }
+            Ok(messages)
+        }
+
+        fn send_message(&mut self, msg: &Message) -> Result<(), ClientError> {
+            let raw = match self.framer.encryption() {
+                Some(layer) => layer.encode(msg)?,
+                None => msg.encode(),
+            };
+            self.send_raw(&raw)
+        }
+
+        fn send_raw(&mut self, data: &[u8]) -> Result<(), ClientError> {
+            self.tx.borrow_mut().push_back(data.to_vec());
+            Ok(())
+        }
+
+        fn set_encryption(
+            &mut self,
+            encryption: Option<EncryptionLayer>,
+        ) -> Result<(), ClientError> {
+            self.framer.set_encryption(encryption);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestPeripheral {
+        core_info: CoreInfo,
+        handlers: Handlers<TestPeripheral>,
+        polls: u8,
+    }
+
+    impl Peripheral for TestPeripheral {
+        fn core_info(&self) -> &CoreInfo {
+            &self.core_info
+        }
+
+        fn equipment_category(&self) -> &str {
+            "Payout"
+        }
+
+        fn handlers(&self) -> &Handlers<Self> {
+            &self.handlers
+        }
+    }
+
+    fn request(header: HeaderType) -> Payload {
+        Payload {
+            header,
+            data: vec![],
+        }
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let mut handlers = Handlers::new();
+        handlers.register(HeaderType::SimplePoll, |device: &mut TestPeripheral, _| {
+            device.polls += 1;
+            Response::Ack
+        });
+        handlers.register(HeaderType::RequestStatus, |_, _| Response::Reply(vec![0]));
+
+        let mut device = TestPeripheral {
+            core_info: CoreInfo {
+                comms_revision: [1, 4, 6],
+                base_year: 2000,
+                creation_date: Some(DateCode::new(2019, 5, 17).unwrap()),
+                ..CoreInfo::new("MHE", "SCH2", "A", SerialNumber::new(1).unwrap(), "1.0")
+            },
+            handlers,
+            polls: 0,
+        };
+
+        // Core headers
+        assert_eq!(
+            dispatch(&mut device, &request(HeaderType::RequestManufacturerId)),
+            Response::Reply(b"MHE".to_vec())
+        );
+        assert_eq!(
+            dispatch(
+                &mut device,
+                &request(HeaderType::RequestEquipmentCategoryId)
+            ),
+            Response::Reply(b"Payout".to_vec())
+        );
+
+        assert_eq!(
+            dispatch(&mut device, &request(HeaderType::RequestCommsRevision)),
+            Response::Reply(vec![1, 4, 6])
+        );
+        assert_eq!(
+            dispatch(&mut device, &request(HeaderType::RequestPollingPriority)),
+            Response::Reply(vec![2, 20])
+        );
+        assert_eq!(
+            dispatch(&mut device, &request(HeaderType::RequestAddressMode)),
+            Response::Nak
+        );
+        assert_eq!(
+            dispatch(
+                &mut device,
+                &request(HeaderType::RequestCommsStatusVariables)
+            ),
+            Response::Reply(vec![0, 0, 0])
+        );
+
+        assert_eq!(
+            dispatch(&mut device, &request(HeaderType::RequestBaseYear)),
+            Response::Reply(b"2000".to_vec())
+        );
+        assert_eq!(
+            dispatch(&mut device, &request(HeaderType::RequestCreationDate)),
+            Response::Reply(vec![177, 38])
+        );
+        assert_eq!(
+            dispatch(
+                &mut device,
+                &request(HeaderType::RequestLastModificationDate)
+            ),
+            Response::Nak
+        );
+        assert_eq!(
+            dispatch(&mut device, &request(HeaderType::RequestRealTimeClock)),
+            Response::Nak
+        );
+
+        // Registered handlers take precedence
+        assert_eq!(
+            dispatch(&mut device, &request(HeaderType::SimplePoll)),
+            Response::Ack
+        );
+        assert_eq!(device.polls, 1);
+        assert_eq!(
+            dispatch(&mut device, &request(HeaderType::RequestStatus)),
+            Response::Reply(vec![0])
+        );
+
+        // Anything else is NAK-ed
+        let nak = dispatch(&mut device, &request(HeaderType::DispenseCoins));
+        assert_eq!(nak, Response::Nak);
+        assert_eq!(nak.into_payload().unwrap().header.to_u8(), 5);
+    }
+}
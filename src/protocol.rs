@@ -1,17 +1,37 @@
-use std::str;
+use core::convert;
+use core::fmt;
+use core::str;
+use core::time::Duration;
+
+#[cfg(feature = "alloc")]
+use alloc::{string::String, string::ToString, vec, vec::Vec};
+
+#[cfg(feature = "encryption")]
+use crate::encryption::EncryptionLayer;
 
 pub type Address = u8;
+#[cfg(feature = "alloc")]
 pub type Data = Vec<u8>;
 
 pub type CRC = [u8; 2];
 
-#[derive(Debug, Clone, Copy)]
+/// 5 header and checksum bytes plus 255 data bytes
+pub const MAX_FRAME_LENGTH: usize = 260;
+
+/// Address of the host, CRC frames are implicitly sent from here
+pub const HOST_ADDRESS: Address = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ChecksumType {
     SimpleChecksum,
     CRCChecksum,
 }
 
+#[cfg(feature = "alloc")]
 impl ChecksumType {
+    // Public API since 0.1, kept instead of implementing FromStr
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(checksum_type: String) -> ChecksumType {
         match checksum_type.as_ref() {
             "simple" => ChecksumType::SimpleChecksum,
@@ -21,7 +41,112 @@ impl ChecksumType {
     }
 }
 
+/// How received frames are validated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumMode {
+    /// Only frames with this checksum type are valid
+    Expect(ChecksumType),
+    /// Either checksum type, frames valid with both are rejected as
+    /// `ErrorType::AmbiguousChecksum`
+    Auto,
+}
+
+impl convert::From<ChecksumType> for ChecksumMode {
+    fn from(checksum_type: ChecksumType) -> ChecksumMode {
+        ChecksumMode::Expect(checksum_type)
+    }
+}
+
+/// Serialized form of header, error and event codes, known codes by their
+/// name and unknown ones by number
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+enum NamedCode<N> {
+    Name(String),
+    Number(N),
+}
+
+#[cfg(feature = "serde")]
+impl convert::From<HeaderType> for NamedCode<u8> {
+    fn from(header: HeaderType) -> NamedCode<u8> {
+        match header {
+            HeaderType::Unknown(n) => NamedCode::Number(n),
+            _ => NamedCode::Name(header.name().to_string()),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl convert::TryFrom<NamedCode<u8>> for HeaderType {
+    type Error = ErrorType;
+
+    fn try_from(code: NamedCode<u8>) -> Result<Self, Self::Error> {
+        match code {
+            NamedCode::Number(n) => Ok(HeaderType::from_u8(n)),
+            NamedCode::Name(name) => HeaderType::from_name(&name).ok_or(ErrorType::ParseError),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl convert::From<CoinAcceptorError> for NamedCode<u8> {
+    fn from(error: CoinAcceptorError) -> NamedCode<u8> {
+        match error {
+            CoinAcceptorError::Unknown(n) => NamedCode::Number(n),
+            CoinAcceptorError::InhibitedCoinType(n) => {
+                NamedCode::Name(format!("{}{}", error.name(), n))
+            }
+            _ => NamedCode::Name(error.name().to_string()),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl convert::TryFrom<NamedCode<u8>> for CoinAcceptorError {
+    type Error = ErrorType;
+
+    fn try_from(code: NamedCode<u8>) -> Result<Self, Self::Error> {
+        match code {
+            NamedCode::Number(n) => Ok(CoinAcceptorError::from_u8(n)),
+            NamedCode::Name(name) => {
+                CoinAcceptorError::from_name(&name).ok_or(ErrorType::ParseError)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl convert::From<BillEvent> for NamedCode<(u8, u8)> {
+    fn from(event: BillEvent) -> NamedCode<(u8, u8)> {
+        match event {
+            BillEvent::Unknown(a, b) => NamedCode::Number((a, b)),
+            BillEvent::BillTypeValidatedAndSent(n) | BillEvent::BillTypeValidatedAndHeld(n) => {
+                NamedCode::Name(format!("{}{}", event.name(), n))
+            }
+            _ => NamedCode::Name(event.name().to_string()),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl convert::TryFrom<NamedCode<(u8, u8)>> for BillEvent {
+    type Error = ErrorType;
+
+    fn try_from(code: NamedCode<(u8, u8)>) -> Result<Self, Self::Error> {
+        match code {
+            NamedCode::Number(n) => Ok(BillEvent::from_u8(n)),
+            NamedCode::Name(name) => BillEvent::from_name(&name).ok_or(ErrorType::ParseError),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "NamedCode<u8>", into = "NamedCode<u8>")
+)]
 pub enum HeaderType {
     FactorySetup,
     SimplePoll,
@@ -171,6 +296,8 @@ pub enum HeaderType {
     SwitchEncryptionKey,
     RequestEncryptedHopperStatus,
     RequestEncryptedMonetaryId,
+    Busy,
+    Nak,
     RequestCommsRevision,
     ClearCommsStatusVariables,
     RequestCommsStatusVariables,
@@ -321,8 +448,8 @@ impl HeaderType {
             119 => HeaderType::RequestHopperBalance,
             118 => HeaderType::ModifyCashboxValue,
             117 => HeaderType::RequestCashboxValue,
-            116 => HeaderType::ModifyRealTimeClock,
-            115 => HeaderType::RequestRealTimeClock,
+            116 => HeaderType::RequestRealTimeClock,
+            115 => HeaderType::ModifyRealTimeClock,
             114 => HeaderType::RequestUSBId,
             113 => HeaderType::SwitchBaudRate,
             112 => HeaderType::ReadEncryptedEvents,
@@ -330,6 +457,8 @@ impl HeaderType {
             110 => HeaderType::SwitchEncryptionKey,
             109 => HeaderType::RequestEncryptedHopperStatus,
             108 => HeaderType::RequestEncryptedMonetaryId,
+            6 => HeaderType::Busy,
+            5 => HeaderType::Nak,
             4 => HeaderType::RequestCommsRevision,
             3 => HeaderType::ClearCommsStatusVariables,
             2 => HeaderType::RequestCommsStatusVariables,
@@ -480,8 +609,8 @@ impl HeaderType {
             HeaderType::RequestHopperBalance => 119,
             HeaderType::ModifyCashboxValue => 118,
             HeaderType::RequestCashboxValue => 117,
-            HeaderType::ModifyRealTimeClock => 116,
-            HeaderType::RequestRealTimeClock => 115,
+            HeaderType::ModifyRealTimeClock => 115,
+            HeaderType::RequestRealTimeClock => 116,
             HeaderType::RequestUSBId => 114,
             HeaderType::SwitchBaudRate => 113,
             HeaderType::ReadEncryptedEvents => 112,
@@ -489,6 +618,8 @@ impl HeaderType {
             HeaderType::SwitchEncryptionKey => 110,
             HeaderType::RequestEncryptedHopperStatus => 109,
             HeaderType::RequestEncryptedMonetaryId => 108,
+            HeaderType::Busy => 6,
+            HeaderType::Nak => 5,
             HeaderType::RequestCommsRevision => 4,
             HeaderType::ClearCommsStatusVariables => 3,
             HeaderType::RequestCommsStatusVariables => 2,
@@ -497,10 +628,371 @@ impl HeaderType {
             HeaderType::Unknown(n) => n,
         }
     }
+
+    /// Variant name of the header, `Unknown` for unknown headers
+    pub fn name(&self) -> &'static str {
+        match self {
+            HeaderType::FactorySetup => "FactorySetup",
+            HeaderType::SimplePoll => "SimplePoll",
+            HeaderType::AddressPoll => "AddressPoll",
+            HeaderType::AddressClash => "AddressClash",
+            HeaderType::AddressChange => "AddressChange",
+            HeaderType::AddressRandom => "AddressRandom",
+            HeaderType::RequestPollingPriority => "RequestPollingPriority",
+            HeaderType::RequestStatus => "RequestStatus",
+            HeaderType::RequestVariableSet => "RequestVariableSet",
+            HeaderType::RequestManufacturerId => "RequestManufacturerId",
+            HeaderType::RequestEquipmentCategoryId => "RequestEquipmentCategoryId",
+            HeaderType::RequestProductCode => "RequestProductCode",
+            HeaderType::RequestDatabaseVersion => "RequestDatabaseVersion",
+            HeaderType::RequestSerialNumber => "RequestSerialNumber",
+            HeaderType::RequestSoftwareRevision => "RequestSoftwareRevision",
+            HeaderType::TestSolenoids => "TestSolenoids",
+            HeaderType::OperateMotors => "OperateMotors",
+            HeaderType::TestOutputLines => "TestOutputLines",
+            HeaderType::ReadInputLines => "ReadInputLines",
+            HeaderType::ReadOptoStates => "ReadOptoStates",
+            HeaderType::ReadLastCreditOrErrorCode => "ReadLastCreditOrErrorCode",
+            HeaderType::IssueGuardCode => "IssueGuardCode",
+            HeaderType::LatchOutputLines => "LatchOutputLines",
+            HeaderType::PerformSelfcheck => "PerformSelfcheck",
+            HeaderType::ModifyInhibitStatus => "ModifyInhibitStatus",
+            HeaderType::RequestInhibitStatus => "RequestInhibitStatus",
+            HeaderType::ReadBufferedCreditOrErrorCodes => "ReadBufferedCreditOrErrorCodes",
+            HeaderType::ModifyMasterInhibitStatus => "ModifyMasterInhibitStatus",
+            HeaderType::RequestMasterInhibitStatus => "RequestMasterInhibitStatus",
+            HeaderType::RequestInsertionCounter => "RequestInsertionCounter",
+            HeaderType::RequestAcceptCounter => "RequestAcceptCounter",
+            HeaderType::DispenseCoins => "DispenseCoins",
+            HeaderType::DispenseChange => "DispenseChange",
+            HeaderType::ModifySorterOverrideStatus => "ModifySorterOverrideStatus",
+            HeaderType::RequestSorterOverrideStatus => "RequestSorterOverrideStatus",
+            HeaderType::OneshotCredit => "OneshotCredit",
+            HeaderType::EnterNewPINNumber => "EnterNewPINNumber",
+            HeaderType::EnterPINNumber => "EnterPINNumber",
+            HeaderType::RequestPayoutHighLowStatus => "RequestPayoutHighLowStatus",
+            HeaderType::RequestDataStorageAvailability => "RequestDataStorageAvailability",
+            HeaderType::ReadDataBlock => "ReadDataBlock",
+            HeaderType::WriteDataBlock => "WriteDataBlock",
+            HeaderType::RequestOptionFlags => "RequestOptionFlags",
+            HeaderType::RequestCoinPosition => "RequestCoinPosition",
+            HeaderType::PowerManagementControl => "PowerManagementControl",
+            HeaderType::ModifySorterPaths => "ModifySorterPaths",
+            HeaderType::RequestSorterPaths => "RequestSorterPaths",
+            HeaderType::ModifyPayoutAbsoluteCount => "ModifyPayoutAbsoluteCount",
+            HeaderType::RequestPayoutAbsoluteCount => "RequestPayoutAbsoluteCount",
+            HeaderType::EmptyPayout => "EmptyPayout",
+            HeaderType::RequestAuditInformationBlock => "RequestAuditInformationBlock",
+            HeaderType::MeterControl => "MeterControl",
+            HeaderType::DisplayControl => "DisplayControl",
+            HeaderType::TeachModeControl => "TeachModeControl",
+            HeaderType::RequestTeachStatus => "RequestTeachStatus",
+            HeaderType::UploadCoinData => "UploadCoinData",
+            HeaderType::ConfigurationToEEPROM => "ConfigurationToEEPROM",
+            HeaderType::CountersToEEPROM => "CountersToEEPROM",
+            HeaderType::CalculateROMChecksum => "CalculateROMChecksum",
+            HeaderType::RequestCreationDate => "RequestCreationDate",
+            HeaderType::RequestLastModificationDate => "RequestLastModificationDate",
+            HeaderType::RequestRejectCounter => "RequestRejectCounter",
+            HeaderType::RequestFraudCounter => "RequestFraudCounter",
+            HeaderType::RequestBuildCode => "RequestBuildCode",
+            HeaderType::KeypadControl => "KeypadControl",
+            HeaderType::RequestPayoutStatus => "RequestPayoutStatus",
+            HeaderType::ModifyDefaultSorterPath => "ModifyDefaultSorterPath",
+            HeaderType::RequestDefaultSorterPath => "RequestDefaultSorterPath",
+            HeaderType::ModifyPayoutCapacity => "ModifyPayoutCapacity",
+            HeaderType::RequestPayoutCapacity => "RequestPayoutCapacity",
+            HeaderType::ModifyCoinId => "ModifyCoinId",
+            HeaderType::RequestCoinId => "RequestCoinId",
+            HeaderType::UploadWindowData => "UploadWindowData",
+            HeaderType::DownloadCalibrationInfo => "DownloadCalibrationInfo",
+            HeaderType::ModifySecuritySetting => "ModifySecuritySetting",
+            HeaderType::RequestSecuritySetting => "RequestSecuritySetting",
+            HeaderType::ModifyBankSelect => "ModifyBankSelect",
+            HeaderType::RequestBankSelect => "RequestBankSelect",
+            HeaderType::HandheldFunction => "HandheldFunction",
+            HeaderType::RequestAlarmCounter => "RequestAlarmCounter",
+            HeaderType::ModifyPayoutFloat => "ModifyPayoutFloat",
+            HeaderType::RequestPayoutFloat => "RequestPayoutFloat",
+            HeaderType::RequestThermistorReading => "RequestThermistorReading",
+            HeaderType::EmergencyStop => "EmergencyStop",
+            HeaderType::RequestHopperCoin => "RequestHopperCoin",
+            HeaderType::RequestBaseYear => "RequestBaseYear",
+            HeaderType::RequestAddressMode => "RequestAddressMode",
+            HeaderType::RequestHopperDispenseCount => "RequestHopperDispenseCount",
+            HeaderType::DispenseHopperCoins => "DispenseHopperCoins",
+            HeaderType::RequestHopperStatus => "RequestHopperStatus",
+            HeaderType::ModifyVariableSet => "ModifyVariableSet",
+            HeaderType::EnableHopper => "EnableHopper",
+            HeaderType::TestHopper => "TestHopper",
+            HeaderType::ModifyInhibitAndOverrideRegisters => "ModifyInhibitAndOverrideRegisters",
+            HeaderType::PumpRNG => "PumpRNG",
+            HeaderType::RequestCipherKey => "RequestCipherKey",
+            HeaderType::ReadBufferedBillEvents => "ReadBufferedBillEvents",
+            HeaderType::ModifyBillId => "ModifyBillId",
+            HeaderType::RequestBillId => "RequestBillId",
+            HeaderType::RequestCountryScalingFactor => "RequestCountryScalingFactor",
+            HeaderType::RequestBillPosition => "RequestBillPosition",
+            HeaderType::RouteBill => "RouteBill",
+            HeaderType::ModifyBillOperatingMode => "ModifyBillOperatingMode",
+            HeaderType::RequestBillOperatingMode => "RequestBillOperatingMode",
+            HeaderType::TestLamps => "TestLamps",
+            HeaderType::RequestIndividualAcceptCounter => "RequestIndividualAcceptCounter",
+            HeaderType::RequestIndividualErrorCounter => "RequestIndividualErrorCounter",
+            HeaderType::ReadOptoVoltages => "ReadOptoVoltages",
+            HeaderType::PerformStackerCycle => "PerformStackerCycle",
+            HeaderType::OperateBidirectionalMotors => "OperateBidirectionalMotors",
+            HeaderType::RequestCurrencyRevision => "RequestCurrencyRevision",
+            HeaderType::UploadBillTables => "UploadBillTables",
+            HeaderType::BeginBillTableUpgrade => "BeginBillTableUpgrade",
+            HeaderType::FinishBillTableUpgrade => "FinishBillTableUpgrade",
+            HeaderType::RequestFirmwareUpgradeCapability => "RequestFirmwareUpgradeCapability",
+            HeaderType::UploadFirmware => "UploadFirmware",
+            HeaderType::BeginFirmwareUpgrade => "BeginFirmwareUpgrade",
+            HeaderType::FinishFirmwareUpgrade => "FinishFirmwareUpgrade",
+            HeaderType::SwitchEncryptionCode => "SwitchEncryptionCode",
+            HeaderType::StoreEncryptionCode => "StoreEncryptionCode",
+            HeaderType::SetAcceptLimit => "SetAcceptLimit",
+            HeaderType::DispenseHopperValue => "DispenseHopperValue",
+            HeaderType::RequestHopperPollingValue => "RequestHopperPollingValue",
+            HeaderType::EmergencyStopValue => "EmergencyStopValue",
+            HeaderType::RequestHopperCoinValue => "RequestHopperCoinValue",
+            HeaderType::RequestIndexedHopperDispenseCount => "RequestIndexedHopperDispenseCount",
+            HeaderType::ReadBarcodeData => "ReadBarcodeData",
+            HeaderType::RequestMoneyIn => "RequestMoneyIn",
+            HeaderType::RequestMoneyOut => "RequestMoneyOut",
+            HeaderType::ClearMoneyCounters => "ClearMoneyCounters",
+            HeaderType::PayMoneyOut => "PayMoneyOut",
+            HeaderType::VerifyMoneyOut => "VerifyMoneyOut",
+            HeaderType::RequestActivityRegister => "RequestActivityRegister",
+            HeaderType::RequestErrorStatus => "RequestErrorStatus",
+            HeaderType::PurgeHopper => "PurgeHopper",
+            HeaderType::ModifyHopperBalance => "ModifyHopperBalance",
+            HeaderType::RequestHopperBalance => "RequestHopperBalance",
+            HeaderType::ModifyCashboxValue => "ModifyCashboxValue",
+            HeaderType::RequestCashboxValue => "RequestCashboxValue",
+            HeaderType::ModifyRealTimeClock => "ModifyRealTimeClock",
+            HeaderType::RequestRealTimeClock => "RequestRealTimeClock",
+            HeaderType::RequestUSBId => "RequestUSBId",
+            HeaderType::SwitchBaudRate => "SwitchBaudRate",
+            HeaderType::ReadEncryptedEvents => "ReadEncryptedEvents",
+            HeaderType::RequestEncryptionSupport => "RequestEncryptionSupport",
+            HeaderType::SwitchEncryptionKey => "SwitchEncryptionKey",
+            HeaderType::RequestEncryptedHopperStatus => "RequestEncryptedHopperStatus",
+            HeaderType::RequestEncryptedMonetaryId => "RequestEncryptedMonetaryId",
+            HeaderType::Busy => "Busy",
+            HeaderType::Nak => "Nak",
+            HeaderType::RequestCommsRevision => "RequestCommsRevision",
+            HeaderType::ClearCommsStatusVariables => "ClearCommsStatusVariables",
+            HeaderType::RequestCommsStatusVariables => "RequestCommsStatusVariables",
+            HeaderType::ResetDevice => "ResetDevice",
+            HeaderType::Reply => "Reply",
+            HeaderType::Unknown(_) => "Unknown",
+        }
+    }
+
+    /// Header with the variant name `name`, `None` if there is none
+    pub fn from_name(name: &str) -> Option<HeaderType> {
+        match name {
+            "FactorySetup" => Some(HeaderType::FactorySetup),
+            "SimplePoll" => Some(HeaderType::SimplePoll),
+            "AddressPoll" => Some(HeaderType::AddressPoll),
+            "AddressClash" => Some(HeaderType::AddressClash),
+            "AddressChange" => Some(HeaderType::AddressChange),
+            "AddressRandom" => Some(HeaderType::AddressRandom),
+            "RequestPollingPriority" => Some(HeaderType::RequestPollingPriority),
+            "RequestStatus" => Some(HeaderType::RequestStatus),
+            "RequestVariableSet" => Some(HeaderType::RequestVariableSet),
+            "RequestManufacturerId" => Some(HeaderType::RequestManufacturerId),
+            "RequestEquipmentCategoryId" => Some(HeaderType::RequestEquipmentCategoryId),
+            "RequestProductCode" => Some(HeaderType::RequestProductCode),
+            "RequestDatabaseVersion" => Some(HeaderType::RequestDatabaseVersion),
+            "RequestSerialNumber" => Some(HeaderType::RequestSerialNumber),
+            "RequestSoftwareRevision" => Some(HeaderType::RequestSoftwareRevision),
+            "TestSolenoids" => Some(HeaderType::TestSolenoids),
+            "OperateMotors" => Some(HeaderType::OperateMotors),
+            "TestOutputLines" => Some(HeaderType::TestOutputLines),
+            "ReadInputLines" => Some(HeaderType::ReadInputLines),
+            "ReadOptoStates" => Some(HeaderType::ReadOptoStates),
+            "ReadLastCreditOrErrorCode" => Some(HeaderType::ReadLastCreditOrErrorCode),
+            "IssueGuardCode" => Some(HeaderType::IssueGuardCode),
+            "LatchOutputLines" => Some(HeaderType::LatchOutputLines),
+            "PerformSelfcheck" => Some(HeaderType::PerformSelfcheck),
+            "ModifyInhibitStatus" => Some(HeaderType::ModifyInhibitStatus),
+            "RequestInhibitStatus" => Some(HeaderType::RequestInhibitStatus),
+            "ReadBufferedCreditOrErrorCodes" => Some(HeaderType::ReadBufferedCreditOrErrorCodes),
+            "ModifyMasterInhibitStatus" => Some(HeaderType::ModifyMasterInhibitStatus),
+            "RequestMasterInhibitStatus" => Some(HeaderType::RequestMasterInhibitStatus),
+            "RequestInsertionCounter" => Some(HeaderType::RequestInsertionCounter),
+            "RequestAcceptCounter" => Some(HeaderType::RequestAcceptCounter),
+            "DispenseCoins" => Some(HeaderType::DispenseCoins),
+            "DispenseChange" => Some(HeaderType::DispenseChange),
+            "ModifySorterOverrideStatus" => Some(HeaderType::ModifySorterOverrideStatus),
+            "RequestSorterOverrideStatus" => Some(HeaderType::RequestSorterOverrideStatus),
+            "OneshotCredit" => Some(HeaderType::OneshotCredit),
+            "EnterNewPINNumber" => Some(HeaderType::EnterNewPINNumber),
+            "EnterPINNumber" => Some(HeaderType::EnterPINNumber),
+            "RequestPayoutHighLowStatus" => Some(HeaderType::RequestPayoutHighLowStatus),
+            "RequestDataStorageAvailability" => Some(HeaderType::RequestDataStorageAvailability),
+            "ReadDataBlock" => Some(HeaderType::ReadDataBlock),
+            "WriteDataBlock" => Some(HeaderType::WriteDataBlock),
+            "RequestOptionFlags" => Some(HeaderType::RequestOptionFlags),
+            "RequestCoinPosition" => Some(HeaderType::RequestCoinPosition),
+            "PowerManagementControl" => Some(HeaderType::PowerManagementControl),
+            "ModifySorterPaths" => Some(HeaderType::ModifySorterPaths),
+            "RequestSorterPaths" => Some(HeaderType::RequestSorterPaths),
+            "ModifyPayoutAbsoluteCount" => Some(HeaderType::ModifyPayoutAbsoluteCount),
+            "RequestPayoutAbsoluteCount" => Some(HeaderType::RequestPayoutAbsoluteCount),
+            "EmptyPayout" => Some(HeaderType::EmptyPayout),
+            "RequestAuditInformationBlock" => Some(HeaderType::RequestAuditInformationBlock),
+            "MeterControl" => Some(HeaderType::MeterControl),
+            "DisplayControl" => Some(HeaderType::DisplayControl),
+            "TeachModeControl" => Some(HeaderType::TeachModeControl),
+            "RequestTeachStatus" => Some(HeaderType::RequestTeachStatus),
+            "UploadCoinData" => Some(HeaderType::UploadCoinData),
+            "ConfigurationToEEPROM" => Some(HeaderType::ConfigurationToEEPROM),
+            "CountersToEEPROM" => Some(HeaderType::CountersToEEPROM),
+            "CalculateROMChecksum" => Some(HeaderType::CalculateROMChecksum),
+            "RequestCreationDate" => Some(HeaderType::RequestCreationDate),
+            "RequestLastModificationDate" => Some(HeaderType::RequestLastModificationDate),
+            "RequestRejectCounter" => Some(HeaderType::RequestRejectCounter),
+            "RequestFraudCounter" => Some(HeaderType::RequestFraudCounter),
+            "RequestBuildCode" => Some(HeaderType::RequestBuildCode),
+            "KeypadControl" => Some(HeaderType::KeypadControl),
+            "RequestPayoutStatus" => Some(HeaderType::RequestPayoutStatus),
+            "ModifyDefaultSorterPath" => Some(HeaderType::ModifyDefaultSorterPath),
+            "RequestDefaultSorterPath" => Some(HeaderType::RequestDefaultSorterPath),
+            "ModifyPayoutCapacity" => Some(HeaderType::ModifyPayoutCapacity),
+            "RequestPayoutCapacity" => Some(HeaderType::RequestPayoutCapacity),
+            "ModifyCoinId" => Some(HeaderType::ModifyCoinId),
+            "RequestCoinId" => Some(HeaderType::RequestCoinId),
+            "UploadWindowData" => Some(HeaderType::UploadWindowData),
+            "DownloadCalibrationInfo" => Some(HeaderType::DownloadCalibrationInfo),
+            "ModifySecuritySetting" => Some(HeaderType::ModifySecuritySetting),
+            "RequestSecuritySetting" => Some(HeaderType::RequestSecuritySetting),
+            "ModifyBankSelect" => Some(HeaderType::ModifyBankSelect),
+            "RequestBankSelect" => Some(HeaderType::RequestBankSelect),
+            "HandheldFunction" => Some(HeaderType::HandheldFunction),
+            "RequestAlarmCounter" => Some(HeaderType::RequestAlarmCounter),
+            "ModifyPayoutFloat" => Some(HeaderType::ModifyPayoutFloat),
+            "RequestPayoutFloat" => Some(HeaderType::RequestPayoutFloat),
+            "RequestThermistorReading" => Some(HeaderType::RequestThermistorReading),
+            "EmergencyStop" => Some(HeaderType::EmergencyStop),
+            "RequestHopperCoin" => Some(HeaderType::RequestHopperCoin),
+            "RequestBaseYear" => Some(HeaderType::RequestBaseYear),
+            "RequestAddressMode" => Some(HeaderType::RequestAddressMode),
+            "RequestHopperDispenseCount" => Some(HeaderType::RequestHopperDispenseCount),
+            "DispenseHopperCoins" => Some(HeaderType::DispenseHopperCoins),
+            "RequestHopperStatus" => Some(HeaderType::RequestHopperStatus),
+            "ModifyVariableSet" => Some(HeaderType::ModifyVariableSet),
+            "EnableHopper" => Some(HeaderType::EnableHopper),
+            "TestHopper" => Some(HeaderType::TestHopper),
+            "ModifyInhibitAndOverrideRegisters" => {
+                Some(HeaderType::ModifyInhibitAndOverrideRegisters)
+            }
+            "PumpRNG" => Some(HeaderType::PumpRNG),
+            "RequestCipherKey" => Some(HeaderType::RequestCipherKey),
+            "ReadBufferedBillEvents" => Some(HeaderType::ReadBufferedBillEvents),
+            "ModifyBillId" => Some(HeaderType::ModifyBillId),
+            "RequestBillId" => Some(HeaderType::RequestBillId),
+            "RequestCountryScalingFactor" => Some(HeaderType::RequestCountryScalingFactor),
+            "RequestBillPosition" => Some(HeaderType::RequestBillPosition),
+            "RouteBill" => Some(HeaderType::RouteBill),
+            "ModifyBillOperatingMode" => Some(HeaderType::ModifyBillOperatingMode),
+            "RequestBillOperatingMode" => Some(HeaderType::RequestBillOperatingMode),
+            "TestLamps" => Some(HeaderType::TestLamps),
+            "RequestIndividualAcceptCounter" => Some(HeaderType::RequestIndividualAcceptCounter),
+            "RequestIndividualErrorCounter" => Some(HeaderType::RequestIndividualErrorCounter),
+            "ReadOptoVoltages" => Some(HeaderType::ReadOptoVoltages),
+            "PerformStackerCycle" => Some(HeaderType::PerformStackerCycle),
+            "OperateBidirectionalMotors" => Some(HeaderType::OperateBidirectionalMotors),
+            "RequestCurrencyRevision" => Some(HeaderType::RequestCurrencyRevision),
+            "UploadBillTables" => Some(HeaderType::UploadBillTables),
+            "BeginBillTableUpgrade" => Some(HeaderType::BeginBillTableUpgrade),
+            "FinishBillTableUpgrade" => Some(HeaderType::FinishBillTableUpgrade),
+            "RequestFirmwareUpgradeCapability" => {
+                Some(HeaderType::RequestFirmwareUpgradeCapability)
+            }
+            "UploadFirmware" => Some(HeaderType::UploadFirmware),
+            "BeginFirmwareUpgrade" => Some(HeaderType::BeginFirmwareUpgrade),
+            "FinishFirmwareUpgrade" => Some(HeaderType::FinishFirmwareUpgrade),
+            "SwitchEncryptionCode" => Some(HeaderType::SwitchEncryptionCode),
+            "StoreEncryptionCode" => Some(HeaderType::StoreEncryptionCode),
+            "SetAcceptLimit" => Some(HeaderType::SetAcceptLimit),
+            "DispenseHopperValue" => Some(HeaderType::DispenseHopperValue),
+            "RequestHopperPollingValue" => Some(HeaderType::RequestHopperPollingValue),
+            "EmergencyStopValue" => Some(HeaderType::EmergencyStopValue),
+            "RequestHopperCoinValue" => Some(HeaderType::RequestHopperCoinValue),
+            "RequestIndexedHopperDispenseCount" => {
+                Some(HeaderType::RequestIndexedHopperDispenseCount)
+            }
+            "ReadBarcodeData" => Some(HeaderType::ReadBarcodeData),
+            "RequestMoneyIn" => Some(HeaderType::RequestMoneyIn),
+            "RequestMoneyOut" => Some(HeaderType::RequestMoneyOut),
+            "ClearMoneyCounters" => Some(HeaderType::ClearMoneyCounters),
+            "PayMoneyOut" => Some(HeaderType::PayMoneyOut),
+            "VerifyMoneyOut" => Some(HeaderType::VerifyMoneyOut),
+            "RequestActivityRegister" => Some(HeaderType::RequestActivityRegister),
+            "RequestErrorStatus" => Some(HeaderType::RequestErrorStatus),
+            "PurgeHopper" => Some(HeaderType::PurgeHopper),
+            "ModifyHopperBalance" => Some(HeaderType::ModifyHopperBalance),
+            "RequestHopperBalance" => Some(HeaderType::RequestHopperBalance),
+            "ModifyCashboxValue" => Some(HeaderType::ModifyCashboxValue),
+            "RequestCashboxValue" => Some(HeaderType::RequestCashboxValue),
+            "ModifyRealTimeClock" => Some(HeaderType::ModifyRealTimeClock),
+            "RequestRealTimeClock" => Some(HeaderType::RequestRealTimeClock),
+            "RequestUSBId" => Some(HeaderType::RequestUSBId),
+            "SwitchBaudRate" => Some(HeaderType::SwitchBaudRate),
+            "ReadEncryptedEvents" => Some(HeaderType::ReadEncryptedEvents),
+            "RequestEncryptionSupport" => Some(HeaderType::RequestEncryptionSupport),
+            "SwitchEncryptionKey" => Some(HeaderType::SwitchEncryptionKey),
+            "RequestEncryptedHopperStatus" => Some(HeaderType::RequestEncryptedHopperStatus),
+            "RequestEncryptedMonetaryId" => Some(HeaderType::RequestEncryptedMonetaryId),
+            "Busy" => Some(HeaderType::Busy),
+            "Nak" => Some(HeaderType::Nak),
+            "RequestCommsRevision" => Some(HeaderType::RequestCommsRevision),
+            "ClearCommsStatusVariables" => Some(HeaderType::ClearCommsStatusVariables),
+            "RequestCommsStatusVariables" => Some(HeaderType::RequestCommsStatusVariables),
+            "ResetDevice" => Some(HeaderType::ResetDevice),
+            "Reply" => Some(HeaderType::Reply),
+            _ => None,
+        }
+    }
+}
+
+/// Name of the header, `Unknown` for unknown headers
+impl fmt::Display for HeaderType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
 }
 
+/// Classification of coin acceptor errors and bill events, in increasing
+/// order of severity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Severity {
+    /// Normal operation or a change of state
+    Status,
+    /// The coin or bill was returned to the customer
+    Reject,
+    /// Attempted fraud
+    Fraud,
+    /// Hardware fault, may need service
+    Fault,
+}
+
+/// Coin acceptor error codes of the ccTalk generic specification, issue 4.7
+///
+/// Codes that issue doesn't assign, 41..=127 and 160..=252, are `Unknown`.
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "NamedCode<u8>", into = "NamedCode<u8>")
+)]
 pub enum CoinAcceptorError {
+    NullEvent,
     RejectCoin,
     InhibitedCoin,
     MultipleWindow,
@@ -541,12 +1033,8 @@ pub enum CoinAcceptorError {
     CoinTooSlowValidationSensor,
     CoinIncorrectlySorted,
     ExternalLightAttack,
-    InhibitedCoinType1,
-    InhibitedCoinType2,
-    InhibitedCoinType3,
-    InhibitedCoinType4,
-    InhibitedCoinType5,
-    InhibitedCoinType6,
+    /// Inhibited coin of type 1..=32, codes 128..=159
+    InhibitedCoinType(u8),
     DataBlockRequest,
     FlightDeckOpen,
     UnspecifiedAlarm,
@@ -556,6 +1044,7 @@ pub enum CoinAcceptorError {
 impl CoinAcceptorError {
     pub fn from_u8(n: u8) -> CoinAcceptorError {
         match n {
+            0 => CoinAcceptorError::NullEvent,
             1 => CoinAcceptorError::RejectCoin,
             2 => CoinAcceptorError::InhibitedCoin,
             3 => CoinAcceptorError::MultipleWindow,
@@ -596,12 +1085,7 @@ impl CoinAcceptorError {
             38 => CoinAcceptorError::CoinTooSlowValidationSensor,
             39 => CoinAcceptorError::CoinIncorrectlySorted,
             40 => CoinAcceptorError::ExternalLightAttack,
-            128 => CoinAcceptorError::InhibitedCoinType1,
-            129 => CoinAcceptorError::InhibitedCoinType2,
-            130 => CoinAcceptorError::InhibitedCoinType3,
-            131 => CoinAcceptorError::InhibitedCoinType4,
-            132 => CoinAcceptorError::InhibitedCoinType5,
-            133 => CoinAcceptorError::InhibitedCoinType6,
+            n @ 128..=159 => CoinAcceptorError::InhibitedCoinType(n - 127),
             253 => CoinAcceptorError::DataBlockRequest,
             254 => CoinAcceptorError::FlightDeckOpen,
             255 => CoinAcceptorError::UnspecifiedAlarm,
@@ -611,6 +1095,7 @@ impl CoinAcceptorError {
 
     pub fn to_u8(&self) -> u8 {
         match *self {
+            CoinAcceptorError::NullEvent => 0,
             CoinAcceptorError::RejectCoin => 1,
             CoinAcceptorError::InhibitedCoin => 2,
             CoinAcceptorError::MultipleWindow => 3,
@@ -651,34 +1136,307 @@ impl CoinAcceptorError {
             CoinAcceptorError::CoinTooSlowValidationSensor => 38,
             CoinAcceptorError::CoinIncorrectlySorted => 39,
             CoinAcceptorError::ExternalLightAttack => 40,
-            CoinAcceptorError::InhibitedCoinType1 => 128,
-            CoinAcceptorError::InhibitedCoinType2 => 129,
-            CoinAcceptorError::InhibitedCoinType3 => 130,
-            CoinAcceptorError::InhibitedCoinType4 => 131,
-            CoinAcceptorError::InhibitedCoinType5 => 132,
-            CoinAcceptorError::InhibitedCoinType6 => 133,
+            CoinAcceptorError::InhibitedCoinType(n) => n + 127,
             CoinAcceptorError::DataBlockRequest => 253,
             CoinAcceptorError::FlightDeckOpen => 254,
             CoinAcceptorError::UnspecifiedAlarm => 255,
             CoinAcceptorError::Unknown(n) => n,
         }
     }
+
+    /// Variant name of the error, `Unknown` for unknown codes, without the
+    /// coin type of `InhibitedCoinType`
+    pub fn name(&self) -> &'static str {
+        match self {
+            CoinAcceptorError::NullEvent => "NullEvent",
+            CoinAcceptorError::RejectCoin => "RejectCoin",
+            CoinAcceptorError::InhibitedCoin => "InhibitedCoin",
+            CoinAcceptorError::MultipleWindow => "MultipleWindow",
+            CoinAcceptorError::WakeUpTimeout => "WakeUpTimeout",
+            CoinAcceptorError::ValidationTimeout => "ValidationTimeout",
+            CoinAcceptorError::CreditSensorTimeout => "CreditSensorTimeout",
+            CoinAcceptorError::SorterOptoTimeout => "SorterOptoTimeout",
+            CoinAcceptorError::SecondCloseCoinError => "SecondCloseCoinError",
+            CoinAcceptorError::AcceptGateNotReady => "AcceptGateNotReady",
+            CoinAcceptorError::CreditSensorNotReady => "CreditSensorNotReady",
+            CoinAcceptorError::SorterNotReady => "SorterNotReady",
+            CoinAcceptorError::RejectCoinNotCleared => "RejectCoinNotCleared",
+            CoinAcceptorError::ValidationSensorNotReady => "ValidationSensorNotReady",
+            CoinAcceptorError::CreditSensorBlocked => "CreditSensorBlocked",
+            CoinAcceptorError::SorterOptoBlocked => "SorterOptoBlocked",
+            CoinAcceptorError::CreditSequenceError => "CreditSequenceError",
+            CoinAcceptorError::CoinGoingBackwards => "CoinGoingBackwards",
+            CoinAcceptorError::CoinTooFastCreditSensor => "CoinTooFastCreditSensor",
+            CoinAcceptorError::CoinTooSlowCreditSensor => "CoinTooSlowCreditSensor",
+            CoinAcceptorError::CoinOnStringActive => "CoinOnStringActive",
+            CoinAcceptorError::DCEOptoTimeout => "DCEOptoTimeout",
+            CoinAcceptorError::DCEOptoNotSeen => "DCEOptoNotSeen",
+            CoinAcceptorError::CreditSensorReachedEarly => "CreditSensorReachedEarly",
+            CoinAcceptorError::RejectCoinRepeatedly => "RejectCoinRepeatedly",
+            CoinAcceptorError::RejectSlug => "RejectSlug",
+            CoinAcceptorError::RejectSensorBlocked => "RejectSensorBlocked",
+            CoinAcceptorError::GamesOverload => "GamesOverload",
+            CoinAcceptorError::MaxCoinMeterPulsesExceeded => "MaxCoinMeterPulsesExceeded",
+            CoinAcceptorError::AcceptGateOpenNotClosed => "AcceptGateOpenNotClosed",
+            CoinAcceptorError::AcceptGateClosedNotOpen => "AcceptGateClosedNotOpen",
+            CoinAcceptorError::ManifoldOptoTimeout => "ManifoldOptoTimeout",
+            CoinAcceptorError::ManifoldOptoBlocked => "ManifoldOptoBlocked",
+            CoinAcceptorError::ManifoldNotReady => "ManifoldNotReady",
+            CoinAcceptorError::SecurityStatusChanged => "SecurityStatusChanged",
+            CoinAcceptorError::MotorException => "MotorException",
+            CoinAcceptorError::SwallowedCoin => "SwallowedCoin",
+            CoinAcceptorError::CoinTooFastValidationSensor => "CoinTooFastValidationSensor",
+            CoinAcceptorError::CoinTooSlowValidationSensor => "CoinTooSlowValidationSensor",
+            CoinAcceptorError::CoinIncorrectlySorted => "CoinIncorrectlySorted",
+            CoinAcceptorError::ExternalLightAttack => "ExternalLightAttack",
+            CoinAcceptorError::InhibitedCoinType(_) => "InhibitedCoinType",
+            CoinAcceptorError::DataBlockRequest => "DataBlockRequest",
+            CoinAcceptorError::FlightDeckOpen => "FlightDeckOpen",
+            CoinAcceptorError::UnspecifiedAlarm => "UnspecifiedAlarm",
+            CoinAcceptorError::Unknown(_) => "Unknown",
+        }
+    }
+
+    /// Error with the variant name `name`, `None` if there is none
+    ///
+    /// The coin type follows the name of inhibited coin types, e.g.
+    /// `InhibitedCoinType3`.
+    pub fn from_name(name: &str) -> Option<CoinAcceptorError> {
+        if let Some(coin_type) = name.strip_prefix("InhibitedCoinType") {
+            return match coin_type.parse() {
+                Ok(coin_type @ 1..=32) => Some(CoinAcceptorError::InhibitedCoinType(coin_type)),
+                _ => None,
+            };
+        }
+        match name {
+            "NullEvent" => Some(CoinAcceptorError::NullEvent),
+            "RejectCoin" => Some(CoinAcceptorError::RejectCoin),
+            "InhibitedCoin" => Some(CoinAcceptorError::InhibitedCoin),
+            "MultipleWindow" => Some(CoinAcceptorError::MultipleWindow),
+            "WakeUpTimeout" => Some(CoinAcceptorError::WakeUpTimeout),
+            "ValidationTimeout" => Some(CoinAcceptorError::ValidationTimeout),
+            "CreditSensorTimeout" => Some(CoinAcceptorError::CreditSensorTimeout),
+            "SorterOptoTimeout" => Some(CoinAcceptorError::SorterOptoTimeout),
+            "SecondCloseCoinError" => Some(CoinAcceptorError::SecondCloseCoinError),
+            "AcceptGateNotReady" => Some(CoinAcceptorError::AcceptGateNotReady),
+            "CreditSensorNotReady" => Some(CoinAcceptorError::CreditSensorNotReady),
+            "SorterNotReady" => Some(CoinAcceptorError::SorterNotReady),
+            "RejectCoinNotCleared" => Some(CoinAcceptorError::RejectCoinNotCleared),
+            "ValidationSensorNotReady" => Some(CoinAcceptorError::ValidationSensorNotReady),
+            "CreditSensorBlocked" => Some(CoinAcceptorError::CreditSensorBlocked),
+            "SorterOptoBlocked" => Some(CoinAcceptorError::SorterOptoBlocked),
+            "CreditSequenceError" => Some(CoinAcceptorError::CreditSequenceError),
+            "CoinGoingBackwards" => Some(CoinAcceptorError::CoinGoingBackwards),
+            "CoinTooFastCreditSensor" => Some(CoinAcceptorError::CoinTooFastCreditSensor),
+            "CoinTooSlowCreditSensor" => Some(CoinAcceptorError::CoinTooSlowCreditSensor),
+            "CoinOnStringActive" => Some(CoinAcceptorError::CoinOnStringActive),
+            "DCEOptoTimeout" => Some(CoinAcceptorError::DCEOptoTimeout),
+            "DCEOptoNotSeen" => Some(CoinAcceptorError::DCEOptoNotSeen),
+            "CreditSensorReachedEarly" => Some(CoinAcceptorError::CreditSensorReachedEarly),
+            "RejectCoinRepeatedly" => Some(CoinAcceptorError::RejectCoinRepeatedly),
+            "RejectSlug" => Some(CoinAcceptorError::RejectSlug),
+            "RejectSensorBlocked" => Some(CoinAcceptorError::RejectSensorBlocked),
+            "GamesOverload" => Some(CoinAcceptorError::GamesOverload),
+            "MaxCoinMeterPulsesExceeded" => Some(CoinAcceptorError::MaxCoinMeterPulsesExceeded),
+            "AcceptGateOpenNotClosed" => Some(CoinAcceptorError::AcceptGateOpenNotClosed),
+            "AcceptGateClosedNotOpen" => Some(CoinAcceptorError::AcceptGateClosedNotOpen),
+            "ManifoldOptoTimeout" => Some(CoinAcceptorError::ManifoldOptoTimeout),
+            "ManifoldOptoBlocked" => Some(CoinAcceptorError::ManifoldOptoBlocked),
+            "ManifoldNotReady" => Some(CoinAcceptorError::ManifoldNotReady),
+            "SecurityStatusChanged" => Some(CoinAcceptorError::SecurityStatusChanged),
+            "MotorException" => Some(CoinAcceptorError::MotorException),
+            "SwallowedCoin" => Some(CoinAcceptorError::SwallowedCoin),
+            "CoinTooFastValidationSensor" => Some(CoinAcceptorError::CoinTooFastValidationSensor),
+            "CoinTooSlowValidationSensor" => Some(CoinAcceptorError::CoinTooSlowValidationSensor),
+            "CoinIncorrectlySorted" => Some(CoinAcceptorError::CoinIncorrectlySorted),
+            "ExternalLightAttack" => Some(CoinAcceptorError::ExternalLightAttack),
+            "DataBlockRequest" => Some(CoinAcceptorError::DataBlockRequest),
+            "FlightDeckOpen" => Some(CoinAcceptorError::FlightDeckOpen),
+            "UnspecifiedAlarm" => Some(CoinAcceptorError::UnspecifiedAlarm),
+            _ => None,
+        }
+    }
+
+    /// Text of the ccTalk error code table
+    pub fn description(&self) -> &'static str {
+        match *self {
+            CoinAcceptorError::NullEvent => "Null event, no error",
+            CoinAcceptorError::RejectCoin => "Reject coin",
+            CoinAcceptorError::InhibitedCoin => "Inhibited coin",
+            CoinAcceptorError::MultipleWindow => "Multiple window",
+            CoinAcceptorError::WakeUpTimeout => "Wake-up timeout",
+            CoinAcceptorError::ValidationTimeout => "Validation timeout",
+            CoinAcceptorError::CreditSensorTimeout => "Credit sensor timeout",
+            CoinAcceptorError::SorterOptoTimeout => "Sorter opto timeout",
+            CoinAcceptorError::SecondCloseCoinError => "2nd close coin error",
+            CoinAcceptorError::AcceptGateNotReady => "Accept gate not ready",
+            CoinAcceptorError::CreditSensorNotReady => "Credit sensor not ready",
+            CoinAcceptorError::SorterNotReady => "Sorter not ready",
+            CoinAcceptorError::RejectCoinNotCleared => "Reject coin not cleared",
+            CoinAcceptorError::ValidationSensorNotReady => "Validation sensor not ready",
+            CoinAcceptorError::CreditSensorBlocked => "Credit sensor blocked",
+            CoinAcceptorError::SorterOptoBlocked => "Sorter opto blocked",
+            CoinAcceptorError::CreditSequenceError => "Credit sequence error",
+            CoinAcceptorError::CoinGoingBackwards => "Coin going backwards",
+            CoinAcceptorError::CoinTooFastCreditSensor => "Coin too fast over the credit sensor",
+            CoinAcceptorError::CoinTooSlowCreditSensor => "Coin too slow over the credit sensor",
+            CoinAcceptorError::CoinOnStringActive => "Coin on string mechanism activated",
+            CoinAcceptorError::DCEOptoTimeout => "DCE opto timeout",
+            CoinAcceptorError::DCEOptoNotSeen => "DCE opto not seen",
+            CoinAcceptorError::CreditSensorReachedEarly => "Credit sensor reached too early",
+            CoinAcceptorError::RejectCoinRepeatedly => "Reject coin, repeated sequential trip",
+            CoinAcceptorError::RejectSlug => "Reject slug",
+            CoinAcceptorError::RejectSensorBlocked => "Reject sensor blocked",
+            CoinAcceptorError::GamesOverload => "Games overload",
+            CoinAcceptorError::MaxCoinMeterPulsesExceeded => "Max. coin meter pulses exceeded",
+            CoinAcceptorError::AcceptGateOpenNotClosed => "Accept gate open not closed",
+            CoinAcceptorError::AcceptGateClosedNotOpen => "Accept gate closed not open",
+            CoinAcceptorError::ManifoldOptoTimeout => "Manifold opto timeout",
+            CoinAcceptorError::ManifoldOptoBlocked => "Manifold opto blocked",
+            CoinAcceptorError::ManifoldNotReady => "Manifold not ready",
+            CoinAcceptorError::SecurityStatusChanged => "Security status changed",
+            CoinAcceptorError::MotorException => "Motor exception",
+            CoinAcceptorError::SwallowedCoin => "Swallowed coin",
+            CoinAcceptorError::CoinTooFastValidationSensor => {
+                "Coin too fast over the validation sensor"
+            }
+            CoinAcceptorError::CoinTooSlowValidationSensor => {
+                "Coin too slow over the validation sensor"
+            }
+            CoinAcceptorError::CoinIncorrectlySorted => "Coin incorrectly sorted",
+            CoinAcceptorError::ExternalLightAttack => "External light attack",
+            CoinAcceptorError::InhibitedCoinType(_) => "Inhibited coin type",
+            CoinAcceptorError::DataBlockRequest => "Data block request",
+            CoinAcceptorError::FlightDeckOpen => {
+                "Coin return mechanism activated, flight deck open"
+            }
+            CoinAcceptorError::UnspecifiedAlarm => "Unspecified alarm",
+            CoinAcceptorError::Unknown(_) => "Unknown error code",
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        match *self {
+            CoinAcceptorError::NullEvent => Severity::Status,
+            CoinAcceptorError::RejectCoin => Severity::Reject,
+            CoinAcceptorError::InhibitedCoin => Severity::Reject,
+            CoinAcceptorError::MultipleWindow => Severity::Reject,
+            CoinAcceptorError::WakeUpTimeout => Severity::Fault,
+            CoinAcceptorError::ValidationTimeout => Severity::Fault,
+            CoinAcceptorError::CreditSensorTimeout => Severity::Fault,
+            CoinAcceptorError::SorterOptoTimeout => Severity::Fault,
+            CoinAcceptorError::SecondCloseCoinError => Severity::Reject,
+            CoinAcceptorError::AcceptGateNotReady => Severity::Reject,
+            CoinAcceptorError::CreditSensorNotReady => Severity::Reject,
+            CoinAcceptorError::SorterNotReady => Severity::Reject,
+            CoinAcceptorError::RejectCoinNotCleared => Severity::Reject,
+            CoinAcceptorError::ValidationSensorNotReady => Severity::Reject,
+            CoinAcceptorError::CreditSensorBlocked => Severity::Fault,
+            CoinAcceptorError::SorterOptoBlocked => Severity::Fault,
+            CoinAcceptorError::CreditSequenceError => Severity::Fraud,
+            CoinAcceptorError::CoinGoingBackwards => Severity::Fraud,
+            CoinAcceptorError::CoinTooFastCreditSensor => Severity::Fraud,
+            CoinAcceptorError::CoinTooSlowCreditSensor => Severity::Fraud,
+            CoinAcceptorError::CoinOnStringActive => Severity::Fraud,
+            CoinAcceptorError::DCEOptoTimeout => Severity::Fault,
+            CoinAcceptorError::DCEOptoNotSeen => Severity::Fault,
+            CoinAcceptorError::CreditSensorReachedEarly => Severity::Fraud,
+            CoinAcceptorError::RejectCoinRepeatedly => Severity::Fraud,
+            CoinAcceptorError::RejectSlug => Severity::Fraud,
+            CoinAcceptorError::RejectSensorBlocked => Severity::Fault,
+            CoinAcceptorError::GamesOverload => Severity::Reject,
+            CoinAcceptorError::MaxCoinMeterPulsesExceeded => Severity::Reject,
+            CoinAcceptorError::AcceptGateOpenNotClosed => Severity::Fault,
+            CoinAcceptorError::AcceptGateClosedNotOpen => Severity::Fault,
+            CoinAcceptorError::ManifoldOptoTimeout => Severity::Fault,
+            CoinAcceptorError::ManifoldOptoBlocked => Severity::Fault,
+            CoinAcceptorError::ManifoldNotReady => Severity::Fault,
+            CoinAcceptorError::SecurityStatusChanged => Severity::Status,
+            CoinAcceptorError::MotorException => Severity::Fault,
+            CoinAcceptorError::SwallowedCoin => Severity::Fault,
+            CoinAcceptorError::CoinTooFastValidationSensor => Severity::Fraud,
+            CoinAcceptorError::CoinTooSlowValidationSensor => Severity::Fraud,
+            CoinAcceptorError::CoinIncorrectlySorted => Severity::Fault,
+            CoinAcceptorError::ExternalLightAttack => Severity::Fraud,
+            CoinAcceptorError::InhibitedCoinType(_) => Severity::Reject,
+            CoinAcceptorError::DataBlockRequest => Severity::Status,
+            CoinAcceptorError::FlightDeckOpen => Severity::Status,
+            CoinAcceptorError::UnspecifiedAlarm => Severity::Fault,
+            CoinAcceptorError::Unknown(_) => Severity::Fault,
+        }
+    }
+
+    /// Whether the coin went through to the cash box or a sorter path,
+    /// `None` when it may or may not have
+    ///
+    /// Accepted coins are not credited, so they are lost to the customer.
+    pub fn coin_accepted(&self) -> Option<bool> {
+        match *self {
+            CoinAcceptorError::NullEvent => Some(false),
+            CoinAcceptorError::RejectCoin => Some(false),
+            CoinAcceptorError::InhibitedCoin => Some(false),
+            CoinAcceptorError::MultipleWindow => Some(false),
+            CoinAcceptorError::WakeUpTimeout => None,
+            CoinAcceptorError::ValidationTimeout => None,
+            CoinAcceptorError::CreditSensorTimeout => None,
+            CoinAcceptorError::SorterOptoTimeout => Some(true),
+            CoinAcceptorError::SecondCloseCoinError => Some(false),
+            CoinAcceptorError::AcceptGateNotReady => Some(false),
+            CoinAcceptorError::CreditSensorNotReady => Some(false),
+            CoinAcceptorError::SorterNotReady => Some(false),
+            CoinAcceptorError::RejectCoinNotCleared => Some(false),
+            CoinAcceptorError::ValidationSensorNotReady => Some(false),
+            CoinAcceptorError::CreditSensorBlocked => Some(false),
+            CoinAcceptorError::SorterOptoBlocked => Some(false),
+            CoinAcceptorError::CreditSequenceError => Some(true),
+            CoinAcceptorError::CoinGoingBackwards => Some(true),
+            CoinAcceptorError::CoinTooFastCreditSensor => Some(true),
+            CoinAcceptorError::CoinTooSlowCreditSensor => Some(true),
+            CoinAcceptorError::CoinOnStringActive => Some(true),
+            CoinAcceptorError::DCEOptoTimeout => Some(false),
+            CoinAcceptorError::DCEOptoNotSeen => Some(false),
+            CoinAcceptorError::CreditSensorReachedEarly => Some(true),
+            CoinAcceptorError::RejectCoinRepeatedly => Some(false),
+            CoinAcceptorError::RejectSlug => Some(false),
+            CoinAcceptorError::RejectSensorBlocked => Some(false),
+            CoinAcceptorError::GamesOverload => Some(false),
+            CoinAcceptorError::MaxCoinMeterPulsesExceeded => Some(false),
+            CoinAcceptorError::AcceptGateOpenNotClosed => Some(true),
+            CoinAcceptorError::AcceptGateClosedNotOpen => Some(false),
+            CoinAcceptorError::ManifoldOptoTimeout => Some(true),
+            CoinAcceptorError::ManifoldOptoBlocked => Some(false),
+            CoinAcceptorError::ManifoldNotReady => Some(false),
+            CoinAcceptorError::SecurityStatusChanged => Some(false),
+            CoinAcceptorError::MotorException => None,
+            CoinAcceptorError::SwallowedCoin => Some(true),
+            CoinAcceptorError::CoinTooFastValidationSensor => Some(false),
+            CoinAcceptorError::CoinTooSlowValidationSensor => Some(false),
+            CoinAcceptorError::CoinIncorrectlySorted => Some(true),
+            CoinAcceptorError::ExternalLightAttack => None,
+            CoinAcceptorError::InhibitedCoinType(_) => Some(false),
+            CoinAcceptorError::DataBlockRequest => Some(false),
+            CoinAcceptorError::FlightDeckOpen => Some(false),
+            CoinAcceptorError::UnspecifiedAlarm => None,
+            CoinAcceptorError::Unknown(_) => None,
+        }
+    }
 }
 
+/// Bill validator event codes of the ccTalk generic specification, issue 4.7
+///
+/// Status codes after (0, 21) and result B over 1 for bill types aren't
+/// assigned by that issue, they are `Unknown`.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "NamedCode<(u8, u8)>", into = "NamedCode<(u8, u8)>")
+)]
 pub enum BillEvent {
-    BillTypeValidatedAndSent1,
-    BillTypeValidatedAndSent2,
-    BillTypeValidatedAndSent3,
-    BillTypeValidatedAndSent4,
-    BillTypeValidatedAndSent5,
-    BillTypeValidatedAndSent6,
-    BillTypeValidatedAndHeld1,
-    BillTypeValidatedAndHeld2,
-    BillTypeValidatedAndHeld3,
-    BillTypeValidatedAndHeld4,
-    BillTypeValidatedAndHeld5,
-    BillTypeValidatedAndHeld6,
+    /// Bill of the type validated and sent to the stacker
+    BillTypeValidatedAndSent(u8),
+    /// Bill of the type validated and held in escrow
+    BillTypeValidatedAndHeld(u8),
     MasterInhibitActive,
     BillReturnedFromEscrow,
     InvalidBillValidation,
@@ -707,18 +1465,8 @@ pub enum BillEvent {
 impl BillEvent {
     pub fn from_u8(n: (u8, u8)) -> BillEvent {
         match n {
-            (1, 0) => BillEvent::BillTypeValidatedAndSent1,
-            (2, 0) => BillEvent::BillTypeValidatedAndSent2,
-            (3, 0) => BillEvent::BillTypeValidatedAndSent3,
-            (4, 0) => BillEvent::BillTypeValidatedAndSent4,
-            (5, 0) => BillEvent::BillTypeValidatedAndSent5,
-            (6, 0) => BillEvent::BillTypeValidatedAndSent6,
-            (1, 1) => BillEvent::BillTypeValidatedAndHeld1,
-            (2, 1) => BillEvent::BillTypeValidatedAndHeld2,
-            (3, 1) => BillEvent::BillTypeValidatedAndHeld3,
-            (4, 1) => BillEvent::BillTypeValidatedAndHeld4,
-            (5, 1) => BillEvent::BillTypeValidatedAndHeld5,
-            (6, 1) => BillEvent::BillTypeValidatedAndHeld6,
+            (n @ 1..=255, 0) => BillEvent::BillTypeValidatedAndSent(n),
+            (n @ 1..=255, 1) => BillEvent::BillTypeValidatedAndHeld(n),
             (0, 0) => BillEvent::MasterInhibitActive,
             (0, 1) => BillEvent::BillReturnedFromEscrow,
             (0, 2) => BillEvent::InvalidBillValidation,
@@ -747,18 +1495,8 @@ impl BillEvent {
 
     pub fn to_u8(&self) -> (u8, u8) {
         match *self {
-            BillEvent::BillTypeValidatedAndSent1 => (1, 0),
-            BillEvent::BillTypeValidatedAndSent2 => (2, 0),
-            BillEvent::BillTypeValidatedAndSent3 => (3, 0),
-            BillEvent::BillTypeValidatedAndSent4 => (4, 0),
-            BillEvent::BillTypeValidatedAndSent5 => (5, 0),
-            BillEvent::BillTypeValidatedAndSent6 => (6, 0),
-            BillEvent::BillTypeValidatedAndHeld1 => (1, 1),
-            BillEvent::BillTypeValidatedAndHeld2 => (2, 1),
-            BillEvent::BillTypeValidatedAndHeld3 => (3, 1),
-            BillEvent::BillTypeValidatedAndHeld4 => (4, 1),
-            BillEvent::BillTypeValidatedAndHeld5 => (5, 1),
-            BillEvent::BillTypeValidatedAndHeld6 => (6, 1),
+            BillEvent::BillTypeValidatedAndSent(n) => (n, 0),
+            BillEvent::BillTypeValidatedAndHeld(n) => (n, 1),
             BillEvent::MasterInhibitActive => (0, 0),
             BillEvent::BillReturnedFromEscrow => (0, 1),
             BillEvent::InvalidBillValidation => (0, 2),
@@ -784,218 +1522,2216 @@ impl BillEvent {
             BillEvent::Unknown(a, b) => (a, b),
         }
     }
+
+    /// Variant name of the event, `Unknown` for unknown codes, without the
+    /// bill type
+    pub fn name(&self) -> &'static str {
+        match self {
+            BillEvent::BillTypeValidatedAndSent(_) => "BillTypeValidatedAndSent",
+            BillEvent::BillTypeValidatedAndHeld(_) => "BillTypeValidatedAndHeld",
+            BillEvent::MasterInhibitActive => "MasterInhibitActive",
+            BillEvent::BillReturnedFromEscrow => "BillReturnedFromEscrow",
+            BillEvent::InvalidBillValidation => "InvalidBillValidation",
+            BillEvent::InvalidBillTransport => "InvalidBillTransport",
+            BillEvent::InhibitedBillSerial => "InhibitedBillSerial",
+            BillEvent::InhibitedBillDIP => "InhibitedBillDIP",
+            BillEvent::BillJammedInTransportUnsafe => "BillJammedInTransportUnsafe",
+            BillEvent::BillJammedInStacker => "BillJammedInStacker",
+            BillEvent::BillPulledBackwards => "BillPulledBackwards",
+            BillEvent::BillTamper => "BillTamper",
+            BillEvent::StackerOK => "StackerOK",
+            BillEvent::StackerRemoved => "StackerRemoved",
+            BillEvent::StackerInserted => "StackerInserted",
+            BillEvent::StackerFaulty => "StackerFaulty",
+            BillEvent::StackerFull => "StackerFull",
+            BillEvent::StackerJammed => "StackerJammed",
+            BillEvent::BillJammedInTransportSafe => "BillJammedInTransportSafe",
+            BillEvent::OptoFraudDetected => "OptoFraudDetected",
+            BillEvent::StringFraudDetected => "StringFraudDetected",
+            BillEvent::AntiStringMechFaulty => "AntiStringMechFaulty",
+            BillEvent::BarcodeDetected => "BarcodeDetected",
+            BillEvent::UnknownBillTypeStacked => "UnknownBillTypeStacked",
+            BillEvent::Unknown(..) => "Unknown",
+        }
+    }
+
+    /// Event with the variant name `name`, `None` if there is none
+    ///
+    /// The bill type follows the name of validated bills, e.g.
+    /// `BillTypeValidatedAndHeld6`.
+    pub fn from_name(name: &str) -> Option<BillEvent> {
+        let bill_type = |bill_type: &str| match bill_type.parse() {
+            Ok(bill_type @ 1..=255) => Some(bill_type),
+            _ => None,
+        };
+        if let Some(n) = name.strip_prefix("BillTypeValidatedAndSent") {
+            return bill_type(n).map(BillEvent::BillTypeValidatedAndSent);
+        }
+        if let Some(n) = name.strip_prefix("BillTypeValidatedAndHeld") {
+            return bill_type(n).map(BillEvent::BillTypeValidatedAndHeld);
+        }
+        match name {
+            "MasterInhibitActive" => Some(BillEvent::MasterInhibitActive),
+            "BillReturnedFromEscrow" => Some(BillEvent::BillReturnedFromEscrow),
+            "InvalidBillValidation" => Some(BillEvent::InvalidBillValidation),
+            "InvalidBillTransport" => Some(BillEvent::InvalidBillTransport),
+            "InhibitedBillSerial" => Some(BillEvent::InhibitedBillSerial),
+            "InhibitedBillDIP" => Some(BillEvent::InhibitedBillDIP),
+            "BillJammedInTransportUnsafe" => Some(BillEvent::BillJammedInTransportUnsafe),
+            "BillJammedInStacker" => Some(BillEvent::BillJammedInStacker),
+            "BillPulledBackwards" => Some(BillEvent::BillPulledBackwards),
+            "BillTamper" => Some(BillEvent::BillTamper),
+            "StackerOK" => Some(BillEvent::StackerOK),
+            "StackerRemoved" => Some(BillEvent::StackerRemoved),
+            "StackerInserted" => Some(BillEvent::StackerInserted),
+            "StackerFaulty" => Some(BillEvent::StackerFaulty),
+            "StackerFull" => Some(BillEvent::StackerFull),
+            "StackerJammed" => Some(BillEvent::StackerJammed),
+            "BillJammedInTransportSafe" => Some(BillEvent::BillJammedInTransportSafe),
+            "OptoFraudDetected" => Some(BillEvent::OptoFraudDetected),
+            "StringFraudDetected" => Some(BillEvent::StringFraudDetected),
+            "AntiStringMechFaulty" => Some(BillEvent::AntiStringMechFaulty),
+            "BarcodeDetected" => Some(BillEvent::BarcodeDetected),
+            "UnknownBillTypeStacked" => Some(BillEvent::UnknownBillTypeStacked),
+            _ => None,
+        }
+    }
+
+    /// Text of the ccTalk bill event table
+    pub fn description(&self) -> &'static str {
+        match *self {
+            BillEvent::BillTypeValidatedAndSent(_) => "Bill validated and sent to the stacker",
+            BillEvent::BillTypeValidatedAndHeld(_) => "Bill validated and held in escrow",
+            BillEvent::MasterInhibitActive => "Master inhibit active",
+            BillEvent::BillReturnedFromEscrow => "Bill returned from escrow",
+            BillEvent::InvalidBillValidation => "Invalid bill, validation fail",
+            BillEvent::InvalidBillTransport => "Invalid bill, transport problem",
+            BillEvent::InhibitedBillSerial => "Inhibited bill, on serial",
+            BillEvent::InhibitedBillDIP => "Inhibited bill, on DIP switches",
+            BillEvent::BillJammedInTransportUnsafe => "Bill jammed in transport, unsafe mode",
+            BillEvent::BillJammedInStacker => "Bill jammed in stacker",
+            BillEvent::BillPulledBackwards => "Bill pulled backwards",
+            BillEvent::BillTamper => "Bill tamper",
+            BillEvent::StackerOK => "Stacker OK",
+            BillEvent::StackerRemoved => "Stacker removed",
+            BillEvent::StackerInserted => "Stacker inserted",
+            BillEvent::StackerFaulty => "Stacker faulty",
+            BillEvent::StackerFull => "Stacker full",
+            BillEvent::StackerJammed => "Stacker jammed",
+            BillEvent::BillJammedInTransportSafe => "Bill jammed in transport, safe mode",
+            BillEvent::OptoFraudDetected => "Opto fraud detected",
+            BillEvent::StringFraudDetected => "String fraud detected",
+            BillEvent::AntiStringMechFaulty => "Anti-string mechanism faulty",
+            BillEvent::BarcodeDetected => "Barcode detected",
+            BillEvent::UnknownBillTypeStacked => "Unknown bill type stacked",
+            BillEvent::Unknown(..) => "Unknown bill event",
+        }
+    }
+
+    /// The event type column of the ccTalk bill event table, validated
+    /// bills are status events
+    pub fn severity(&self) -> Severity {
+        match *self {
+            BillEvent::BillTypeValidatedAndSent(_) => Severity::Status,
+            BillEvent::BillTypeValidatedAndHeld(_) => Severity::Status,
+            BillEvent::MasterInhibitActive => Severity::Status,
+            BillEvent::BillReturnedFromEscrow => Severity::Status,
+            BillEvent::InvalidBillValidation => Severity::Reject,
+            BillEvent::InvalidBillTransport => Severity::Reject,
+            BillEvent::InhibitedBillSerial => Severity::Reject,
+            BillEvent::InhibitedBillDIP => Severity::Reject,
+            BillEvent::BillJammedInTransportUnsafe => Severity::Fault,
+            BillEvent::BillJammedInStacker => Severity::Fault,
+            BillEvent::BillPulledBackwards => Severity::Fraud,
+            BillEvent::BillTamper => Severity::Fraud,
+            BillEvent::StackerOK => Severity::Status,
+            BillEvent::StackerRemoved => Severity::Status,
+            BillEvent::StackerInserted => Severity::Status,
+            BillEvent::StackerFaulty => Severity::Fault,
+            BillEvent::StackerFull => Severity::Status,
+            BillEvent::StackerJammed => Severity::Fault,
+            BillEvent::BillJammedInTransportSafe => Severity::Fault,
+            BillEvent::OptoFraudDetected => Severity::Fraud,
+            BillEvent::StringFraudDetected => Severity::Fraud,
+            BillEvent::AntiStringMechFaulty => Severity::Fault,
+            BillEvent::BarcodeDetected => Severity::Status,
+            BillEvent::UnknownBillTypeStacked => Severity::Status,
+            BillEvent::Unknown(..) => Severity::Fault,
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
-pub struct Payload {
-    pub header: HeaderType,
-    pub data: Data,
+/// Six character coin identifier, e.g. `EU200A`
+///
+/// Format is two letter country code, three character value and one
+/// character issue code. Constructors only check the length and ASCII
+/// encoding, use `is_valid` to check the format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoinId([u8; 6]);
+
+impl CoinId {
+    pub fn new(coin_id: &str) -> Result<CoinId, ErrorType> {
+        CoinId::from_bytes(coin_id.as_bytes())
+    }
+
+    pub fn from_bytes(raw: &[u8]) -> Result<CoinId, ErrorType> {
+        if raw.len() != 6 || !raw.is_ascii() {
+            return Err(ErrorType::ParseError);
+        }
+        let mut coin_id = [0u8; 6];
+        coin_id.copy_from_slice(raw);
+        Ok(CoinId(coin_id))
+    }
+
+    /// Checks the coin id format
+    ///
+    /// Value may contain a decimal point or multiplier (`m`, `K`, `M`, `G`),
+    /// e.g. `EU1K0A` or `GB.50A`. Empty channel `......` is also valid.
+    pub fn is_valid(&self) -> bool {
+        if *self == CoinId::default() {
+            return true;
+        }
+        let (country, rest) = self.0.split_at(2);
+        let (value, issue) = rest.split_at(3);
+        country.iter().all(u8::is_ascii_uppercase)
+            && value.iter().any(u8::is_ascii_digit)
+            && value
+                .iter()
+                .all(|c| c.is_ascii_digit() || b".mKMG".contains(c))
+            && issue[0].is_ascii_uppercase()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Constructors only accept ASCII
+        str::from_utf8(&self.0).unwrap_or("")
+    }
 }
 
-impl Payload {
-    pub fn encode(&self) -> Vec<u8> {
-        let mut data_temp = self.data.clone();
-        data_temp.insert(0, self.header.to_u8());
-        data_temp
+impl Default for CoinId {
+    /// Empty coin channel
+    fn default() -> Self {
+        CoinId(*b"......")
     }
+}
 
-    pub fn sum(&self) -> u16 {
-        let payload = self.encode();
-        let mut payload_sum = 0u16;
-        for num in &payload {
-            payload_sum += *num as u16;
+/// 24-bit device serial number, sent as 3 bytes LSB first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "u32", into = "u32")
+)]
+pub struct SerialNumber(u32);
+
+impl SerialNumber {
+    pub const MAX: u32 = 0xFF_FFFF;
+
+    pub fn new(serial_number: u32) -> Result<SerialNumber, ErrorType> {
+        if serial_number > SerialNumber::MAX {
+            return Err(ErrorType::ParseError);
         }
-        payload_sum
+        Ok(SerialNumber(serial_number))
     }
 
-    pub fn as_str(&self) -> Result<String, str::Utf8Error> {
-        let res = str::from_utf8(&self.data);
-        match res {
-            Ok(result) => Ok(String::from(result)),
-            Err(e) => Err(e),
+    pub fn from_bytes(raw: &[u8]) -> Result<SerialNumber, ErrorType> {
+        match *raw {
+            [lsb, mid, msb] => Ok(SerialNumber(u32::from_le_bytes([lsb, mid, msb, 0]))),
+            _ => Err(ErrorType::ParseError),
         }
     }
+
+    pub fn to_bytes(&self) -> [u8; 3] {
+        let [lsb, mid, msb, _] = self.0.to_le_bytes();
+        [lsb, mid, msb]
+    }
+
+    pub fn value(&self) -> u32 {
+        self.0
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum ErrorType {
-    PartialMessage,
-    ChecksumError,
-    NotAReply,
-    NoResponse,
-    ParseError,
+impl convert::TryFrom<u32> for SerialNumber {
+    type Error = ErrorType;
+
+    fn try_from(serial_number: u32) -> Result<Self, Self::Error> {
+        SerialNumber::new(serial_number)
+    }
 }
 
-#[derive(Debug, Clone)]
-pub struct Message {
-    pub destination: Address,
-    pub length: u8,
-    pub source: Address,
-    pub payload: Payload,
-    pub checksum_type: ChecksumType,
+impl convert::From<SerialNumber> for u32 {
+    fn from(serial_number: SerialNumber) -> u32 {
+        serial_number.0
+    }
 }
 
-impl Message {
-    pub fn new(
-        destination: Address,
-        source: Address,
-        payload: Payload,
-        checksum_type: ChecksumType,
-    ) -> Message {
-        let length = payload.data.len();
-        Message {
-            destination: destination,
-            length: length as u8,
-            source: source,
-            payload: payload,
-            checksum_type: checksum_type,
+impl fmt::Display for SerialNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Base year of date codes when the device doesn't report one
+pub const DEFAULT_BASE_YEAR: u16 = 2000;
+
+/// Calendar date of a ccTalk date code
+///
+/// On the wire a date code is 2 bytes LSB first, with the day in bits 0-4,
+/// the month in bits 5-8 and the years since the base year in bits 9-15.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "String", into = "String")
+)]
+pub struct DateCode {
+    year: u16,
+    month: u8,
+    day: u8,
+}
+
+impl DateCode {
+    pub fn new(year: u16, month: u8, day: u8) -> Result<DateCode, ErrorType> {
+        if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+            return Err(ErrorType::ParseError);
         }
+        Ok(DateCode { year, month, day })
     }
 
-    pub fn encode(&self) -> Vec<u8> {
-        let mut temp = Vec::<u8>::new();
-        temp.push(self.destination);
-        temp.push(self.length);
+    /// Parse a date code relative to `base_year`
+    pub fn from_bytes(raw: &[u8], base_year: u16) -> Result<DateCode, ErrorType> {
+        let code = match *raw {
+            [lsb, msb] => u16::from_le_bytes([lsb, msb]),
+            _ => return Err(ErrorType::ParseError),
+        };
+        let year = base_year
+            .checked_add(code >> 9)
+            .ok_or(ErrorType::ParseError)?;
+        DateCode::new(year, ((code >> 5) & 0x0F) as u8, (code & 0x1F) as u8)
+    }
 
-        match self.checksum_type {
-            ChecksumType::SimpleChecksum => {
-                temp.push(self.source);
-                temp.append(&mut self.payload.encode());
-                temp.push(self.calc_checksum());
-            }
-            ChecksumType::CRCChecksum => {
-                let crc = self.calc_own_crc();
-                temp.push(crc[0]);
-                temp.append(&mut self.payload.encode());
-                temp.push(crc[1]);
+    /// Encode relative to `base_year`, which must be at most 127 years before
+    pub fn to_bytes(&self, base_year: u16) -> Result<[u8; 2], ErrorType> {
+        let years = match self.year.checked_sub(base_year) {
+            Some(years @ 0..=127) => years,
+            _ => return Err(ErrorType::ParseError),
+        };
+        Ok(((years << 9) | (self.month as u16) << 5 | self.day as u16).to_le_bytes())
+    }
+
+    /// Parse `YYYY-MM-DD`
+    pub fn parse(date: &str) -> Result<DateCode, ErrorType> {
+        let mut parts = date.splitn(3, '-').map(|part| part.parse::<u16>());
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(Ok(year)), Some(Ok(month)), Some(Ok(day))) if month < 256 && day < 256 => {
+                DateCode::new(year, month as u8, day as u8)
             }
+            _ => Err(ErrorType::ParseError),
         }
+    }
 
-        temp
+    pub fn year(&self) -> u16 {
+        self.year
     }
 
-    pub fn decode(raw: &mut Vec<u8>) -> Result<Message, ErrorType> {
-        // log::debug!("Decoding raw: {:?}", raw);
+    pub fn month(&self) -> u8 {
+        self.month
+    }
 
-        let msg_length = raw.len() as u16;
+    pub fn day(&self) -> u8 {
+        self.day
+    }
 
-        if msg_length < 2 {
-            return Err(ErrorType::PartialMessage);
-        }
+    /// Days since 1970-01-01
+    fn days_since_epoch(&self) -> i64 {
+        // Days from civil, shifted so the year starts in March
+        let year = self.year as i64 - (self.month <= 2) as i64;
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let month = (self.month as i64 + 9) % 12;
+        let day_of_year = (153 * month + 2) / 5 + self.day as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * 146_097 + day_of_era - 719_468
+    }
 
-        let data_length = raw[1];
-        let expected_msg_length = data_length as u16 + 5;
+    fn from_days_since_epoch(days: i64) -> DateCode {
+        let days = days + 719_468;
+        let era = days.div_euclid(146_097);
+        let day_of_era = days - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * month + 2) / 5 + 1) as u8;
+        let month = if month < 10 { month + 3 } else { month - 9 } as u8;
+        let year = (year_of_era + era * 400 + (month <= 2) as i64) as u16;
+        DateCode { year, month, day }
+    }
+}
 
-        if msg_length < expected_msg_length {
-            return Err(ErrorType::PartialMessage);
-        };
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        4 | 6 | 9 | 11 => 30,
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        _ => 31,
+    }
+}
 
-        let mut raw_msg: Vec<u8> = raw.drain(0..expected_msg_length as usize).collect();
-        // don't touch raw after this, it's the next message
+impl fmt::Display for DateCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
 
-        let checksum_type: ChecksumType;
-        let source: u8;
+#[cfg(feature = "alloc")]
+impl convert::TryFrom<String> for DateCode {
+    type Error = ErrorType;
 
-        if Message::validate_checksum(&raw_msg) {
-            checksum_type = ChecksumType::SimpleChecksum;
-            source = raw_msg[2];
-        } else {
-            if Message::validate_crc(&raw_msg) {
-                checksum_type = ChecksumType::CRCChecksum;
-                source = 1; // Source address is always 1 in CRC mode
-            } else {
-                log::error!("Failed raw: {:?}", raw_msg);
-                return Err(ErrorType::ChecksumError);
-            }
-        }
+    fn try_from(date: String) -> Result<Self, Self::Error> {
+        DateCode::parse(&date)
+    }
+}
 
-        let mut raw_data = raw_msg.split_off(4);
+#[cfg(feature = "alloc")]
+impl convert::From<DateCode> for String {
+    fn from(date: DateCode) -> String {
+        date.to_string()
+    }
+}
 
-        // remove checksum
-        raw_data.pop();
+/// Parse the reply of `RequestBaseYear`, 4 ASCII digits
+pub fn parse_base_year(raw: &[u8]) -> Result<u16, ErrorType> {
+    if raw.len() != 4 {
+        return Err(ErrorType::ParseError);
+    }
+    str::from_utf8(raw)
+        .ok()
+        .and_then(|year| year.parse().ok())
+        .ok_or(ErrorType::ParseError)
+}
 
-        let destination = raw_msg[0];
-        let header_int = raw_msg[3];
-        let payload = Payload {
-            header: HeaderType::from_u8(header_int),
-            data: raw_data,
-        };
+/// Real time clock value, seconds since 1970-01-01 00:00:00 UTC
+///
+/// Sent as 4 bytes LSB first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RealTimeClock(u32);
 
-        Ok(Message {
-            destination: destination,
-            length: data_length,
-            source: source,
-            payload: payload,
-            checksum_type: checksum_type,
-        })
+impl RealTimeClock {
+    pub fn new(seconds: u32) -> RealTimeClock {
+        RealTimeClock(seconds)
     }
 
-    pub fn calc_checksum(&self) -> u8 {
-        let payload_sum = self.payload.sum();
+    /// Clock at midnight of `date` plus the time of day
+    pub fn from_date_time(
+        date: DateCode,
+        hour: u8,
+        minute: u8,
+        second: u8,
+    ) -> Result<RealTimeClock, ErrorType> {
+        if hour > 23 || minute > 59 || second > 59 {
+            return Err(ErrorType::ParseError);
+        }
+        let seconds = date.days_since_epoch() * 86_400
+            + hour as i64 * 3600
+            + minute as i64 * 60
+            + second as i64;
+        convert::TryFrom::try_from(seconds)
+            .map(RealTimeClock)
+            .map_err(|_| ErrorType::ParseError)
+    }
 
-        let sum: u16 =
-            payload_sum + self.destination as u16 + self.length as u16 + self.source as u16;
+    pub fn from_bytes(raw: &[u8]) -> Result<RealTimeClock, ErrorType> {
+        match *raw {
+            [b0, b1, b2, b3] => Ok(RealTimeClock(u32::from_le_bytes([b0, b1, b2, b3]))),
+            _ => Err(ErrorType::ParseError),
+        }
+    }
 
-        let checksum = sum % 256;
+    pub fn to_bytes(&self) -> [u8; 4] {
+        self.0.to_le_bytes()
+    }
 
-        if checksum == 0 {
-            0u8
-        } else {
-            (256 - checksum) as u8
-        }
+    pub fn seconds(&self) -> u32 {
+        self.0
     }
 
-    pub fn calc_own_crc(&self) -> CRC {
-        let mut data = Vec::<u8>::new();
+    pub fn date(&self) -> DateCode {
+        DateCode::from_days_since_epoch((self.0 / 86_400) as i64)
+    }
 
-        data.push(self.destination);
-        data.push(self.length);
-        data.append(&mut self.payload.encode());
+    /// Hour, minute and second
+    pub fn time_of_day(&self) -> (u8, u8, u8) {
+        let seconds = self.0 % 86_400;
+        (
+            (seconds / 3600) as u8,
+            (seconds / 60 % 60) as u8,
+            (seconds % 60) as u8,
+        )
+    }
+}
 
-        Message::calc_crc(&data)
+impl fmt::Display for RealTimeClock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (hour, minute, second) = self.time_of_day();
+        write!(f, "{} {:02}:{:02}:{:02}", self.date(), hour, minute, second)
     }
+}
 
-    pub fn calc_crc(data: &Vec<u8>) -> CRC {
-        let poly = 0x1021;
-        let mut crc = 0u16;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TeachStatus {
+    Aborted,
+    Error,
+    InProgress,
+    Completed,
+    Unknown(u8),
+}
 
-        for byte in data {
-            crc ^= (*byte as u16) << 8 & 0xffff;
-            for _ in 0..8 {
-                if (crc & 0x8000) != 0 {
-                    crc = ((crc << 1) ^ poly) & 0xffff;
-                } else {
-                    crc <<= 1;
-                    crc &= 0xffff;
-                }
-            }
+impl TeachStatus {
+    pub fn from_u8(n: u8) -> TeachStatus {
+        match n {
+            252 => TeachStatus::Aborted,
+            253 => TeachStatus::Error,
+            254 => TeachStatus::InProgress,
+            255 => TeachStatus::Completed,
+            _ => TeachStatus::Unknown(n),
         }
-        [(crc & 0xff) as u8, (crc >> 8 & 0xff) as u8]
     }
 
-    pub fn validate_checksum(raw: &Vec<u8>) -> bool {
-        if raw.is_empty() {
-            log::error!("Validate checksum called on empty message!");
-            return false;
+    pub fn to_u8(&self) -> u8 {
+        match *self {
+            TeachStatus::Aborted => 252,
+            TeachStatus::Error => 253,
+            TeachStatus::InProgress => 254,
+            TeachStatus::Completed => 255,
+            TeachStatus::Unknown(n) => n,
+        }
+    }
+}
+
+/// Memory type of the data storage, tells when stored data is lost
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MemoryType {
+    /// Volatile, lost on reset
+    VolatileOnReset,
+    /// Volatile, lost on power down
+    VolatileOnPowerDown,
+    /// Permanent, limited number of writes
+    PermanentLimited,
+    /// Permanent, unlimited number of writes
+    PermanentUnlimited,
+    Unknown(u8),
+}
+
+impl MemoryType {
+    pub fn from_u8(n: u8) -> MemoryType {
+        match n {
+            0 => MemoryType::VolatileOnReset,
+            1 => MemoryType::VolatileOnPowerDown,
+            2 => MemoryType::PermanentLimited,
+            3 => MemoryType::PermanentUnlimited,
+            _ => MemoryType::Unknown(n),
         }
+    }
 
-        let mut sum = 0u16;
-        for num in raw {
-            sum += *num as u16;
+    pub fn to_u8(&self) -> u8 {
+        match *self {
+            MemoryType::VolatileOnReset => 0,
+            MemoryType::VolatileOnPowerDown => 1,
+            MemoryType::PermanentLimited => 2,
+            MemoryType::PermanentUnlimited => 3,
+            MemoryType::Unknown(n) => n,
         }
-        let rem = sum % 256;
-        rem == 0
     }
 
-    pub fn validate_crc(raw: &Vec<u8>) -> bool {
-        if raw.is_empty() {
-            log::error!("Validate CRC called on empty message!");
-            return false;
+    pub fn is_permanent(&self) -> bool {
+        matches!(
+            self,
+            MemoryType::PermanentLimited | MemoryType::PermanentUnlimited
+        )
+    }
+}
+
+/// Data storage layout, the reply of `RequestDataStorageAvailability`
+///
+/// Read and write blocks address the same memory, a block size of 0
+/// means there is no read or write access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DataStorage {
+    pub memory_type: MemoryType,
+    pub read_blocks: u8,
+    pub read_bytes_per_block: u8,
+    pub write_blocks: u8,
+    pub write_bytes_per_block: u8,
+}
+
+impl Default for DataStorage {
+    /// No data storage
+    fn default() -> Self {
+        DataStorage {
+            memory_type: MemoryType::VolatileOnReset,
+            read_blocks: 0,
+            read_bytes_per_block: 0,
+            write_blocks: 0,
+            write_bytes_per_block: 0,
+        }
+    }
+}
+
+impl DataStorage {
+    pub fn from_bytes(data: &[u8]) -> Result<DataStorage, ErrorType> {
+        match *data {
+            [memory_type, read_blocks, read_bytes_per_block, write_blocks, write_bytes_per_block] => {
+                Ok(DataStorage {
+                    memory_type: MemoryType::from_u8(memory_type),
+                    read_blocks,
+                    read_bytes_per_block,
+                    write_blocks,
+                    write_bytes_per_block,
+                })
+            }
+            _ => Err(ErrorType::ParseError),
         }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 5] {
+        [
+            self.memory_type.to_u8(),
+            self.read_blocks,
+            self.read_bytes_per_block,
+            self.write_blocks,
+            self.write_bytes_per_block,
+        ]
+    }
+
+    /// Size of the memory covered by the read and write blocks
+    pub fn size(&self) -> usize {
+        let read = self.read_blocks as usize * self.read_bytes_per_block as usize;
+        let write = self.write_blocks as usize * self.write_bytes_per_block as usize;
+        read.max(write)
+    }
+}
+
+/// Device variables of an equipment category
+///
+/// `RequestVariableSet` replies with all variables, `ModifyVariableSet`
+/// only takes the writable ones, which come first.
+#[cfg(feature = "alloc")]
+pub trait VariableSet: Sized {
+    fn from_bytes(data: &[u8]) -> Result<Self, ErrorType>;
+    /// Data of `ModifyVariableSet`
+    fn to_modify_bytes(&self) -> Data;
+    /// Data of the `RequestVariableSet` reply
+    fn to_bytes(&self) -> Data;
+}
+
+/// Coin acceptor variable set
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CoinAcceptorVariables {
+    /// Reject coins while the host has not read the full credit buffer
+    pub inhibit_on_full_buffer: bool,
+    /// Number of events in the credit buffer, read-only
+    pub buffer_size: u8,
+}
+
+#[cfg(feature = "alloc")]
+impl VariableSet for CoinAcceptorVariables {
+    fn from_bytes(data: &[u8]) -> Result<Self, ErrorType> {
+        match *data {
+            [inhibit_on_full_buffer, buffer_size, ..] => Ok(CoinAcceptorVariables {
+                inhibit_on_full_buffer: inhibit_on_full_buffer != 0,
+                buffer_size,
+            }),
+            [inhibit_on_full_buffer] => Ok(CoinAcceptorVariables {
+                inhibit_on_full_buffer: inhibit_on_full_buffer != 0,
+                buffer_size: 0,
+            }),
+            _ => Err(ErrorType::ParseError),
+        }
+    }
+
+    fn to_modify_bytes(&self) -> Data {
+        vec![self.inhibit_on_full_buffer as u8]
+    }
+
+    fn to_bytes(&self) -> Data {
+        vec![self.inhibit_on_full_buffer as u8, self.buffer_size]
+    }
+}
+
+/// Payout (hopper) variable set
+///
+/// Host side only, there is no hopper emulator to answer it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HopperVariables {
+    /// Motor current limit, device specific units
+    pub current_limit: u8,
+    /// Delay before the motor stops after the last coin, in ms
+    pub motor_stop_delay: u8,
+    /// Payout gives up when no coin is seen for this time, in 100ms
+    pub payout_timeout: u8,
+    /// Highest motor current measured, read-only
+    pub max_current: u8,
+    /// Supply voltage, device specific units, read-only
+    pub supply_voltage: u8,
+    /// Address selected by the connector, read-only
+    pub connector_address: u8,
+}
+
+#[cfg(feature = "alloc")]
+impl VariableSet for HopperVariables {
+    fn from_bytes(data: &[u8]) -> Result<Self, ErrorType> {
+        if data.len() < 3 {
+            return Err(ErrorType::ParseError);
+        }
+        let read_only = |i: usize| data.get(i).copied().unwrap_or(0);
+        Ok(HopperVariables {
+            current_limit: data[0],
+            motor_stop_delay: data[1],
+            payout_timeout: data[2],
+            max_current: read_only(3),
+            supply_voltage: read_only(4),
+            connector_address: read_only(5),
+        })
+    }
+
+    fn to_modify_bytes(&self) -> Data {
+        vec![
+            self.current_limit,
+            self.motor_stop_delay,
+            self.payout_timeout,
+        ]
+    }
+
+    fn to_bytes(&self) -> Data {
+        vec![
+            self.current_limit,
+            self.motor_stop_delay,
+            self.payout_timeout,
+            self.max_current,
+            self.supply_voltage,
+            self.connector_address,
+        ]
+    }
+}
+
+/// Coin acceptor option flags, reply to `RequestOptionFlags`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CoinAcceptorOptions {
+    /// Credits are reported as coin values instead of coin positions
+    pub coin_value_format: bool,
+}
+
+impl CoinAcceptorOptions {
+    pub fn from_u8(n: u8) -> CoinAcceptorOptions {
+        CoinAcceptorOptions {
+            coin_value_format: n & 1 != 0,
+        }
+    }
+
+    pub fn to_u8(&self) -> u8 {
+        self.coin_value_format as u8
+    }
+}
+
+/// Bill validator option flags, reply to `RequestOptionFlags`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BillValidatorOptions {
+    pub stacker: bool,
+    pub escrow: bool,
+    pub individual_accept_counters: bool,
+    pub individual_error_counters: bool,
+    pub non_volatile_counters: bool,
+    pub bill_teach: bool,
+    pub bill_security_tuning: bool,
+    pub remote_bill_programming: bool,
+}
+
+impl BillValidatorOptions {
+    pub fn from_u8(n: u8) -> BillValidatorOptions {
+        let bit = |i: u8| n & (1 << i) != 0;
+        BillValidatorOptions {
+            stacker: bit(0),
+            escrow: bit(1),
+            individual_accept_counters: bit(2),
+            individual_error_counters: bit(3),
+            non_volatile_counters: bit(4),
+            bill_teach: bit(5),
+            bill_security_tuning: bit(6),
+            remote_bill_programming: bit(7),
+        }
+    }
+
+    pub fn to_u8(&self) -> u8 {
+        [
+            self.stacker,
+            self.escrow,
+            self.individual_accept_counters,
+            self.individual_error_counters,
+            self.non_volatile_counters,
+            self.bill_teach,
+            self.bill_security_tuning,
+            self.remote_bill_programming,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |flags, (i, &set)| flags | ((set as u8) << i))
+    }
+}
+
+/// How the device address is stored and can be changed, reply to
+/// `RequestAddressMode`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct AddressMode {
+    pub rom: bool,
+    pub ram: bool,
+    /// EEPROM or battery backed RAM
+    pub eeprom: bool,
+    pub interface_connector: bool,
+    pub pcb_links: bool,
+    pub switch: bool,
+    /// Changed by serial commands, lost on power down
+    pub serial_volatile: bool,
+    /// Changed by serial commands and kept over power down
+    pub serial_non_volatile: bool,
+}
+
+impl AddressMode {
+    pub fn from_u8(n: u8) -> AddressMode {
+        let bit = |i: u8| n & (1 << i) != 0;
+        AddressMode {
+            rom: bit(0),
+            ram: bit(1),
+            eeprom: bit(2),
+            interface_connector: bit(3),
+            pcb_links: bit(4),
+            switch: bit(5),
+            serial_volatile: bit(6),
+            serial_non_volatile: bit(7),
+        }
+    }
+
+    pub fn to_u8(&self) -> u8 {
+        [
+            self.rom,
+            self.ram,
+            self.eeprom,
+            self.interface_connector,
+            self.pcb_links,
+            self.switch,
+            self.serial_volatile,
+            self.serial_non_volatile,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |flags, (i, &set)| flags | ((set as u8) << i))
+    }
+}
+
+/// Unit of the polling priority value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PollingUnit {
+    /// Interval given in the product manual, e.g. polling on a hardware
+    /// event line
+    Special,
+    Milliseconds,
+    TenMilliseconds,
+    Seconds,
+    Minutes,
+    Hours,
+    Days,
+    Weeks,
+    Months,
+    Years,
+    Unknown(u8),
+}
+
+impl PollingUnit {
+    pub fn from_u8(n: u8) -> PollingUnit {
+        match n {
+            0 => PollingUnit::Special,
+            1 => PollingUnit::Milliseconds,
+            2 => PollingUnit::TenMilliseconds,
+            3 => PollingUnit::Seconds,
+            4 => PollingUnit::Minutes,
+            5 => PollingUnit::Hours,
+            6 => PollingUnit::Days,
+            7 => PollingUnit::Weeks,
+            8 => PollingUnit::Months,
+            9 => PollingUnit::Years,
+            _ => PollingUnit::Unknown(n),
+        }
+    }
+
+    pub fn to_u8(&self) -> u8 {
+        match *self {
+            PollingUnit::Special => 0,
+            PollingUnit::Milliseconds => 1,
+            PollingUnit::TenMilliseconds => 2,
+            PollingUnit::Seconds => 3,
+            PollingUnit::Minutes => 4,
+            PollingUnit::Hours => 5,
+            PollingUnit::Days => 6,
+            PollingUnit::Weeks => 7,
+            PollingUnit::Months => 8,
+            PollingUnit::Years => 9,
+            PollingUnit::Unknown(n) => n,
+        }
+    }
+
+    /// Length of one unit, `None` for special and unknown units
+    ///
+    /// Months are 30 and years 365 days.
+    pub fn duration(&self) -> Option<Duration> {
+        let seconds = |n: u64| Some(Duration::from_secs(n));
+        match *self {
+            PollingUnit::Milliseconds => Some(Duration::from_millis(1)),
+            PollingUnit::TenMilliseconds => Some(Duration::from_millis(10)),
+            PollingUnit::Seconds => seconds(1),
+            PollingUnit::Minutes => seconds(60),
+            PollingUnit::Hours => seconds(60 * 60),
+            PollingUnit::Days => seconds(24 * 60 * 60),
+            PollingUnit::Weeks => seconds(7 * 24 * 60 * 60),
+            PollingUnit::Months => seconds(30 * 24 * 60 * 60),
+            PollingUnit::Years => seconds(365 * 24 * 60 * 60),
+            PollingUnit::Special | PollingUnit::Unknown(_) => None,
+        }
+    }
+}
+
+/// Recommended polling interval, reply to `RequestPollingPriority`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PollingPriority {
+    pub unit: PollingUnit,
+    pub value: u8,
+}
+
+/// Polling priority of emulated devices when none is configured, 200ms
+pub const DEFAULT_POLLING_PRIORITY: PollingPriority = PollingPriority {
+    unit: PollingUnit::TenMilliseconds,
+    value: 20,
+};
+
+impl PollingPriority {
+    pub fn new(unit: PollingUnit, value: u8) -> PollingPriority {
+        PollingPriority { unit, value }
+    }
+
+    /// Unit followed by the value
+    pub fn from_bytes(raw: &[u8]) -> Result<PollingPriority, ErrorType> {
+        match *raw {
+            [unit, value] => Ok(PollingPriority::new(PollingUnit::from_u8(unit), value)),
+            _ => Err(ErrorType::ParseError),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 2] {
+        [self.unit.to_u8(), self.value]
+    }
+
+    /// The interval, `None` if it's special or the unit is unknown
+    pub fn interval(&self) -> Option<Duration> {
+        self.unit
+            .duration()
+            .map(|unit| unit * u32::from(self.value))
+    }
+}
+
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Payload {
+    pub header: HeaderType,
+    pub data: Data,
+}
+
+#[cfg(feature = "alloc")]
+impl Payload {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut raw = Vec::with_capacity(self.data.len() + 1);
+        raw.push(self.header.to_u8());
+        raw.extend_from_slice(&self.data);
+        raw
+    }
+
+    pub fn sum(&self) -> u16 {
+        self.data
+            .iter()
+            .fold(self.header.to_u8() as u16, |sum, &byte| {
+                sum.wrapping_add(byte as u16)
+            })
+    }
+
+    pub fn as_str(&self) -> Result<String, str::Utf8Error> {
+        let res = str::from_utf8(&self.data);
+        match res {
+            Ok(result) => Ok(String::from(result)),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ErrorType {
+    PartialMessage,
+    ChecksumError,
+    /// The frame is valid with both checksum types
+    AmbiguousChecksum,
+    NotAReply,
+    Nak,
+    Busy,
+    NoResponse,
+    ParseError,
+    /// More than 255 data bytes, the length doesn't fit in its byte
+    PayloadTooLong,
+    /// A BNV code is used without the BNV algorithm to apply it
+    NoBnvAlgorithm,
+}
+
+impl fmt::Display for ErrorType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "MessageFields", into = "MessageFields")
+)]
+pub struct Message {
+    pub destination: Address,
+    pub length: u8,
+    /// CRC frames have no source address, it's `None` when decoded from one
+    pub source: Option<Address>,
+    pub payload: Payload,
+    pub checksum_type: ChecksumType,
+}
+
+/// Serialized form of a message, without the length that `Message::new`
+/// derives from the payload
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct MessageFields {
+    destination: Address,
+    source: Option<Address>,
+    payload: Payload,
+    checksum_type: ChecksumType,
+}
+
+#[cfg(feature = "serde")]
+impl convert::From<Message> for MessageFields {
+    fn from(msg: Message) -> MessageFields {
+        MessageFields {
+            destination: msg.destination,
+            source: msg.source,
+            payload: msg.payload,
+            checksum_type: msg.checksum_type,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl convert::TryFrom<MessageFields> for Message {
+    type Error = ErrorType;
+
+    fn try_from(fields: MessageFields) -> Result<Self, Self::Error> {
+        let mut msg = Message::new(
+            fields.destination,
+            fields.source.unwrap_or_default(),
+            fields.payload,
+            fields.checksum_type,
+        )?;
+        msg.source = fields.source;
+        Ok(msg)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Message {
+    pub fn new(
+        destination: Address,
+        source: Address,
+        payload: Payload,
+        checksum_type: ChecksumType,
+    ) -> Result<Message, ErrorType> {
+        if payload.data.len() > u8::MAX as usize {
+            return Err(ErrorType::PayloadTooLong);
+        }
+        Ok(Message {
+            destination,
+            length: payload.data.len() as u8,
+            source: Some(source),
+            payload,
+            checksum_type,
+        })
+    }
+
+    /// Size of the encoded frame
+    pub fn encoded_len(&self) -> usize {
+        self.payload.data.len() + 5
+    }
+
+    /// Panics if the payload is over 255 bytes, which `Message::new` rejects
+    pub fn encode(&self) -> Vec<u8> {
+        let mut raw = vec![0u8; self.encoded_len()];
+        self.encode_into(&mut raw).expect("payload over 255 bytes");
+        raw
+    }
+
+    /// Encode into the start of `buf` without allocating, returns the length
+    ///
+    /// The length byte is taken from the payload, not from `length`. Panics
+    /// if `buf` is shorter than `encoded_len`.
+    pub fn encode_into(&self, buf: &mut [u8]) -> Result<usize, ErrorType> {
+        if self.payload.data.len() > u8::MAX as usize {
+            return Err(ErrorType::PayloadTooLong);
+        }
+        Ok(encode_frame(
+            buf,
+            [self.destination, self.data_length(), self.source_address()],
+            self.payload.header,
+            &self.payload.data,
+            self.checksum_type,
+        ))
+    }
+
+    pub fn decode(raw: &mut Vec<u8>, mode: ChecksumMode) -> Result<Message, ErrorType> {
+        // log::debug!("Decoding raw: {:?}", raw);
+
+        let msg_length = raw.len() as u16;
+
+        if msg_length < 2 {
+            return Err(ErrorType::PartialMessage);
+        }
+
+        let data_length = raw[1];
+        let expected_msg_length = data_length as u16 + 5;
+
+        if msg_length < expected_msg_length {
+            return Err(ErrorType::PartialMessage);
+        };
+
+        let raw_msg: Vec<u8> = raw.drain(0..expected_msg_length as usize).collect();
+        // don't touch raw after this, it's the next message
+
+        let result = Message::decode_frame(&raw_msg, mode);
+        #[cfg(feature = "std")]
+        if result.is_err() {
+            log::error!("Failed raw: {:?}", raw_msg);
+        }
+        result
+    }
+
+    /// Decode exactly one frame, the length byte must match `raw`
+    fn decode_frame(raw_msg: &[u8], mode: ChecksumMode) -> Result<Message, ErrorType> {
+        MessageRef::decode(raw_msg, mode).map(|message| message.to_message())
+    }
+
+    pub fn calc_checksum(&self) -> u8 {
+        let sum = [self.destination, self.data_length(), self.source_address()]
+            .iter()
+            .chain(self.payload.data.iter())
+            .fold(self.payload.header.to_u8(), |sum, &byte| {
+                sum.wrapping_add(byte)
+            });
+        0u8.wrapping_sub(sum)
+    }
+
+    // The length byte as encoded, `length` may not match a struct literal
+    fn data_length(&self) -> u8 {
+        self.payload.data.len() as u8
+    }
+
+    /// Source address sent with a simple checksum, the host for messages
+    /// decoded from CRC frames
+    pub fn source_address(&self) -> Address {
+        self.source.unwrap_or(HOST_ADDRESS)
+    }
+
+    pub fn calc_own_crc(&self) -> CRC {
+        let crc = crc_update(
+            0,
+            &[
+                self.destination,
+                self.data_length(),
+                self.payload.header.to_u8(),
+            ],
+        );
+        crc_bytes(crc_update(crc, &self.payload.data))
+    }
+
+    pub fn calc_crc(data: &[u8]) -> CRC {
+        crc_bytes(crc_update(0, data))
+    }
+
+    pub fn validate_checksum(raw: &[u8]) -> bool {
+        if raw.is_empty() {
+            #[cfg(feature = "std")]
+            log::error!("Validate checksum called on empty message!");
+            return false;
+        }
+
+        checksum_valid(raw)
+    }
+
+    pub fn validate_crc(raw: &[u8]) -> bool {
+        if raw.len() < 3 {
+            #[cfg(feature = "std")]
+            log::error!("Validate CRC called on a too short message!");
+            return false;
+        }
+
+        crc_valid(raw)
+    }
+}
+
+/// One analyser style line, e.g.
+/// `2 <- 1 [229 ReadBufferedCreditOrErrorCodes] len=0 sum=ok`
+///
+/// Data bytes follow the length in hex. The source is `?` when the message
+/// has none. The checksum is always `ok`, `encode` computes it and decoded
+/// messages have passed it.
+#[cfg(feature = "alloc")]
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_frame_line(
+            f,
+            self.destination,
+            self.source,
+            self.payload.header,
+            &self.payload.data,
+            self.checksum_type,
+            true,
+        )
+    }
+}
+
+fn write_frame_line(
+    f: &mut fmt::Formatter,
+    destination: Address,
+    source: Option<Address>,
+    header: HeaderType,
+    data: &[u8],
+    checksum_type: ChecksumType,
+    checksum_ok: bool,
+) -> fmt::Result {
+    write!(f, "{} <- ", destination)?;
+    match source {
+        Some(source) => write!(f, "{}", source)?,
+        None => write!(f, "?")?,
+    }
+    write!(f, " [{} {}] len={}", header.to_u8(), header, data.len())?;
+    for byte in data {
+        write!(f, " {:02x}", byte)?;
+    }
+    match checksum_type {
+        ChecksumType::SimpleChecksum => write!(f, " sum")?,
+        ChecksumType::CRCChecksum => write!(f, " crc")?,
+    }
+    match checksum_ok {
+        true => write!(f, "=ok"),
+        false => write!(f, "=bad"),
+    }
+}
+
+/// Encode a frame with the destination, length and source (ignored with
+/// CRC checksums) bytes into `buf`
+fn encode_frame(
+    buf: &mut [u8],
+    [destination, length, source]: [u8; 3],
+    header: HeaderType,
+    data: &[u8],
+    checksum_type: ChecksumType,
+) -> usize {
+    let frame_length = data.len() + 5;
+    let frame = &mut buf[..frame_length];
+    frame[0] = destination;
+    frame[1] = length;
+    frame[3] = header.to_u8();
+    frame[4..frame_length - 1].copy_from_slice(data);
+
+    match checksum_type {
+        ChecksumType::SimpleChecksum => {
+            frame[2] = source;
+            frame[frame_length - 1] = 0u8.wrapping_sub(byte_sum(&frame[..frame_length - 1]));
+        }
+        ChecksumType::CRCChecksum => {
+            let crc = frame_crc(frame);
+            frame[2] = crc[0];
+            frame[frame_length - 1] = crc[1];
+        }
+    }
+
+    frame_length
+}
+
+fn byte_sum(raw: &[u8]) -> u8 {
+    raw.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn checksum_valid(raw: &[u8]) -> bool {
+    !raw.is_empty() && byte_sum(raw) == 0
+}
+
+fn crc_valid(raw: &[u8]) -> bool {
+    raw.len() >= 3 && frame_crc(raw) == [raw[2], raw[raw.len() - 1]]
+}
+
+/// CRC-CCITT (polynomial 0x1021, initial value 0)
+fn crc_update(mut crc: u16, data: &[u8]) -> u16 {
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            if (crc & 0x8000) != 0 {
+                crc = (crc << 1) ^ 0x1021;
+            } else {
+                crc <<= 1;
+            }
+        }
+    }
+    crc
+}
+
+fn crc_bytes(crc: u16) -> CRC {
+    crc.to_le_bytes()
+}
+
+/// CRC of a frame, skipping the bytes that carry it
+fn frame_crc(frame: &[u8]) -> CRC {
+    let crc = crc_update(0, &frame[..2]);
+    crc_bytes(crc_update(crc, &frame[3..frame.len() - 1]))
+}
+
+/// The frame at the start of `raw`, as long as its length byte says
+fn frame_at(raw: &[u8]) -> Result<&[u8], ErrorType> {
+    let frame_length = match raw.get(1) {
+        Some(&data_length) => data_length as usize + 5,
+        None => return Err(ErrorType::PartialMessage),
+    };
+    raw.get(..frame_length).ok_or(ErrorType::PartialMessage)
+}
+
+/// Message borrowed from a received frame
+///
+/// Lets a frame be inspected without copying its data, `to_message` makes
+/// an owned `Message` of it.
+#[derive(Debug, Clone, Copy)]
+pub struct MessageRef<'a> {
+    raw: &'a [u8],
+    checksum_type: ChecksumType,
+}
+
+impl<'a> MessageRef<'a> {
+    /// Frame at the start of `raw`, bytes after it are not part of the message
+    pub fn decode(raw: &'a [u8], mode: ChecksumMode) -> Result<MessageRef<'a>, ErrorType> {
+        let raw = frame_at(raw)?;
+
+        let checksum_type = match mode {
+            ChecksumMode::Expect(ChecksumType::SimpleChecksum) if checksum_valid(raw) => {
+                ChecksumType::SimpleChecksum
+            }
+            ChecksumMode::Expect(ChecksumType::CRCChecksum) if crc_valid(raw) => {
+                ChecksumType::CRCChecksum
+            }
+            ChecksumMode::Expect(_) => return Err(ErrorType::ChecksumError),
+            ChecksumMode::Auto => match (checksum_valid(raw), crc_valid(raw)) {
+                (true, true) => return Err(ErrorType::AmbiguousChecksum),
+                (true, false) => ChecksumType::SimpleChecksum,
+                (false, true) => ChecksumType::CRCChecksum,
+                (false, false) => return Err(ErrorType::ChecksumError),
+            },
+        };
+
+        Ok(MessageRef { raw, checksum_type })
+    }
+
+    /// Frame at the start of `raw` read as `checksum_type` without checking
+    /// the checksum, to show corrupted frames in logs
+    pub fn unchecked(
+        raw: &'a [u8],
+        checksum_type: ChecksumType,
+    ) -> Result<MessageRef<'a>, ErrorType> {
+        let raw = frame_at(raw)?;
+        Ok(MessageRef { raw, checksum_type })
+    }
+
+    pub fn destination(&self) -> Address {
+        self.raw[0]
+    }
+
+    /// `None` for CRC frames, which have no source address
+    pub fn source(&self) -> Option<Address> {
+        match self.checksum_type {
+            ChecksumType::SimpleChecksum => Some(self.raw[2]),
+            ChecksumType::CRCChecksum => None,
+        }
+    }
+
+    pub fn header(&self) -> HeaderType {
+        HeaderType::from_u8(self.raw[3])
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        &self.raw[4..self.raw.len() - 1]
+    }
+
+    pub fn checksum_type(&self) -> ChecksumType {
+        self.checksum_type
+    }
+
+    /// The whole frame
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.raw
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn to_message(&self) -> Message {
+        Message {
+            destination: self.destination(),
+            length: self.raw[1],
+            source: self.source(),
+            payload: Payload {
+                header: self.header(),
+                data: self.data().to_vec(),
+            },
+            checksum_type: self.checksum_type,
+        }
+    }
+}
+
+/// Analyser style line of the frame, like `Message`, with the checksum
+/// status of the received bytes, e.g.
+/// `2 <- 1 [229 ReadBufferedCreditOrErrorCodes] len=0 sum=ok`
+impl fmt::Display for MessageRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let checksum_ok = match self.checksum_type {
+            ChecksumType::SimpleChecksum => checksum_valid(self.raw),
+            ChecksumType::CRCChecksum => crc_valid(self.raw),
+        };
+        write_frame_line(
+            f,
+            self.destination(),
+            self.source(),
+            self.header(),
+            self.data(),
+            self.checksum_type,
+            checksum_ok,
+        )
+    }
+}
+
+/// Frame in a fixed-capacity buffer, an owned message without allocation
+#[derive(Clone, Copy)]
+pub struct MessageBuf {
+    raw: [u8; MAX_FRAME_LENGTH],
+    length: usize,
+    checksum_type: ChecksumType,
+}
+
+impl MessageBuf {
+    /// Encode a message, the source is not sent with CRC checksums
+    pub fn new(
+        destination: Address,
+        source: Address,
+        header: HeaderType,
+        data: &[u8],
+        checksum_type: ChecksumType,
+    ) -> Result<MessageBuf, ErrorType> {
+        if data.len() > u8::MAX as usize {
+            return Err(ErrorType::PayloadTooLong);
+        }
+        let mut raw = [0u8; MAX_FRAME_LENGTH];
+        let length = encode_frame(
+            &mut raw,
+            [destination, data.len() as u8, source],
+            header,
+            data,
+            checksum_type,
+        );
+        Ok(MessageBuf {
+            raw,
+            length,
+            checksum_type,
+        })
+    }
+
+    /// Copy of the frame at the start of `raw`
+    pub fn decode(raw: &[u8], mode: ChecksumMode) -> Result<MessageBuf, ErrorType> {
+        let message = MessageRef::decode(raw, mode)?;
+        let frame = message.as_bytes();
+        let mut raw = [0u8; MAX_FRAME_LENGTH];
+        raw[..frame.len()].copy_from_slice(frame);
+        Ok(MessageBuf {
+            raw,
+            length: frame.len(),
+            checksum_type: message.checksum_type(),
+        })
+    }
+
+    pub fn message(&self) -> MessageRef<'_> {
+        MessageRef {
+            raw: self.as_bytes(),
+            checksum_type: self.checksum_type,
+        }
+    }
+
+    /// The encoded frame
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.raw[..self.length]
+    }
+}
+
+impl fmt::Debug for MessageBuf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("MessageBuf").field(&self.message()).finish()
+    }
+}
+
+/// Receive counters of a `Framer`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FramerStats {
+    /// Bytes skipped while looking for the start of a valid frame
+    pub dropped_bytes: u32,
+    /// Bytes not buffered because the buffer was full
+    pub overflowed_bytes: u32,
+    /// Frames that failed the checksum, or were ambiguous in auto mode
+    pub checksum_errors: u32,
+    /// Frames cut short by an inter-byte gap
+    pub incomplete_frames: u32,
+}
+
+/// Largest capacity of a `Framer`, room for two of the largest frames
+pub const FRAMER_CAPACITY: usize = 2 * MAX_FRAME_LENGTH;
+
+/// Splits a byte stream into frames
+///
+/// Unlike `Message::decode`, a frame failing the checksum only drops its
+/// first byte and the search continues at the next one, so one corrupted or
+/// spurious byte doesn't lose the frames that follow. A frame never spans an
+/// inter-byte gap, the transport reports gaps with `gap`.
+///
+/// While resynchronizing, misaligned bytes pass the simple checksum by chance
+/// (1 in 256), the receiver of such a frame should treat it like any other
+/// unexpected message.
+#[derive(Debug, Clone)]
+pub struct Framer {
+    // Buffered bytes are buffer[start..end], dropping a byte is O(1)
+    buffer: [u8; FRAMER_CAPACITY],
+    start: usize,
+    end: usize,
+    capacity: usize,
+    // No frame continues past this offset, the line was idle there
+    gap_end: usize,
+    // Bytes before the gap were dropped, counted as one incomplete frame
+    gap_dropped: bool,
+    mode: ChecksumMode,
+    #[cfg(feature = "encryption")]
+    encryption: Option<EncryptionLayer>,
+    // The buffer starts at a frame boundary
+    synchronized: bool,
+    stats: FramerStats,
+}
+
+impl Framer {
+    /// Framer buffering at most `capacity` bytes, up to `FRAMER_CAPACITY`
+    pub fn new(capacity: usize, mode: ChecksumMode) -> Framer {
+        Framer {
+            buffer: [0u8; FRAMER_CAPACITY],
+            start: 0,
+            end: 0,
+            capacity: capacity.min(FRAMER_CAPACITY),
+            gap_end: 0,
+            gap_dropped: false,
+            mode,
+            #[cfg(feature = "encryption")]
+            encryption: None,
+            synchronized: true,
+            stats: FramerStats::default(),
+        }
+    }
+
+    /// Append received bytes, returns the number of bytes that didn't fit
+    pub fn push(&mut self, data: &[u8]) -> usize {
+        let free = self.capacity.saturating_sub(self.len());
+        let ignored = data.len().saturating_sub(free);
+        let data = &data[..data.len() - ignored];
+        if self.end + data.len() > FRAMER_CAPACITY {
+            self.buffer.copy_within(self.start..self.end, 0);
+            self.gap_end = self.gap_end.saturating_sub(self.start);
+            self.end -= self.start;
+            self.start = 0;
+        }
+        self.buffer[self.end..self.end + data.len()].copy_from_slice(data);
+        self.end += data.len();
+        self.stats.overflowed_bytes += ignored as u32;
+        ignored
+    }
+
+    /// Next complete and valid frame
+    pub fn next_frame(&mut self) -> Option<MessageBuf> {
+        let (length, message) = self.find_frame()?;
+        self.advance(length);
+        self.synchronized = true;
+        Some(message)
+    }
+
+    /// Length of the valid frame at the start of the buffer, bytes that
+    /// can't start one are dropped
+    fn find_frame(&mut self) -> Option<(usize, MessageBuf)> {
+        while !self.is_empty() {
+            let at_gap = self.start < self.gap_end;
+            let available = if at_gap { self.gap_end } else { self.end };
+            let buffered = &self.buffer[self.start..available];
+            // Encryption keeps the length in clear
+            let frame_length = match buffered.get(1) {
+                Some(&data_length) => data_length as usize + 5,
+                None if at_gap => buffered.len() + 1,
+                None => return None,
+            };
+            if buffered.len() < frame_length {
+                if !at_gap {
+                    return None;
+                }
+                // Can't be completed after the gap
+                self.drop_byte();
+                continue;
+            }
+
+            let frame = &buffered[..frame_length];
+            match self.decode(frame) {
+                Ok(message) => return Some((frame_length, message)),
+                #[cfg_attr(not(feature = "std"), allow(unused_variables))]
+                Err(e) => {
+                    if self.synchronized {
+                        #[cfg(feature = "std")]
+                        log::debug!("{}, resynchronizing: {:?}", e, frame);
+                        self.stats.checksum_errors += 1;
+                    }
+                    self.drop_byte();
+                }
+            }
+        }
+        None
+    }
+
+    /// Next complete and valid frame as an owned `Message`
+    #[cfg(feature = "alloc")]
+    pub fn next_message(&mut self) -> Option<Message> {
+        self.next_frame()
+            .map(|message| message.message().to_message())
+    }
+
+    /// The line was idle, no frame continues past the buffered bytes
+    ///
+    /// Valid frames still found in the buffer are returned by `next_frame`,
+    /// the remaining bytes are dropped. When `next_frame` wasn't called since
+    /// an earlier gap, the bytes before that gap are framed right away: valid
+    /// frames stay buffered and the rest is dropped.
+    pub fn gap(&mut self) {
+        let first = self.start;
+        let mut kept = first;
+        while self.start < self.gap_end {
+            if let Some((length, _)) = self.find_frame() {
+                self.buffer
+                    .copy_within(self.start..self.start + length, kept);
+                kept += length;
+                self.advance(length);
+            }
+        }
+        // Close the hole left by the dropped bytes
+        self.buffer.copy_within(self.start..self.end, kept);
+        self.end -= self.start - kept;
+        self.start = first;
+        self.gap_end = self.end;
+        self.gap_dropped = false;
+        if self.is_empty() || kept > first {
+            self.synchronized = true;
+        }
+    }
+
+    /// Discard buffered bytes and frames without counting them
+    pub fn clear(&mut self) {
+        self.start = 0;
+        self.end = 0;
+        self.gap_end = 0;
+        self.synchronized = true;
+    }
+
+    /// Number of buffered bytes not yet part of a frame
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn stats(&self) -> FramerStats {
+        self.stats
+    }
+
+    pub fn set_checksum_mode(&mut self, mode: ChecksumMode) {
+        self.mode = mode;
+    }
+
+    /// Decrypt frames from now on, `None` for plain text
+    #[cfg(feature = "encryption")]
+    pub fn set_encryption(&mut self, encryption: Option<EncryptionLayer>) {
+        self.encryption = encryption;
+    }
+
+    #[cfg(feature = "encryption")]
+    pub fn encryption(&self) -> Option<&EncryptionLayer> {
+        self.encryption.as_ref()
+    }
+
+    pub fn clear_stats(&mut self) {
+        self.stats = FramerStats::default();
+    }
+
+    fn decode(&self, frame: &[u8]) -> Result<MessageBuf, ErrorType> {
+        #[cfg(feature = "encryption")]
+        if let Some(layer) = &self.encryption {
+            let mut plain = [0u8; MAX_FRAME_LENGTH];
+            let plain = &mut plain[..frame.len()];
+            plain.copy_from_slice(frame);
+            layer.decrypt(plain);
+            return MessageBuf::decode(plain, self.mode);
+        }
+        MessageBuf::decode(frame, self.mode)
+    }
+
+    fn advance(&mut self, length: usize) {
+        self.start += length;
+        if self.start == self.gap_end {
+            // The next byte starts a frame after the gap
+            self.synchronized = true;
+        }
+    }
+
+    fn drop_byte(&mut self) {
+        if self.start < self.gap_end && !self.gap_dropped {
+            self.stats.incomplete_frames += 1;
+            self.gap_dropped = true;
+        }
+        self.synchronized = false;
+        self.advance(1);
+        self.stats.dropped_bytes += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_date_codes() {
+        // 2019-05-17 with base year 2000
+        let date = DateCode::new(2019, 5, 17).unwrap();
+        let code: u16 = (19 << 9) | (5 << 5) | 17;
+        assert_eq!(date.to_bytes(2000).unwrap(), code.to_le_bytes());
+        assert_eq!(
+            DateCode::from_bytes(&code.to_le_bytes(), 2000).unwrap(),
+            date
+        );
+        assert!(date.to_bytes(2020).is_err());
+        assert!(date.to_bytes(1891).is_err());
+
+        assert_eq!(DateCode::parse("2019-05-17").unwrap(), date);
+        assert_eq!(date.to_string(), "2019-05-17");
+        assert!(DateCode::parse("2019-02-29").is_err());
+        assert!(DateCode::new(2020, 2, 29).is_ok());
+        assert!(DateCode::from_bytes(&[0, 0], 2000).is_err());
+        assert!(DateCode::from_bytes(&[0x31, 0xff], u16::MAX).is_err());
+
+        assert_eq!(parse_base_year(b"2000").unwrap(), 2000);
+        assert!(parse_base_year(b"20x0").is_err());
+    }
+
+    #[test]
+    fn test_real_time_clock() {
+        let rtc = RealTimeClock::from_bytes(&1_589_920_496u32.to_le_bytes()).unwrap();
+        assert_eq!(rtc.to_string(), "2020-05-19 20:34:56");
+        assert_eq!(
+            RealTimeClock::from_date_time(rtc.date(), 20, 34, 56).unwrap(),
+            rtc
+        );
+        assert_eq!(RealTimeClock::new(0).to_string(), "1970-01-01 00:00:00");
+        assert_eq!(
+            RealTimeClock::new(u32::MAX).date(),
+            DateCode::new(2106, 2, 7).unwrap()
+        );
+        assert!(
+            RealTimeClock::from_date_time(DateCode::new(1969, 12, 31).unwrap(), 0, 0, 0).is_err()
+        );
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_variable_sets() {
+        let hopper = HopperVariables::from_bytes(&[100, 10, 30, 80, 120, 3]).unwrap();
+        assert_eq!(hopper.payout_timeout, 30);
+        assert_eq!(hopper.connector_address, 3);
+        assert_eq!(hopper.to_modify_bytes(), vec![100, 10, 30]);
+        assert_eq!(hopper.to_bytes(), vec![100, 10, 30, 80, 120, 3]);
+        assert!(HopperVariables::from_bytes(&[100, 10]).is_err());
+
+        let acceptor = CoinAcceptorVariables::from_bytes(&[1]).unwrap();
+        assert!(acceptor.inhibit_on_full_buffer);
+        assert_eq!(acceptor.to_modify_bytes(), vec![1]);
+
+        let options = BillValidatorOptions::from_u8(0b1000_0011);
+        assert!(options.stacker && options.escrow && options.remote_bill_programming);
+        assert!(!options.bill_teach);
+        assert_eq!(options.to_u8(), 0b1000_0011);
+    }
+
+    fn frame(destination: Address, header: HeaderType, data: &[u8]) -> Vec<u8> {
+        MessageBuf::new(destination, 1, header, data, ChecksumType::SimpleChecksum)
+            .unwrap()
+            .as_bytes()
+            .to_vec()
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_decode_checksum_mode() {
+        let simple = ChecksumMode::Expect(ChecksumType::SimpleChecksum);
+        let crc = ChecksumMode::Expect(ChecksumType::CRCChecksum);
+        let payload = Payload {
+            header: HeaderType::SimplePoll,
+            data: vec![],
+        };
+
+        let raw = Message::new(2, 3, payload.clone(), ChecksumType::SimpleChecksum)
+            .unwrap()
+            .encode();
+        let message = Message::decode(&mut raw.clone(), simple).unwrap();
+        assert_eq!(message.source, Some(3));
+        assert_eq!(
+            Message::decode(&mut raw.clone(), crc).unwrap_err(),
+            ErrorType::ChecksumError
+        );
+
+        let raw = Message::new(2, 3, payload, ChecksumType::CRCChecksum)
+            .unwrap()
+            .encode();
+        let message = Message::decode(&mut raw.clone(), crc).unwrap();
+        assert_eq!(message.checksum_type, ChecksumType::CRCChecksum);
+        assert_eq!(message.source, None);
+        assert_eq!(message.source_address(), HOST_ADDRESS);
+        assert!(Message::decode(&mut raw.clone(), ChecksumMode::Auto).is_ok());
+
+        // Sums to zero and has a valid CRC
+        let ambiguous = vec![2, 0, 97, 35, 122];
+        assert!(Message::decode(&mut ambiguous.clone(), simple).is_ok());
+        assert!(Message::decode(&mut ambiguous.clone(), crc).is_ok());
+        assert_eq!(
+            Message::decode(&mut ambiguous.clone(), ChecksumMode::Auto).unwrap_err(),
+            ErrorType::AmbiguousChecksum
+        );
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_borrowed_encoding() {
+        for checksum_type in [ChecksumType::SimpleChecksum, ChecksumType::CRCChecksum] {
+            let payload = Payload {
+                header: HeaderType::ModifyInhibitStatus,
+                data: vec![0xff, 0x7f],
+            };
+            let msg = Message::new(2, 1, payload, checksum_type).unwrap();
+
+            let mut buf = [0u8; 16];
+            let length = msg.encode_into(&mut buf).unwrap();
+            assert_eq!(length, msg.encoded_len());
+            assert_eq!(buf[..length], msg.encode()[..]);
+
+            // Trailing bytes belong to the next frame
+            let view = MessageRef::decode(&buf, checksum_type.into()).unwrap();
+            assert_eq!(view.as_bytes().len(), 7);
+            assert_eq!(view.destination(), 2);
+            assert_eq!(view.data(), &[0xff, 0x7f]);
+            assert_eq!(view.to_message().encode(), msg.encode());
+            assert_eq!(
+                MessageRef::decode(&buf[..6], checksum_type.into()).unwrap_err(),
+                ErrorType::PartialMessage
+            );
+        }
+    }
+
+    #[test]
+    fn test_message_buf() {
+        let simple = ChecksumMode::Expect(ChecksumType::SimpleChecksum);
+        let crc = ChecksumMode::Expect(ChecksumType::CRCChecksum);
+        let poll =
+            MessageBuf::new(2, 3, HeaderType::SimplePoll, &[], ChecksumType::CRCChecksum).unwrap();
+        let view = MessageRef::decode(poll.as_bytes(), crc).unwrap();
+        assert_eq!(view.source(), None);
+        assert_eq!(
+            frame_crc(poll.as_bytes()),
+            [poll.as_bytes()[2], poll.as_bytes()[4]]
+        );
+        assert_eq!(
+            MessageRef::decode(poll.as_bytes(), simple).unwrap_err(),
+            ErrorType::ChecksumError
+        );
+        assert_eq!(
+            MessageRef::decode(&poll.as_bytes()[..4], crc).unwrap_err(),
+            ErrorType::PartialMessage
+        );
+
+        // Sums to zero and has a valid CRC
+        let ambiguous = [2, 0, 97, 35, 122];
+        assert!(MessageRef::decode(&ambiguous, simple).is_ok());
+        assert_eq!(
+            MessageRef::decode(&ambiguous, ChecksumMode::Auto).unwrap_err(),
+            ErrorType::AmbiguousChecksum
+        );
+
+        let too_long = MessageBuf::new(
+            2,
+            1,
+            HeaderType::WriteDataBlock,
+            &[0; 256],
+            ChecksumType::CRCChecksum,
+        );
+        assert_eq!(too_long.unwrap_err(), ErrorType::PayloadTooLong);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_roundtrip_all_lengths() {
+        for checksum_type in [ChecksumType::SimpleChecksum, ChecksumType::CRCChecksum] {
+            for length in 0..=255usize {
+                // High bytes so the checksum sum wraps many times
+                let data: Vec<u8> = (0..length).map(|i| 0xff - (i * 7 + length) as u8).collect();
+                let payload = Payload {
+                    header: HeaderType::WriteDataBlock,
+                    data: data.clone(),
+                };
+                let msg = Message::new(0xfe, 0xfd, payload, checksum_type).unwrap();
+
+                let mut raw = msg.encode();
+                assert_eq!(raw.len(), length + 5);
+                let mut buf = [0u8; MAX_FRAME_LENGTH];
+                assert_eq!(msg.encode_into(&mut buf).unwrap(), raw.len());
+                assert_eq!(buf[..raw.len()], raw[..]);
+
+                let buffered =
+                    MessageBuf::new(0xfe, 0xfd, HeaderType::WriteDataBlock, &data, checksum_type)
+                        .unwrap();
+                assert_eq!(buffered.as_bytes(), &raw[..]);
+                let decoded = MessageBuf::decode(&raw, checksum_type.into()).unwrap();
+                assert_eq!(decoded.as_bytes(), &raw[..]);
+
+                let view = MessageRef::decode(&raw, checksum_type.into()).unwrap();
+                assert_eq!(view.destination(), 0xfe);
+                assert_eq!(view.data(), &data[..]);
+                assert_eq!(view.checksum_type(), checksum_type);
+
+                let decoded = Message::decode(&mut raw, checksum_type.into()).unwrap();
+                assert!(raw.is_empty());
+                assert_eq!(decoded.length as usize, length);
+                assert_eq!(decoded.payload.data, data);
+                assert_eq!(decoded.destination, 0xfe);
+                assert_eq!(decoded.checksum_type, checksum_type);
+            }
+        }
+
+        let payload = Payload {
+            header: HeaderType::WriteDataBlock,
+            data: vec![0; 256],
+        };
+        assert_eq!(
+            Message::new(2, 1, payload.clone(), ChecksumType::SimpleChecksum).unwrap_err(),
+            ErrorType::PayloadTooLong
+        );
+
+        // Struct literals bypass the checks of Message::new
+        let mut msg = Message {
+            destination: 2,
+            length: 0,
+            source: Some(1),
+            payload,
+            checksum_type: ChecksumType::SimpleChecksum,
+        };
+        let mut buf = [0u8; MAX_FRAME_LENGTH + 1];
+        assert_eq!(
+            msg.encode_into(&mut buf).unwrap_err(),
+            ErrorType::PayloadTooLong
+        );
+        msg.payload.data.truncate(3);
+        let length = msg.encode_into(&mut buf).unwrap();
+        let decoded = MessageRef::decode(&buf[..length], ChecksumMode::Auto).unwrap();
+        assert_eq!(decoded.data(), &[0, 0, 0]);
+        assert_eq!(msg.calc_checksum(), buf[length - 1]);
+    }
+
+    #[test]
+    fn test_framer_resynchronizes() {
+        let poll = frame(2, HeaderType::SimplePoll, &[]);
+        let reply = frame(1, HeaderType::Reply, &[10, 20, 30]);
+        let mut corrupted = reply.clone();
+        corrupted[4] ^= 0xff;
+
+        let mut stream = vec![0x55];
+        stream.extend(&poll);
+        stream.extend(&corrupted);
+        stream.extend(&reply);
+
+        let mut framer = Framer::new(520, ChecksumType::SimpleChecksum.into());
+        // Byte by byte, as it may arrive from a serial port
+        let mut messages = vec![];
+        for byte in stream {
+            framer.push(&[byte]);
+            while let Some(message) = framer.next_frame() {
+                messages.push(message);
+            }
+        }
+        // Realigned on a length byte from the corrupted data, waiting for
+        // the gap to give up on it
+        assert_eq!(messages.len(), 1);
+        framer.gap();
+        messages.extend(framer.next_frame());
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].message().destination(), 2);
+        assert_eq!(messages[1].message().data(), &[10, 20, 30]);
+        assert!(framer.is_empty());
+        let stats = framer.stats();
+        assert_eq!(stats.dropped_bytes, 1 + corrupted.len() as u32);
+        assert_eq!(stats.checksum_errors, 2);
+    }
+
+    #[test]
+    fn test_framer_gap() {
+        let poll = frame(2, HeaderType::SimplePoll, &[]);
+
+        // Length byte claims more data than will ever arrive
+        let mut framer = Framer::new(520, ChecksumType::SimpleChecksum.into());
+        framer.push(&[2, 200]);
+        framer.push(&poll);
+        assert!(framer.next_frame().is_none());
+        framer.gap();
+        assert_eq!(framer.next_frame().unwrap().message().destination(), 2);
+        assert!(framer.next_frame().is_none());
+        assert!(framer.is_empty());
+
+        // Frame cut short
+        framer.push(&poll[..3]);
+        framer.gap();
+        assert!(framer.next_frame().is_none());
+        assert!(framer.is_empty());
+        assert_eq!(framer.stats().incomplete_frames, 2);
+        assert_eq!(framer.stats().dropped_bytes, 5);
+
+        // Two gaps before reading, the frame doesn't span the first one
+        framer.push(&poll[..3]);
+        framer.gap();
+        framer.push(&poll[3..]);
+        framer.gap();
+        assert!(framer.next_frame().is_none());
+        assert!(framer.is_empty());
+        assert_eq!(framer.stats().incomplete_frames, 4);
+        assert_eq!(framer.stats().dropped_bytes, 10);
+
+        // Frames before an earlier gap are kept until they are read
+        framer.push(&poll);
+        framer.gap();
+        framer.push(&poll);
+        framer.gap();
+        let mut frames = 0;
+        while framer.next_frame().is_some() {
+            frames += 1;
+        }
+        assert_eq!(frames, 2);
+        assert!(framer.is_empty());
+        assert_eq!(framer.stats().incomplete_frames, 4);
+        assert_eq!(framer.stats().dropped_bytes, 10);
+
+        assert_eq!(framer.push(&[0; 600]), 80);
+        assert_eq!(framer.stats().overflowed_bytes, 80);
+    }
+
+    #[test]
+    fn test_framer_reuses_buffer() {
+        let poll = frame(2, HeaderType::SimplePoll, &[]);
+        let mut framer = Framer::new(FRAMER_CAPACITY, ChecksumType::SimpleChecksum.into());
+
+        // Far more bytes than the buffer holds, each frame split in two
+        let mut received = 0;
+        for _ in 0..200 {
+            framer.push(&poll[..2]);
+            assert!(framer.next_frame().is_none());
+            framer.push(&poll[2..]);
+            while framer.next_frame().is_some() {
+                received += 1;
+            }
+        }
+        assert_eq!(received, 200);
+        assert!(framer.is_empty());
+        assert_eq!(framer.stats(), FramerStats::default());
+    }
+
+    #[test]
+    fn test_serial_number_roundtrip() {
+        let serial = SerialNumber::from_bytes(&[149, 48, 16]).unwrap();
+        assert_eq!(serial.value(), 1061013);
+        assert_eq!(serial.to_bytes(), [149, 48, 16]);
+
+        let max = SerialNumber::new(SerialNumber::MAX).unwrap();
+        assert_eq!(SerialNumber::from_bytes(&max.to_bytes()), Ok(max));
+
+        assert_eq!(
+            SerialNumber::new(SerialNumber::MAX + 1),
+            Err(ErrorType::ParseError)
+        );
+        assert_eq!(
+            SerialNumber::from_bytes(&[123, 0]),
+            Err(ErrorType::ParseError)
+        );
+    }
+
+    #[test]
+    fn test_event_tables() {
+        for code in 0..=u8::MAX {
+            let error = CoinAcceptorError::from_u8(code);
+            assert_eq!(error.to_u8(), code);
+            if !matches!(error, CoinAcceptorError::Unknown(_)) {
+                assert_ne!(error.description(), "Unknown error code");
+            }
+        }
+        for a in 0..=u8::MAX {
+            for b in 0..=2 {
+                assert_eq!(BillEvent::from_u8((a, b)).to_u8(), (a, b));
+            }
+        }
+        assert!(matches!(
+            BillEvent::from_u8((5, 2)),
+            BillEvent::Unknown(5, 2)
+        ));
+        // Unassigned in issue 4.7
+        assert!(matches!(
+            CoinAcceptorError::from_u8(41),
+            CoinAcceptorError::Unknown(41)
+        ));
+        assert!(matches!(
+            BillEvent::from_u8((0, 22)),
+            BillEvent::Unknown(0, 22)
+        ));
+
+        let error = CoinAcceptorError::from_u8(159);
+        assert!(matches!(error, CoinAcceptorError::InhibitedCoinType(32)));
+        assert!(matches!(
+            CoinAcceptorError::from_name("InhibitedCoinType32"),
+            Some(CoinAcceptorError::InhibitedCoinType(32))
+        ));
+        assert!(CoinAcceptorError::from_name("InhibitedCoinType33").is_none());
+        assert_eq!(error.severity(), Severity::Reject);
+        assert_eq!(error.coin_accepted(), Some(false));
+        let swallowed = CoinAcceptorError::SwallowedCoin;
+        assert_eq!(swallowed.coin_accepted(), Some(true));
+        assert_eq!(CoinAcceptorError::MotorException.coin_accepted(), None);
+        assert_eq!(CoinAcceptorError::NullEvent.severity(), Severity::Status);
+        assert!(CoinAcceptorError::ExternalLightAttack.severity() >= Severity::Fraud);
+
+        let event = BillEvent::from_u8((200, 1));
+        assert_eq!(event, BillEvent::BillTypeValidatedAndHeld(200));
+        assert_eq!(event.description(), "Bill validated and held in escrow");
+        assert_eq!(
+            BillEvent::from_name("BillTypeValidatedAndSent200"),
+            Some(BillEvent::BillTypeValidatedAndSent(200))
+        );
+        assert_eq!(BillEvent::InhibitedBillDIP.severity(), Severity::Reject);
+        assert_eq!(BillEvent::BillTamper.severity(), Severity::Fraud);
+        assert_eq!(BillEvent::StackerJammed.severity(), Severity::Fault);
+    }
+
+    #[test]
+    fn test_polling_priority() {
+        let priority = PollingPriority::from_bytes(&[2, 20]).unwrap();
+        assert_eq!(priority, DEFAULT_POLLING_PRIORITY);
+        assert_eq!(priority.interval(), Some(Duration::from_millis(200)));
+        assert_eq!(priority.to_bytes(), [2, 20]);
+
+        let weekly = PollingPriority::new(PollingUnit::Weeks, 2);
+        assert_eq!(weekly.interval(), Some(Duration::from_secs(14 * 24 * 3600)));
+        let special = PollingPriority::from_bytes(&[0, 0]).unwrap();
+        assert_eq!(special.unit, PollingUnit::Special);
+        assert_eq!(special.interval(), None);
+        assert_eq!(
+            PollingPriority::from_bytes(&[12, 1]).unwrap().interval(),
+            None
+        );
+        assert!(PollingPriority::from_bytes(&[2]).is_err());
+
+        let mode = AddressMode::from_u8(0b0100_0101);
+        assert!(mode.rom && mode.eeprom && mode.serial_volatile);
+        assert!(!mode.ram && !mode.serial_non_volatile);
+        assert_eq!(mode.to_u8(), 0b0100_0101);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_transcript() {
+        let payload = Payload {
+            header: HeaderType::ModifyInhibitStatus,
+            data: vec![0xff, 0x7f],
+        };
+        let msg = Message::new(2, 1, payload, ChecksumType::CRCChecksum).unwrap();
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains(r#""header":"ModifyInhibitStatus""#));
+        assert!(json.contains(r#""checksum_type":"CRCChecksum""#));
+        assert!(!json.contains("length"));
+        let reloaded: Message = serde_json::from_str(&json).unwrap();
+        assert_eq!(reloaded.encode(), msg.encode());
+
+        // Payloads that don't fit in a frame are rejected
+        let json = json.replace("[255,127]", &format!("{:?}", [0u8; 256]));
+        assert!(serde_json::from_str::<Message>(&json).is_err());
+
+        // Unknown codes by number, known ones are accepted by number too
+        let json = serde_json::to_string(&HeaderType::Unknown(100)).unwrap();
+        assert_eq!(json, "100");
+        let header: HeaderType = serde_json::from_str("254").unwrap();
+        assert!(matches!(header, HeaderType::SimplePoll));
+        assert!(serde_json::from_str::<HeaderType>(r#""NoSuchHeader""#).is_err());
+
+        let error: CoinAcceptorError = serde_json::from_str(r#""RejectCoin""#).unwrap();
+        assert_eq!(error.to_u8(), CoinAcceptorError::RejectCoin.to_u8());
 
-        let mut data = raw.clone();
-        let crc: [u8; 2] = [data.remove(2), data.pop().unwrap()];
+        let events = [
+            BillEvent::BillTypeValidatedAndHeld(6),
+            BillEvent::Unknown(7, 3),
+        ];
+        let json = serde_json::to_string(&events).unwrap();
+        assert_eq!(json, r#"["BillTypeValidatedAndHeld6",[7,3]]"#);
+        assert_eq!(
+            serde_json::from_str::<Vec<BillEvent>>(&json).unwrap(),
+            events
+        );
 
-        crc == Message::calc_crc(&data)
+        let error = serde_json::to_string(&ErrorType::PayloadTooLong).unwrap();
+        assert_eq!(
+            serde_json::from_str::<ErrorType>(&error).unwrap(),
+            ErrorType::PayloadTooLong
+        );
     }
 }
@@ -0,0 +1,380 @@
+use std::convert;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::coinacceptor::CoinAcceptor;
+use crate::protocol::{CoinAcceptorError, CoinId};
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    IOError(io::Error),
+    /// Line number (from 1) and the reason
+    ParseError(usize, String),
+}
+
+impl convert::From<io::Error> for ScenarioError {
+    fn from(e: io::Error) -> ScenarioError {
+        ScenarioError::IOError(e)
+    }
+}
+
+/// Something that happens to the emulated coin acceptor
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    /// Coin inserted into the channel (0..16), accepted as credit unless
+    /// the channel or the master inhibit is active
+    Insert(u8),
+    /// Coin looked up in the coin table when it is inserted, like `Insert`
+    /// with its channel, a coin that is not programmed is rejected
+    InsertCoin(CoinId),
+    Error(u8),
+    Inhibit(u8, bool),
+    MasterInhibit(bool),
+    Reset,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScenarioEvent {
+    /// Time since the start of the scenario
+    pub at: Duration,
+    pub action: Action,
+}
+
+/// Timeline of events replayed against a `CoinAcceptor`
+///
+/// Events are given one per line, or separated by `;`, as a time followed
+/// by an action. `#` starts a comment.
+///
+/// ```text
+/// t=0 insert EU200A
+/// t=2s error flight deck open
+/// t=2.5s inhibit 3 on; t=2.5s master-inhibit off
+/// t=3s reset; t=3.5s reject coin
+/// ```
+///
+/// Times are seconds, or have an `s` or `ms` suffix, the `t=` is optional.
+/// Actions are:
+///
+/// * `insert <coin id | channel>` - channels are 1..=16, a coin id is
+///   looked up in the coin table when inserted and rejected if not found
+/// * `error <code | name>` - e.g. `error 254`, `error FlightDeckOpen` or
+///   `error flight deck open`, the `error` keyword can be left out when the
+///   name is given
+/// * `inhibit <channel> on|off`
+/// * `master-inhibit on|off`
+/// * `reset`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Scenario {
+    pub events: Vec<ScenarioEvent>,
+}
+
+impl Scenario {
+    pub fn parse(raw: &str) -> Result<Scenario, ScenarioError> {
+        let mut events = Vec::new();
+
+        for (i, line) in raw.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            for entry in line.split(';') {
+                let entry = entry.trim();
+                if entry.is_empty() {
+                    continue;
+                }
+                let event = parse_event(entry)
+                    .map_err(|reason| ScenarioError::ParseError(i + 1, reason))?;
+                events.push(event);
+            }
+        }
+
+        // Stable, so events at the same time keep their order
+        events.sort_by_key(|event| event.at);
+        Ok(Scenario { events })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Scenario, ScenarioError> {
+        let raw = fs::read_to_string(path)?;
+        Scenario::parse(&raw)
+    }
+}
+
+fn parse_event(entry: &str) -> Result<ScenarioEvent, String> {
+    let mut words = entry.split_whitespace();
+    let time = words.next().unwrap_or("");
+    let at = parse_time(time.trim_start_matches("t=")).ok_or(format!("invalid time {:?}", time))?;
+
+    let action = words.next().unwrap_or("");
+    let args: Vec<&str> = words.collect();
+    let action = match (action, &args[..]) {
+        ("insert", [coin]) => match parse_channel(coin) {
+            Some(channel) => Action::Insert(channel),
+            None => {
+                let coin_id = CoinId::new(coin)
+                    .ok()
+                    .filter(CoinId::is_valid)
+                    .ok_or(format!("invalid coin {:?}", coin))?;
+                Action::InsertCoin(coin_id)
+            }
+        },
+        ("error", [_, ..]) => {
+            Action::Error(parse_error(&args).ok_or(format!("invalid error {:?}", args.join(" ")))?)
+        }
+        ("inhibit", [channel, state]) => Action::Inhibit(
+            parse_channel(channel).ok_or(format!("invalid channel {:?}", channel))?,
+            parse_state(state)?,
+        ),
+        ("master-inhibit", [state]) => Action::MasterInhibit(parse_state(state)?),
+        ("reset", []) => Action::Reset,
+        _ => {
+            let words: Vec<&str> = entry.split_whitespace().skip(1).collect();
+            Action::Error(parse_error_name(&words).ok_or(format!("invalid action {:?}", entry))?)
+        }
+    };
+
+    Ok(ScenarioEvent { at, action })
+}
+
+fn parse_time(time: &str) -> Option<Duration> {
+    let (value, scale) = if let Some(ms) = time.strip_suffix("ms") {
+        (ms, 0.001)
+    } else {
+        (time.strip_suffix('s').unwrap_or(time), 1.0)
+    };
+    let seconds = value.parse::<f64>().ok()? * scale;
+    if seconds.is_finite() && seconds >= 0.0 {
+        Some(Duration::from_secs_f64(seconds))
+    } else {
+        None
+    }
+}
+
+/// Channel index from a coin position, 1..=16
+fn parse_channel(channel: &str) -> Option<u8> {
+    match channel.parse::<u8>() {
+        Ok(position @ 1..=16) => Some(position - 1),
+        _ => None,
+    }
+}
+
+fn parse_state(state: &str) -> Result<bool, String> {
+    match state {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(format!("expected on or off, got {:?}", state)),
+    }
+}
+
+/// Error code by number or by name
+fn parse_error(words: &[&str]) -> Option<u8> {
+    if let [code] = words {
+        if let Ok(code) = code.parse::<u8>() {
+            return Some(code);
+        }
+    }
+    parse_error_name(words)
+}
+
+/// Error code by name, given as the variant name or as its words, e.g.
+/// `flight deck open` or `flight-deck-open` for `FlightDeckOpen`
+fn parse_error_name(words: &[&str]) -> Option<u8> {
+    let name: String = words
+        .iter()
+        .flat_map(|word| word.split('-'))
+        .flat_map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_uppercase())
+                .into_iter()
+                .chain(chars)
+        })
+        .collect();
+    CoinAcceptorError::from_name(&name).map(|error| error.to_u8())
+}
+
+/// Replays a `Scenario` in real time
+pub struct ScenarioRunner {
+    scenario: Scenario,
+    next: usize,
+    started: Instant,
+}
+
+impl ScenarioRunner {
+    /// Runner with the clock starting now
+    pub fn new(scenario: Scenario) -> ScenarioRunner {
+        ScenarioRunner {
+            scenario,
+            next: 0,
+            started: Instant::now(),
+        }
+    }
+
+    /// Apply the events that are due, returns the number of applied events
+    pub fn poll(&mut self, acceptor: &mut CoinAcceptor) -> usize {
+        let elapsed = self.started.elapsed();
+        self.advance_to(acceptor, elapsed)
+    }
+
+    /// Apply the events up to `elapsed` since the start
+    pub fn advance_to(&mut self, acceptor: &mut CoinAcceptor, elapsed: Duration) -> usize {
+        let mut applied = 0;
+        while let Some(event) = self.scenario.events.get(self.next) {
+            if event.at > elapsed {
+                break;
+            }
+            log::info!("Scenario at {:?}: {:?}", event.at, event.action);
+            apply(acceptor, &event.action);
+            self.next += 1;
+            applied += 1;
+        }
+        applied
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.scenario.events.len()
+    }
+}
+
+fn apply(acceptor: &mut CoinAcceptor, action: &Action) {
+    match *action {
+        Action::Insert(channel) => insert(acceptor, channel),
+        // The coin table may have been reprogrammed since parsing
+        Action::InsertCoin(ref coin_id) => match acceptor.coin_table().find_coin(coin_id) {
+            Some(channel) => insert(acceptor, channel),
+            None => acceptor.add_error(CoinAcceptorError::RejectCoin),
+        },
+        Action::Error(code) => acceptor.add_error(CoinAcceptorError::from_u8(code)),
+        Action::Inhibit(channel, inhibit) => {
+            acceptor.coin_table_mut().set_inhibit(channel, inhibit)
+        }
+        Action::MasterInhibit(inhibit) => acceptor.set_master_inhibit(inhibit),
+        Action::Reset => acceptor.reset(),
+    }
+}
+
+fn insert(acceptor: &mut CoinAcceptor, channel: u8) {
+    if acceptor.get_master_inhibit() || acceptor.coin_table().get_inhibit(channel) {
+        acceptor.add_error(CoinAcceptorError::InhibitedCoin)
+    } else {
+        acceptor.add_credit(channel + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coinacceptor::{CoinInfo, CoinTable};
+    use crate::peripheral::{dispatch, test_core_info, Response};
+    use crate::protocol::{HeaderType, Payload};
+
+    fn acceptor() -> CoinAcceptor {
+        let mut coin_table = CoinTable::default();
+        coin_table.set_coininfo(0, CoinInfo::new(CoinId::new("EU200A").unwrap(), 1));
+        coin_table.set_coininfo(1, CoinInfo::new(CoinId::new("EU100A").unwrap(), 1));
+        CoinAcceptor::new(2, Box::new(coin_table), Box::new(test_core_info()))
+    }
+
+    fn credit(acceptor: &mut CoinAcceptor) -> Vec<u8> {
+        let request = Payload {
+            header: HeaderType::ReadBufferedCreditOrErrorCodes,
+            data: vec![],
+        };
+        match dispatch(acceptor, &request) {
+            Response::Reply(data) => data,
+            response => panic!("unexpected response {:?}", response),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let scenario = Scenario::parse(
+            "t=3s reset\n\
+             t=0 insert EU200A; 500ms insert 2 # comment\n\
+             \n\
+             t=2s flight deck open\n\
+             2 error 1; 2.5 insert GB100A\n\
+             t=2.5s inhibit 3 on; t=2.5s master-inhibit off",
+        )
+        .unwrap();
+
+        let actions: Vec<(u64, Action)> = scenario
+            .events
+            .into_iter()
+            .map(|event| (event.at.as_millis() as u64, event.action))
+            .collect();
+        assert_eq!(
+            actions,
+            vec![
+                (0, Action::InsertCoin(CoinId::new("EU200A").unwrap())),
+                (500, Action::Insert(1)),
+                (2000, Action::Error(254)),
+                (2000, Action::Error(1)),
+                (2500, Action::InsertCoin(CoinId::new("GB100A").unwrap())),
+                (2500, Action::Inhibit(2, true)),
+                (2500, Action::MasterInhibit(false)),
+                (3000, Action::Reset),
+            ]
+        );
+
+        for invalid in [
+            "insert EU200A",
+            "1s insert 17",
+            "1s error foo",
+            "1s inhibit 1",
+            "1s fly",
+        ] {
+            assert!(matches!(
+                Scenario::parse(invalid),
+                Err(ScenarioError::ParseError(1, _))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_runner() {
+        let mut acceptor = acceptor();
+        let scenario = Scenario::parse(
+            "0 insert EU200A; 0 master-inhibit off; 0 insert EU200A; \
+             1s error FlightDeckOpen; 1s inhibit 2 on; 1s insert EU100A; 2s reset",
+        )
+        .unwrap();
+        let mut runner = ScenarioRunner::new(scenario);
+
+        // Rejected while the master inhibit is active
+        assert_eq!(
+            runner.advance_to(&mut acceptor, Duration::from_millis(0)),
+            3
+        );
+        assert_eq!(credit(&mut acceptor)[..5], [2, 1, 1, 0, 2]);
+
+        // Rejected while the channel is inhibited
+        assert_eq!(
+            runner.advance_to(&mut acceptor, Duration::from_millis(1500)),
+            3
+        );
+        assert_eq!(credit(&mut acceptor)[..7], [4, 0, 2, 0, 254, 1, 1]);
+        assert!(!acceptor.get_master_inhibit());
+
+        assert!(!runner.is_finished());
+        assert_eq!(runner.advance_to(&mut acceptor, Duration::from_secs(2)), 1);
+        assert!(runner.is_finished());
+        assert!(acceptor.get_master_inhibit());
+        assert_eq!(credit(&mut acceptor)[0], 0);
+    }
+
+    #[test]
+    fn test_coin_looked_up_when_inserted() {
+        let mut acceptor = acceptor();
+        acceptor.set_master_inhibit(false);
+        let scenario = Scenario::parse("0 insert GB100A; 1s insert EU200A").unwrap();
+        let mut runner = ScenarioRunner::new(scenario);
+
+        // Programmed after parsing, EU200A moved out of channel 1
+        let coin_table = acceptor.coin_table_mut();
+        coin_table.set_coininfo(0, CoinInfo::new(CoinId::new("GB100A").unwrap(), 1));
+        coin_table.set_coininfo(4, CoinInfo::new(CoinId::new("EU200A").unwrap(), 1));
+
+        runner.advance_to(&mut acceptor, Duration::from_secs(1));
+        assert_eq!(credit(&mut acceptor)[..5], [2, 5, 1, 1, 1]);
+    }
+}
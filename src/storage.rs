@@ -0,0 +1,170 @@
+use std::convert;
+use std::io;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use std::fs;
+#[cfg(feature = "serde")]
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum StorageError {
+    IOError(io::Error),
+    FormatError(String),
+}
+
+impl convert::From<io::Error> for StorageError {
+    fn from(e: io::Error) -> StorageError {
+        StorageError::IOError(e)
+    }
+}
+
+/// Coin channel configuration as stored in EEPROM
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StoredCoin {
+    pub coin_id: String,
+    pub sort_paths: [u8; 4],
+    pub inhibit: bool,
+}
+
+/// Device configuration, written by `ConfigurationToEEPROM`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StoredConfiguration {
+    pub coins: Vec<StoredCoin>,
+    pub master_inhibit: bool,
+    pub sorter_override: u8,
+    pub default_sort_path: u8,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub inhibit_on_full_buffer: bool,
+}
+
+/// Coin counters, written by `CountersToEEPROM`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StoredCounters {
+    pub insertion: u32,
+    pub accept: u32,
+}
+
+/// Non-volatile state of an emulated device
+///
+/// Sections are `None` until they are written for the first time.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StoredState {
+    pub configuration: Option<StoredConfiguration>,
+    pub counters: Option<StoredCounters>,
+    /// Contents of permanent data storage blocks
+    pub data_blocks: Option<Vec<u8>>,
+}
+
+/// Storage backend for emulated device state
+pub trait StateStore {
+    fn load(&mut self) -> Result<StoredState, StorageError>;
+    fn save(&mut self, state: &StoredState) -> Result<(), StorageError>;
+}
+
+/// Volatile store, state survives device resets but not restarts
+#[derive(Default)]
+pub struct MemoryStore {
+    state: StoredState,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+}
+
+impl StateStore for MemoryStore {
+    fn load(&mut self) -> Result<StoredState, StorageError> {
+        Ok(self.state.clone())
+    }
+
+    fn save(&mut self, state: &StoredState) -> Result<(), StorageError> {
+        self.state = state.clone();
+        Ok(())
+    }
+}
+
+/// JSON file backed store
+///
+/// A missing file is treated as a blank EEPROM.
+#[cfg(feature = "serde")]
+pub struct FileStore {
+    path: PathBuf,
+}
+
+#[cfg(feature = "serde")]
+impl FileStore {
+    pub fn new<P: Into<PathBuf>>(path: P) -> FileStore {
+        FileStore { path: path.into() }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl StateStore for FileStore {
+    fn load(&mut self) -> Result<StoredState, StorageError> {
+        let raw = match fs::read_to_string(&self.path) {
+            Ok(raw) => raw,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok(StoredState::default());
+            }
+            Err(e) => return Err(StorageError::IOError(e)),
+        };
+        serde_json::from_str(&raw).map_err(|e| StorageError::FormatError(e.to_string()))
+    }
+
+    fn save(&mut self, state: &StoredState) -> Result<(), StorageError> {
+        let raw = serde_json::to_string_pretty(state)
+            .map_err(|e| StorageError::FormatError(e.to_string()))?;
+        // Write a temporary file first, so a crash never leaves a truncated state
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, raw)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_store_roundtrip() {
+        let path = std::env::temp_dir().join(format!("cctalk-store-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut store = FileStore::new(&path);
+        assert_eq!(store.load().unwrap(), StoredState::default());
+
+        let state = StoredState {
+            configuration: Some(StoredConfiguration {
+                coins: vec![StoredCoin {
+                    coin_id: "EU200A".to_string(),
+                    sort_paths: [1, 4, 4, 4],
+                    inhibit: false,
+                }],
+                master_inhibit: false,
+                sorter_override: 0xFF,
+                default_sort_path: 1,
+                inhibit_on_full_buffer: true,
+            }),
+            counters: Some(StoredCounters {
+                insertion: 12,
+                accept: 10,
+            }),
+            data_blocks: Some(vec![0, 1, 2, 3]),
+        };
+        store.save(&state).unwrap();
+
+        let mut store = FileStore::new(&path);
+        assert_eq!(store.load().unwrap(), state);
+
+        fs::remove_file(&path).unwrap();
+    }
+}